@@ -0,0 +1,46 @@
+// コマンド生成(`Generator`)の速度を測る.
+//
+// $ cargo bench --bench generation
+use cannyls_bencher::generator::Generator;
+use cannyls_bencher::parse::parse_workload_str;
+use std::time::Instant;
+
+const WORKLOADS: &[(&str, &str)] = &[
+    (
+        "put then delete",
+        "Ordered[1000000] { <100%> New(1K); }
+         Unordered[1000000] { <60%> Delete; <30%> Get; <10%> New(1K); }",
+    ),
+    (
+        "mixed with selectors",
+        "Ordered[1000000] { <100%> New(1K); }
+         Unordered[2000000] {
+           <30%> Get(zipf 0.99);
+           <20%> Delete(hotspot 10% 90%);
+           <20%> OverWrite(1K, 20, 80);
+           <30%> New(1K);
+         }",
+    ),
+    (
+        "delete range",
+        "Ordered[1000000] { <100%> New(1K); }
+         Command { 1000.times { DeleteRange(10, 11); New(1K); }; }",
+    ),
+];
+
+fn main() {
+    for (name, src) in WORKLOADS {
+        let workload = parse_workload_str(name, src).expect("bug");
+        let start = Instant::now();
+        let mut generator = Generator::new(&workload);
+        let commands = generator.by_ref().count();
+        let elapsed = start.elapsed();
+        println!(
+            "{:<24} {:>9} commands in {:>8.3?} ({:.0} commands/s)",
+            name,
+            commands,
+            elapsed,
+            commands as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+// 実際にリンクされるcannylsのバージョンをレポートに載せるため、
+// Cargo.lockから読み出して環境変数`CANNYLS_VERSION`に設定する.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let lockfile = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lockfile.display());
+
+    let version = fs::read_to_string(&lockfile)
+        .ok()
+        .and_then(|lock| cannyls_version(&lock))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=CANNYLS_VERSION={}", version);
+}
+
+fn cannyls_version(lock: &str) -> Option<String> {
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == "name = \"cannyls\"" {
+            let version = lines.next()?.trim();
+            let version = version.strip_prefix("version = \"")?.strip_suffix('"')?;
+            return Some(version.to_owned());
+        }
+    }
+    None
+}
@@ -0,0 +1,375 @@
+//! ストレージに必要な容量を見積もるモジュール.
+//!
+//! コマンド列を空回しして、生存しているlumpがディスク上で占めるバイト数の最大値を求め、
+//! そこからヘッダ・ジャーナル・データの各領域に必要な大きさを計算する.
+//!
+//! - データ領域: lumpのデータに2バイトのトレイラを付け、ブロックサイズに切り上げて置かれる.
+//!   `Embed`のデータはジャーナルのレコードに書かれるので、データ領域を使わない.
+//! - ジャーナル領域: 生存しているlumpごとにレコードが一つ残る.
+//!   削除や上書きで不要になったレコードもGCで回収されるまで残るので、生存レコードの2倍を確保する
+//!   (cannylsは使用量がリングバッファの半分を超えるまで、補助タスク以外ではGCを始めない).
+//!   先頭の1ブロックはジャーナルのヘッダに使う.
+//!
+//! レコードやトレイラの大きさはcannylsの公開されていないディスク上の形式なので、
+//! テストで実際のストレージの使用量と突き合わせる.
+//! - ヘッダ領域: ストレージのヘッダに1ブロック.
+//!
+//! ジャーナルとデータの割合は`journal_ratio`で決まるので、両方の領域が足りる最小の容量を求める.
+use crate::report::{CapacityReport, StorageSnapshot};
+use crate::{Bytes, RealCommand};
+use cannyls::lump::LumpId;
+use cannyls::storage::StorageHeader;
+use std::cmp::max;
+use std::collections::BTreeMap;
+
+// データ領域でlumpの末尾に付くトレイラ(パディングの長さ)
+const LUMP_DATA_TRAILER_SIZE: u64 = 2;
+
+// ジャーナルのレコードのチェックサムとタグ
+const RECORD_HEADER_SIZE: u64 = 4 + 1;
+
+// `Put`のレコード: lump id、データの長さ、データ領域での位置
+const PUT_RECORD_SIZE: u64 = RECORD_HEADER_SIZE + LumpId::SIZE as u64 + 2 + 5;
+
+// `Embed`のレコード: lump id、データの長さの後にデータそのものを置く
+const EMBED_RECORD_HEADER_SIZE: u64 = RECORD_HEADER_SIZE + LumpId::SIZE as u64 + 2;
+
+// 生存レコードに対するジャーナルのリングバッファの大きさの比.
+// 使用量が半分を超えると書き込みのたびにGCが進むので、生存レコードが半分に収まれば溢れない
+const JOURNAL_SLACK: u64 = 2;
+
+/// 生存しているlumpがディスク上で占めるバイト数.
+///
+/// `Estimator::peak`では、各項目はそれぞれが最大になった時点の値である.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    // Put・Embedで書き込んだデータそのもののバイト数
+    pub payload_bytes: u64,
+    // データ領域で占めるバイト数
+    pub data_bytes: u64,
+    // ジャーナル領域に残るレコードのバイト数
+    pub journal_bytes: u64,
+    pub lumps: usize,
+}
+
+impl Usage {
+    fn add(&mut self, lump: &Usage) {
+        self.payload_bytes += lump.payload_bytes;
+        self.data_bytes += lump.data_bytes;
+        self.journal_bytes += lump.journal_bytes;
+        self.lumps += lump.lumps;
+    }
+
+    fn sub(&mut self, lump: &Usage) {
+        self.payload_bytes -= lump.payload_bytes;
+        self.data_bytes -= lump.data_bytes;
+        self.journal_bytes -= lump.journal_bytes;
+        self.lumps -= lump.lumps;
+    }
+
+    fn max(&mut self, other: &Usage) {
+        self.payload_bytes = max(self.payload_bytes, other.payload_bytes);
+        self.data_bytes = max(self.data_bytes, other.data_bytes);
+        self.journal_bytes = max(self.journal_bytes, other.journal_bytes);
+        self.lumps = max(self.lumps, other.lumps);
+    }
+}
+
+/// コマンド列を順に受け取り、生存しているlumpの占めるバイト数の最大値を求める.
+pub struct Estimator {
+    block_size: u64,
+    live: BTreeMap<LumpId, Usage>,
+    current: Usage,
+    peak: Usage,
+}
+
+impl Estimator {
+    pub fn new(block_size: Bytes) -> Estimator {
+        Estimator {
+            block_size: block_size as u64,
+            live: BTreeMap::new(),
+            current: Usage::default(),
+            peak: Usage::default(),
+        }
+    }
+
+    /// `lumps`が既に存在するものとする. どれもデータ領域に置かれているものとみなす.
+    pub fn add_live_lumps(&mut self, lumps: &[(LumpId, Bytes)]) {
+        for (lumpid, bytes) in lumps {
+            let lump = self.put_usage(*bytes);
+            self.insert(*lumpid, lump);
+        }
+    }
+
+    pub fn observe(&mut self, command: &RealCommand) {
+        match command {
+            RealCommand::Put(lumpid, bytes) => {
+                let lump = self.put_usage(*bytes);
+                self.insert(*lumpid, lump);
+            }
+            RealCommand::Embed(lumpid, bytes) => {
+                let lump = Usage {
+                    payload_bytes: *bytes as u64,
+                    data_bytes: 0,
+                    journal_bytes: EMBED_RECORD_HEADER_SIZE + *bytes as u64,
+                    lumps: 1,
+                };
+                self.insert(*lumpid, lump);
+            }
+            RealCommand::Delete(lumpid, _) => self.remove(lumpid),
+            RealCommand::DeleteRange(start, end) => {
+                let removed: Vec<LumpId> = self.live.range(start..end).map(|(id, _)| *id).collect();
+                for lumpid in &removed {
+                    self.remove(lumpid);
+                }
+            }
+            RealCommand::Get(..)
+            | RealCommand::BeginSection(_)
+            | RealCommand::EndSection(_)
+            | RealCommand::Reopen
+            | RealCommand::Sync
+            | RealCommand::JournalGc
+            | RealCommand::SideJob(_) => {}
+        }
+    }
+
+    pub fn peak(&self) -> Usage {
+        self.peak
+    }
+
+    fn put_usage(&self, bytes: Bytes) -> Usage {
+        let stored = bytes as u64 + LUMP_DATA_TRAILER_SIZE;
+        Usage {
+            payload_bytes: bytes as u64,
+            data_bytes: stored.div_ceil(self.block_size) * self.block_size,
+            journal_bytes: PUT_RECORD_SIZE,
+            lumps: 1,
+        }
+    }
+
+    fn insert(&mut self, lumpid: LumpId, lump: Usage) {
+        if let Some(old) = self.live.insert(lumpid, lump) {
+            self.current.sub(&old);
+        }
+        self.current.add(&lump);
+        self.peak.max(&self.current);
+    }
+
+    fn remove(&mut self, lumpid: &LumpId) {
+        if let Some(old) = self.live.remove(lumpid) {
+            self.current.sub(&old);
+        }
+    }
+}
+
+/// 必要な容量の見積もり.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub peak: Usage,
+    // データ領域に必要な大きさ
+    pub data_region_size: u64,
+    // ジャーナル領域に必要な大きさ. ジャーナルのヘッダと不要なレコードの分を含む
+    pub journal_region_size: u64,
+    // `journal_ratio`で分けた時に、両方の領域が足りる最小の容量
+    pub capacity: u64,
+}
+
+impl Estimate {
+    /// ブロックサイズ`block_size`とジャーナル領域の割合`journal_ratio`で、`peak`を置くのに必要な容量を求める.
+    ///
+    /// `peak`は同じブロックサイズの`Estimator`で求めたものを使う.
+    pub fn new(peak: Usage, block_size: Bytes, journal_ratio: f64) -> Estimate {
+        let block_size = block_size as u64;
+        let align = |bytes: u64| bytes.div_ceil(block_size) * block_size;
+
+        let data_region_size = align(peak.data_bytes);
+        let journal_region_size = block_size + align(JOURNAL_SLACK * peak.journal_bytes);
+        // `StorageBuilder`はジャーナル領域を切り上げ、データ領域を切り捨てるので1ブロックずつ余分に見る
+        let journal_and_data = max(
+            ((journal_region_size + block_size) as f64 / journal_ratio).ceil() as u64,
+            ((data_region_size + block_size) as f64 / (1.0 - journal_ratio)).ceil() as u64,
+        );
+        Estimate {
+            peak,
+            data_region_size,
+            journal_region_size,
+            capacity: block_size + align(journal_and_data),
+        }
+    }
+
+    /// 容量の指定がない時に使う容量. 断片化に備え、見積もりをMiB単位に切り上げて1.5倍する.
+    pub fn default_capacity(&self) -> u64 {
+        let mbyte = 1024 * 1024;
+        let capacity = self.capacity.div_ceil(mbyte) * mbyte;
+        (1.5 * capacity as f64) as u64
+    }
+
+    /// `header`のストレージで足りない領域があれば、その警告を返す.
+    pub fn check(&self, header: &StorageHeader) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if header.data_region_size < self.data_region_size {
+            warnings.push(format!(
+                "the data region ({} bytes) is smaller than the estimated peak usage ({} bytes); \
+                 consider a larger --capacity (estimated {} bytes)",
+                header.data_region_size, self.data_region_size, self.capacity
+            ));
+        }
+        if header.journal_region_size < self.journal_region_size {
+            warnings.push(format!(
+                "the journal region ({} bytes) is smaller than the estimated requirement ({} bytes); \
+                 consider a larger --capacity or --journal-ratio",
+                header.journal_region_size, self.journal_region_size
+            ));
+        }
+        warnings
+    }
+
+    /// 見積もりと、実行中に`snapshots`で記録した使用量の最大値を並べる.
+    pub fn report(&self, header: &StorageHeader, snapshots: &[StorageSnapshot]) -> CapacityReport {
+        CapacityReport {
+            estimated_capacity: self.capacity,
+            estimated_data_usage_bytes: self.peak.data_bytes,
+            estimated_journal_usage_bytes: self.peak.journal_bytes,
+            estimated_lumps: self.peak.lumps,
+            data_region_size: header.data_region_size,
+            journal_region_size: header.journal_region_size,
+            data_usage_bytes: snapshots.iter().map(|s| s.data_usage_bytes).max(),
+            journal_usage_bytes: snapshots.iter().map(|s| s.journal_usage_bytes).max(),
+            lumps: snapshots.iter().map(|s| s.lumps).max(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{dry_run, Generator};
+    use crate::parse::parse_workload_str;
+    use crate::report::Metadata;
+    use crate::run_commands::{do_command, do_commands, make_storage, statistics, Summary};
+    use crate::StorageOptions;
+    use cannyls::nvm::MemoryNvm;
+    use cannyls::storage::Storage;
+
+    // ストレージのデータ領域とジャーナル領域の使用量
+    fn usage(storage: &Storage<MemoryNvm>) -> (u64, u64) {
+        let metrics = storage.metrics();
+        (
+            metrics.data_region().usage_bytes(),
+            metrics.journal_region().queue().usage_bytes(),
+        )
+    }
+
+    #[test]
+    fn layout_matches_cannyls() {
+        for &bs in &[512, 4096] {
+            let options = StorageOptions {
+                block_size: Some(bs),
+                ..Default::default()
+            };
+            let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+            let mut storage = make_storage(nvm, &options);
+            let mut estimator = Estimator::new(bs);
+            // トレイラが収まる最大の大きさと、1バイトはみ出す大きさ
+            for command in [
+                RealCommand::Put(LumpId::new(1), bs - 2),
+                RealCommand::Put(LumpId::new(2), bs - 1),
+                RealCommand::Embed(LumpId::new(3), 100),
+            ] {
+                estimator.observe(&command);
+                do_commands(&mut storage, Some(command));
+                let peak = estimator.peak();
+                assert_eq!(usage(&storage), (peak.data_bytes, peak.journal_bytes));
+            }
+            assert_eq!(estimator.peak().data_bytes, 3 * bs as u64);
+        }
+    }
+
+    #[test]
+    fn estimate_covers_observed_usage() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command { 300.times { New(uniform(1, 8K)); }; 100.times { Embed(1K); }; }
+               Unordered[3000] { <40%> OverWrite(uniform(1, 8K)); <20%> Delete; <20%> New(2K); <20%> Get; }"#,
+        )
+        .unwrap();
+        let estimate = Estimate::new(
+            dry_run(&w).peak,
+            StorageOptions::DEFAULT_BLOCK_SIZE,
+            StorageOptions::DEFAULT_JOURNAL_RATIO,
+        );
+        let nvm = MemoryNvm::new(vec![0; estimate.capacity as usize]);
+        let mut storage = make_storage(nvm, &w.storage);
+        assert_eq!(estimate.check(storage.header()), Vec::<String>::new());
+
+        // セクションの途中も含め、コマンドごとに使用量を見る.
+        // ジャーナルの余裕が足りなければ、cannylsが`StorageFull`を返して途中で失敗する
+        let ring_buffer_size =
+            estimate.journal_region_size - StorageOptions::DEFAULT_BLOCK_SIZE as u64;
+        let mut summary = Summary::default();
+        for command in Generator::new(&w) {
+            do_command(&mut storage, &command, &mut summary);
+            let (data, journal) = usage(&storage);
+            assert!(data <= estimate.peak.data_bytes, "{} {:?}", data, estimate);
+            assert!(journal <= ring_buffer_size, "{} {:?}", journal, estimate);
+        }
+    }
+
+    #[test]
+    fn lumps_are_rounded_up_to_blocks() {
+        let mut estimator = Estimator::new(4096);
+        estimator.observe(&RealCommand::Put(LumpId::new(1), 4095));
+        estimator.observe(&RealCommand::Put(LumpId::new(2), 4094));
+        estimator.observe(&RealCommand::Embed(LumpId::new(3), 100));
+        let peak = estimator.peak();
+        assert_eq!(peak.payload_bytes, 4095 + 4094 + 100);
+        assert_eq!(peak.data_bytes, 8192 + 4096);
+        assert_eq!(
+            peak.journal_bytes,
+            2 * PUT_RECORD_SIZE + EMBED_RECORD_HEADER_SIZE + 100
+        );
+
+        // 上書きと削除の後も最大値は変わらない
+        estimator.observe(&RealCommand::Put(LumpId::new(1), 10));
+        estimator.observe(&RealCommand::DeleteRange(LumpId::new(2), LumpId::new(4)));
+        assert_eq!(estimator.peak(), peak);
+    }
+
+    #[test]
+    fn estimated_capacity_holds_the_workload() {
+        let w = parse_workload_str(
+            "test",
+            r#"Storage { block_size: 4K; journal_ratio: 0.05; }
+               Command { 300.times { New(uniform(1, 8K)); }; 300.times { Embed(1K); }; }
+               Unordered[300] { <50%> OverWrite(8K); <50%> Get; }"#,
+        )
+        .unwrap();
+        let estimate = Estimate::new(dry_run(&w).peak, 4096, 0.05);
+        assert!(estimate.peak.data_bytes >= 300 * 4096, "{:?}", estimate);
+
+        let mut storage = make_storage(
+            MemoryNvm::new(vec![0; estimate.capacity as usize]),
+            &w.storage,
+        );
+        assert_eq!(estimate.check(storage.header()), Vec::<String>::new());
+        let summary = do_commands(&mut storage, Generator::new(&w));
+        let report = statistics(&summary, Metadata::default());
+
+        let capacity = estimate.report(storage.header(), &report.snapshots);
+        assert_eq!(capacity.lumps, Some(estimate.peak.lumps));
+        assert!(capacity.data_usage_bytes.unwrap() <= estimate.peak.data_bytes);
+    }
+
+    #[test]
+    fn small_regions_are_warned() {
+        let w = parse_workload_str("test", "Command { 1000.times { New(1K); }; }").unwrap();
+        let estimate = Estimate::new(
+            dry_run(&w).peak,
+            StorageOptions::DEFAULT_BLOCK_SIZE,
+            StorageOptions::DEFAULT_JOURNAL_RATIO,
+        );
+        let storage = make_storage(MemoryNvm::new(vec![0; 1024 * 1024]), &w.storage);
+        let warnings = estimate.check(storage.header());
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+    }
+}
@@ -0,0 +1,277 @@
+//! NVMへの読み書きを数えるための`NonVolatileMemory`のラッパー.
+//!
+//! ストレージはNVMをヘッダ・ジャーナル・データの各領域に`split`して使うので、
+//! `split`で作られた部分ごとに数え、集計する時に開始位置から領域を決める.
+//! `split`する前のNVM全体への読み書きは、先頭にあるヘッダ領域に数える.
+use crate::report::{IoReport, RegionIo};
+use cannyls::block::BlockSize;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::storage::StorageHeader;
+use cannyls::Result;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct Counters {
+    reads: AtomicU64,
+    read_bytes: AtomicU64,
+    writes: AtomicU64,
+    write_bytes: AtomicU64,
+    syncs: AtomicU64,
+    seeks: AtomicU64,
+    // シークで移動した距離の合計
+    seek_distance: AtomicU64,
+}
+
+impl Counters {
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for counter in &[
+            &self.reads,
+            &self.read_bytes,
+            &self.writes,
+            &self.write_bytes,
+            &self.syncs,
+            &self.seeks,
+            &self.seek_distance,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn add_to(&self, io: &mut RegionIo) {
+        io.reads += self.reads.load(Ordering::Relaxed);
+        io.read_bytes += self.read_bytes.load(Ordering::Relaxed);
+        io.writes += self.writes.load(Ordering::Relaxed);
+        io.write_bytes += self.write_bytes.load(Ordering::Relaxed);
+        io.syncs += self.syncs.load(Ordering::Relaxed);
+        io.seeks += self.seeks.load(Ordering::Relaxed);
+        io.seek_distance_bytes += self.seek_distance.load(Ordering::Relaxed);
+    }
+}
+
+// `split`で作られた部分の(開始位置, カウンタ)
+type Registry = Arc<Mutex<Vec<(u64, Arc<Counters>)>>>;
+
+/// `inner`への読み書きを数えるNVM.
+#[derive(Debug)]
+pub struct CountingNvm<N> {
+    inner: N,
+    start: u64,
+    counters: Arc<Counters>,
+    registry: Registry,
+    // 設定されている間は数えない
+    paused: Arc<AtomicBool>,
+}
+
+impl<N: NonVolatileMemory> CountingNvm<N> {
+    pub fn new(inner: N) -> CountingNvm<N> {
+        let counters = Arc::new(Counters::default());
+        CountingNvm {
+            inner,
+            start: 0,
+            registry: Arc::new(Mutex::new(vec![(0, counters.clone())])),
+            counters,
+            paused: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// このNVMへの読み書きの回数を読み出すためのハンドル.
+    pub fn counter(&self) -> IoCounter {
+        IoCounter {
+            registry: self.registry.clone(),
+            paused: self.paused.clone(),
+        }
+    }
+
+    fn part(registry: &Registry, paused: &Arc<AtomicBool>, inner: N, start: u64) -> CountingNvm<N> {
+        let counters = Arc::new(Counters::default());
+        registry.lock().unwrap().push((start, counters.clone()));
+        CountingNvm {
+            inner,
+            start,
+            counters,
+            registry: registry.clone(),
+            paused: paused.clone(),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+impl<N: NonVolatileMemory> NonVolatileMemory for CountingNvm<N> {
+    fn sync(&mut self) -> Result<()> {
+        if !self.is_paused() {
+            Counters::add(&self.counters.syncs, 1);
+        }
+        self.inner.sync()
+    }
+
+    fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.inner.capacity()
+    }
+
+    fn block_size(&self) -> BlockSize {
+        self.inner.block_size()
+    }
+
+    fn split(self, position: u64) -> Result<(Self, Self)> {
+        let (left, right) = self.inner.split(position)?;
+        Ok((
+            Self::part(&self.registry, &self.paused, left, self.start),
+            Self::part(&self.registry, &self.paused, right, self.start + position),
+        ))
+    }
+}
+
+impl<N: NonVolatileMemory> Read for CountingNvm<N> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if !self.is_paused() {
+            Counters::add(&self.counters.reads, 1);
+            Counters::add(&self.counters.read_bytes, read as u64);
+        }
+        Ok(read)
+    }
+}
+
+impl<N: NonVolatileMemory> Write for CountingNvm<N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if !self.is_paused() {
+            Counters::add(&self.counters.writes, 1);
+            Counters::add(&self.counters.write_bytes, written as u64);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<N: NonVolatileMemory> Seek for CountingNvm<N> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let from = self.inner.position();
+        let to = self.inner.seek(pos)?;
+        if !self.is_paused() {
+            Counters::add(&self.counters.seeks, 1);
+            Counters::add(&self.counters.seek_distance, from.max(to) - from.min(to));
+        }
+        Ok(to)
+    }
+}
+
+/// `CountingNvm`が数えた読み書きの回数を読み出すハンドル.
+#[derive(Debug, Clone)]
+pub struct IoCounter {
+    registry: Registry,
+    paused: Arc<AtomicBool>,
+}
+
+impl IoCounter {
+    /// 開き直したNVM全体`inner`への読み書きも、このハンドルで数えられるようにする.
+    pub fn wrap<N: NonVolatileMemory>(&self, inner: N) -> CountingNvm<N> {
+        CountingNvm::part(&self.registry, &self.paused, inner, 0)
+    }
+
+    /// `paused`の間は読み書きを数えない. 統計に含めたくない読み書きを挟むために使う.
+    pub fn pause(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// これまでの回数を捨てる. ストレージの作成にかかった読み書きを除くために使う.
+    pub fn reset(&self) {
+        for (_, counters) in self.registry.lock().unwrap().iter() {
+            counters.reset();
+        }
+    }
+
+    /// 領域ごとの回数を集計し、Put・Embedで書き込んだバイト数とGetで読み出したバイト数に対する比を求める.
+    pub fn report(
+        &self,
+        header: &StorageHeader,
+        logical_write_bytes: u64,
+        logical_read_bytes: u64,
+    ) -> IoReport {
+        let journal_start = header.region_size();
+        let data_start = journal_start + header.journal_region_size;
+        let mut regions: Vec<RegionIo> = ["header", "journal", "data"]
+            .iter()
+            .map(|name| RegionIo::new(name))
+            .collect();
+        for (start, counters) in self.registry.lock().unwrap().iter() {
+            let region = if *start < journal_start {
+                0
+            } else if *start < data_start {
+                1
+            } else {
+                2
+            };
+            counters.add_to(&mut regions[region]);
+        }
+
+        let write_bytes: u64 = regions.iter().map(|r| r.write_bytes).sum();
+        let read_bytes: u64 = regions.iter().map(|r| r.read_bytes).sum();
+        IoReport {
+            write_amplification: ratio(write_bytes, logical_write_bytes),
+            read_amplification: ratio(read_bytes, logical_read_bytes),
+            syncs: regions.iter().map(|r| r.syncs).sum(),
+            logical_write_bytes,
+            logical_read_bytes,
+            regions,
+        }
+    }
+}
+
+fn ratio(device: u64, logical: u64) -> f64 {
+    if logical == 0 {
+        0.0
+    } else {
+        device as f64 / logical as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use crate::parse::parse_workload_str;
+    use crate::run_commands::{do_commands, make_storage};
+    use crate::StorageOptions;
+    use cannyls::nvm::MemoryNvm;
+
+    #[test]
+    fn counts_are_split_by_region() {
+        let w = parse_workload_str("test", "Ordered[100] { <50%> New(4K); <50%> Get; }").unwrap();
+        let nvm = CountingNvm::new(MemoryNvm::new(vec![0; 4 * 1024 * 1024]));
+        let counter = nvm.counter();
+        let mut storage = make_storage(nvm, &StorageOptions::default());
+        counter.reset();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+
+        let (written, read) = summary.logical_bytes();
+        assert_eq!((written, read), (50 * 4096, 50 * 4096));
+        let io = counter.report(storage.header(), written, read);
+        let names: Vec<&str> = io.regions.iter().map(|r| r.region.as_str()).collect();
+        assert_eq!(names, vec!["header", "journal", "data"]);
+
+        let data = &io.regions[2];
+        assert_eq!(data.writes, 50);
+        assert_eq!(data.reads, 50);
+        // lumpごとに末尾のブロックにパディングが付く
+        assert!(data.write_bytes > written);
+        assert!(io.write_amplification > 1.0);
+        assert!(io.read_amplification >= 1.0);
+    }
+}
@@ -0,0 +1,474 @@
+//! クラッシュを模擬するための`NonVolatileMemory`のラッパー.
+//!
+//! 最後の`sync`以降の書き込みは、書き込む前の内容とともに記録しておく.
+//! クラッシュさせると、その記録を使って書き込みの全部または一部を取り消す.
+//! クラッシュは`crash_after`で予約して、コマンドの読み書きの途中で起こすこともできる.
+//! クラッシュした後の書き込みと`sync`は捨て、読み込みはエラーにする.
+//! ストレージは`split`した領域ごとにNVMを持つが、どの領域の`sync`もNVM全体を同期したものとみなす.
+use cannyls::block::BlockSize;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::{Error, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// クラッシュの仕方.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // 最後の`sync`以降の書き込みをすべて失う
+    DropUnsynced,
+
+    // 最後の`sync`以降の書き込みが、発行した順にある所まで残る.
+    // 境目の書き込みは先頭から何ブロックかだけ残る
+    TornWrites,
+
+    // 読み書きの途中でI/Oエラーを返し、その後に`DropUnsynced`と同じくクラッシュする
+    IoError,
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Fault, String> {
+        match s {
+            "drop" => Ok(Fault::DropUnsynced),
+            "torn" => Ok(Fault::TornWrites),
+            "error" => Ok(Fault::IoError),
+            _ => Err(format!("unknown fault: {} (drop, torn or error)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::DropUnsynced => write!(f, "drop"),
+            Fault::TornWrites => write!(f, "torn"),
+            Fault::IoError => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared<N> {
+    inner: N,
+    // 最後の`sync`以降の書き込み. (位置, 書き込む前の内容, 書き込んだ長さ)
+    unsynced: Vec<(u64, Vec<u8>, usize)>,
+    // これまでの読み書きと`sync`の回数
+    ops: u64,
+    // この回数目の操作でI/Oエラーを返す
+    fail_at: Option<u64>,
+    // この回数目の操作の直前にクラッシュする
+    crash_at: Option<(u64, Fault, StdRng)>,
+    syncs: u64,
+    // クラッシュしてから`restart`するまでの書き込みは捨てる
+    crashed: bool,
+}
+
+impl<N: NonVolatileMemory> Shared<N> {
+    // 操作を数え、予約したI/Oエラーを返すかクラッシュさせる
+    fn count_op(&mut self) -> io::Result<()> {
+        if self.crashed {
+            return Ok(());
+        }
+        self.ops += 1;
+        if self.fail_at == Some(self.ops) {
+            self.fail_at = None;
+            return Err(io::Error::other("injected I/O error"));
+        }
+        if self
+            .crash_at
+            .as_ref()
+            .is_some_and(|(at, ..)| *at == self.ops)
+        {
+            let (_, fault, mut rng) = self.crash_at.take().expect("bug");
+            self.crash(fault, &mut rng);
+        }
+        Ok(())
+    }
+
+    // 最後の`sync`以降の書き込みを`fault`に従って取り消す
+    fn crash<R: Rng>(&mut self, fault: Fault, rng: &mut R) {
+        let block_size = u64::from(self.inner.block_size().as_u16()) as usize;
+        let unsynced = std::mem::take(&mut self.unsynced);
+        // この番目より前の書き込みは残り、この番目の書き込みは途中まで残る
+        let cut = match fault {
+            Fault::DropUnsynced | Fault::IoError => 0,
+            Fault::TornWrites => rng.gen_range(0, unsynced.len() + 1),
+        };
+        for (i, (position, old, written)) in unsynced.into_iter().enumerate().rev() {
+            let keep = match i.cmp(&cut) {
+                Ordering::Less => written,
+                Ordering::Equal if fault == Fault::TornWrites => {
+                    rng.gen_range(0, written / block_size + 1) * block_size
+                }
+                _ => 0,
+            };
+            if keep < written {
+                self.undo(position, &old, keep)
+                    .unwrap_or_else(|e| panic!("Crash Error: {}", e));
+            }
+        }
+        self.inner
+            .sync()
+            .unwrap_or_else(|e| panic!("Crash Error: {}", e));
+        self.fail_at = None;
+        self.crash_at = None;
+        self.crashed = true;
+    }
+
+    fn read_at(&mut self, position: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        self.inner.read(buf)
+    }
+
+    fn write_at(&mut self, position: u64, buf: &[u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        let old = self
+            .inner
+            .aligned_read_bytes(buf.len())
+            .map_err(io::Error::other)?
+            .to_vec();
+
+        self.inner.seek(SeekFrom::Start(position))?;
+        let written = self.inner.write(buf)?;
+        self.unsynced.push((position, old, written));
+        Ok(written)
+    }
+
+    // 書き込みのうち、先頭の`keep`バイトより後ろを書き込む前の内容に戻す
+    fn undo(&mut self, position: u64, old: &[u8], keep: usize) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(position + keep as u64))?;
+        self.inner.aligned_write_all(|buf| {
+            buf.extend_from_slice(&old[keep..]);
+            Ok(())
+        })
+    }
+}
+
+/// `inner`への書き込みを記録し、クラッシュを模擬できるNVM.
+#[derive(Debug)]
+pub struct FaultyNvm<N> {
+    shared: Arc<Mutex<Shared<N>>>,
+    start: u64,
+    end: u64,
+    position: u64,
+}
+
+impl<N: NonVolatileMemory> FaultyNvm<N> {
+    pub fn new(inner: N) -> FaultyNvm<N> {
+        let end = inner.capacity();
+        FaultyNvm {
+            shared: Arc::new(Mutex::new(Shared {
+                inner,
+                unsynced: Vec::new(),
+                ops: 0,
+                fail_at: None,
+                crash_at: None,
+                syncs: 0,
+                crashed: false,
+            })),
+            start: 0,
+            end,
+            position: 0,
+        }
+    }
+
+    /// このNVMにクラッシュやI/Oエラーを起こすためのハンドル.
+    pub fn injector(&self) -> FaultInjector<N> {
+        FaultInjector {
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared<N>> {
+        self.shared.lock().unwrap()
+    }
+}
+
+impl<N: NonVolatileMemory> NonVolatileMemory for FaultyNvm<N> {
+    fn sync(&mut self) -> Result<()> {
+        let mut shared = self.lock();
+        shared.count_op().map_err(Error::from)?;
+        if shared.crashed {
+            return Ok(());
+        }
+        shared.inner.sync()?;
+        shared.unsynced.clear();
+        shared.syncs += 1;
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn capacity(&self) -> u64 {
+        self.end - self.start
+    }
+
+    fn block_size(&self) -> BlockSize {
+        self.lock().inner.block_size()
+    }
+
+    fn split(self, position: u64) -> Result<(Self, Self)> {
+        if position > self.capacity() || !self.block_size().is_aligned(position) {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "invalid split position");
+            return Err(Error::from(e));
+        }
+        let middle = self.start + position;
+        let left = FaultyNvm {
+            shared: self.shared.clone(),
+            start: self.start,
+            end: middle,
+            position: 0,
+        };
+        let right = FaultyNvm {
+            shared: self.shared,
+            start: middle,
+            end: self.end,
+            position: 0,
+        };
+        Ok((left, right))
+    }
+}
+
+impl<N: NonVolatileMemory> Read for FaultyNvm<N> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min((self.capacity() - self.position) as usize);
+        let position = self.start + self.position;
+        let read = {
+            let mut shared = self.lock();
+            shared.count_op()?;
+            if shared.crashed {
+                return Err(io::Error::other("the NVM has crashed"));
+            }
+            shared.read_at(position, &mut buf[..len])?
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<N: NonVolatileMemory> Write for FaultyNvm<N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min((self.capacity() - self.position) as usize);
+        let position = self.start + self.position;
+        let written = {
+            let mut shared = self.lock();
+            shared.count_op()?;
+            if shared.crashed {
+                len
+            } else {
+                shared.write_at(position, &buf[..len])?
+            }
+        };
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<N: NonVolatileMemory> Seek for FaultyNvm<N> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self
+            .convert_to_offset(pos)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if !self.block_size().is_aligned(position) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unaligned position",
+            ));
+        }
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// `FaultyNvm`にクラッシュやI/Oエラーを起こすためのハンドル.
+#[derive(Debug)]
+pub struct FaultInjector<N> {
+    shared: Arc<Mutex<Shared<N>>>,
+}
+
+impl<N: NonVolatileMemory> FaultInjector<N> {
+    /// これまでに`sync`した回数.
+    pub fn syncs(&self) -> u64 {
+        self.shared.lock().unwrap().syncs
+    }
+
+    /// 今から`n`回目の読み書きまたは`sync`でI/Oエラーを返す.
+    pub fn fail_after(&self, n: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.fail_at = Some(shared.ops + n);
+    }
+
+    /// 今から`n`回目の読み書きまたは`sync`の直前に、`fault`に従ってクラッシュさせる.
+    ///
+    /// 書き込みを途中まで残す位置は`seed`から決める.
+    pub fn crash_after(&self, n: u64, fault: Fault, seed: u64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.crash_at = Some((shared.ops + n, fault, StdRng::seed_from_u64(seed)));
+    }
+
+    /// まだ起きていないI/Oエラーとクラッシュの予約を取り消す.
+    pub fn cancel(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.fail_at = None;
+        shared.crash_at = None;
+    }
+
+    /// 予定したI/Oエラーがまだ起きていないかどうか.
+    pub fn is_failure_pending(&self) -> bool {
+        self.shared.lock().unwrap().fail_at.is_some()
+    }
+
+    /// クラッシュしてから、まだ`restart`していないかどうか.
+    pub fn is_crashed(&self) -> bool {
+        self.shared.lock().unwrap().crashed
+    }
+
+    /// クラッシュさせる.
+    ///
+    /// 最後の`sync`以降の書き込みを`fault`に従って取り消し、`restart`するまでの書き込みは捨てる.
+    pub fn crash<R: Rng>(&self, fault: Fault, rng: &mut R) {
+        self.shared.lock().unwrap().crash(fault, rng);
+    }
+
+    /// クラッシュから再起動し、NVM全体をもう一度開く.
+    pub fn restart(&self) -> FaultyNvm<N> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.crashed = false;
+        FaultyNvm {
+            shared: self.shared.clone(),
+            start: 0,
+            end: shared.inner.capacity(),
+            position: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cannyls::nvm::MemoryNvm;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn read_all<N: NonVolatileMemory>(nvm: &mut FaultyNvm<N>) -> Vec<u8> {
+        let mut buf = vec![0; nvm.capacity() as usize];
+        nvm.seek(SeekFrom::Start(0)).unwrap();
+        nvm.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn crash_drops_unsynced_writes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 4096]));
+        let injector = nvm.injector();
+        let (mut left, mut right) = nvm.split(1024).unwrap();
+
+        left.write_all(&[1; 512]).unwrap();
+        right.sync().unwrap();
+        right.write_all(&[2; 1024]).unwrap();
+        left.write_all(&[3; 512]).unwrap();
+        assert_eq!(injector.syncs(), 1);
+
+        injector.crash(Fault::DropUnsynced, &mut rng);
+        left.seek(SeekFrom::Start(0)).unwrap();
+        left.write_all(&[4; 512]).unwrap();
+
+        let bytes = read_all(&mut injector.restart());
+        assert_eq!(&bytes[..512], &[1; 512][..]);
+        assert!(bytes[512..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn crash_tears_writes_at_block_boundaries() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 64 * 512]));
+        let injector = nvm.injector();
+        nvm.write_all(&[1; 64 * 512]).unwrap();
+
+        injector.crash(Fault::TornWrites, &mut rng);
+        let bytes = read_all(&mut injector.restart());
+        let kept = bytes.iter().take_while(|&&b| b == 1).count();
+        assert_eq!(kept % 512, 0);
+        assert!(bytes[kept..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn torn_writes_are_kept_in_order() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 8 * 512]));
+            let injector = nvm.injector();
+            for i in 1..=8 {
+                nvm.write_all(&[i; 512]).unwrap();
+            }
+
+            injector.crash(Fault::TornWrites, &mut rng);
+            let bytes = read_all(&mut injector.restart());
+            let kept = bytes.chunks(512).take_while(|b| b[0] != 0).count();
+            for (i, block) in bytes.chunks(512).enumerate() {
+                let expected = if i < kept { i as u8 + 1 } else { 0 };
+                assert!(block.iter().all(|&b| b == expected), "{}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn io_error_is_returned_at_the_chosen_operation() {
+        let mut nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 4096]));
+        let injector = nvm.injector();
+        injector.fail_after(2);
+        assert!(nvm.write_all(&[1; 512]).is_ok());
+        assert!(nvm.write_all(&[1; 512]).is_err());
+        assert!(!injector.is_failure_pending());
+        assert!(nvm.write_all(&[1; 512]).is_ok());
+    }
+
+    #[test]
+    fn crash_happens_at_the_chosen_operation() {
+        let mut nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 4096]));
+        let injector = nvm.injector();
+        nvm.write_all(&[1; 512]).unwrap();
+        nvm.sync().unwrap();
+        injector.crash_after(2, Fault::DropUnsynced, 0);
+        nvm.write_all(&[2; 512]).unwrap();
+        assert!(!injector.is_crashed());
+
+        // クラッシュした後の書き込みは捨てられ、読み込みは失敗する
+        nvm.write_all(&[3; 512]).unwrap();
+        assert!(injector.is_crashed());
+        let mut buf = [0; 512];
+        nvm.seek(SeekFrom::Start(0)).unwrap();
+        assert!(nvm.read_exact(&mut buf).is_err());
+
+        let bytes = read_all(&mut injector.restart());
+        assert_eq!(&bytes[..512], &[1; 512][..]);
+        assert!(bytes[512..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn cancel_drops_pending_faults() {
+        let mut nvm = FaultyNvm::new(MemoryNvm::new(vec![0; 4096]));
+        let injector = nvm.injector();
+        injector.fail_after(1);
+        injector.crash_after(2, Fault::DropUnsynced, 0);
+        injector.cancel();
+        for _ in 0..4 {
+            nvm.write_all(&[1; 512]).unwrap();
+        }
+        assert!(!injector.is_failure_pending());
+        assert!(!injector.is_crashed());
+    }
+}
@@ -1,14 +1,19 @@
 use super::{Bytes, RealCommand, Workload};
+use crate::capacity::{self, Estimator};
+use crate::live_set::LiveSet;
 use crate::rand::SeedableRng;
-use crate::{Command, Section, Statement};
-use cannyls::lump::LumpId;
+use crate::{
+    Command, Freq, Repeat, Section, SectionBody, SectionInfo, Selector, SizeDist, Statement,
+};
+use cannyls::lump::{LumpData, LumpId};
 use std::cmp::max;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
 pub struct State {
     rng: rand::rngs::StdRng,
     next: LumpId,
-    live_ids: Vec<(LumpId, Bytes)>,
-    pub commands: Vec<RealCommand>,
+    live_ids: LiveSet,
     peek_bytes: usize,
     current_bytes: usize,
 }
@@ -18,154 +23,562 @@ impl State {
         State {
             rng: rand::rngs::StdRng::seed_from_u64(seed.unwrap_or(0)),
             next: LumpId::new(1),
-            live_ids: Vec::new(),
-            commands: Vec::new(),
+            live_ids: LiveSet::new(),
             peek_bytes: 0,
             current_bytes: 0,
         }
     }
+
+    // `lumps`が既に存在するものとする. 新しいlumpにはそれらより大きいidを使う
+    fn add_live_lumps(&mut self, lumps: &[(LumpId, Bytes)]) {
+        for (lumpid, bytes) in lumps {
+            self.live_ids.push(*lumpid, *bytes);
+            self.current_bytes += bytes;
+            if *lumpid >= self.next {
+                self.next = LumpId::new(lumpid.as_u128() + 1);
+            }
+        }
+        self.peek_bytes = max(self.peek_bytes, self.current_bytes);
+    }
 }
 
-pub fn workload_to_real_commands(workload: &Workload) -> (Vec<RealCommand>, usize) {
-    let mut state = State::new(workload.seed);
-    let commands = deal_workload(&mut state, workload);
-    commands_to_real_commands(&mut state, commands);
-    (state.commands, state.peek_bytes)
+// 各セクションから、次に展開するstatementを取り出すための状態
+enum Plan<'a> {
+    Commands(Option<&'a [Command]>),
+    Ordered {
+        statements: &'a [(Freq, Statement)],
+        iter: usize,
+        index: usize,
+        done: usize,
+    },
+    // 乱数列を従来の一括生成と一致させるため、並び替えだけは前もって行う。
+    // 保持するのはstatementの添字だけなので、コマンド列そのものよりずっと小さい。
+    Unordered {
+        statements: &'a [(Freq, Statement)],
+        order: std::vec::IntoIter<u32>,
+    },
+    // 時間で区切ったセクション.
+    // Orderedは`Ordered[100]`を、Unorderedは頻度に従った抽選を時間が尽きるまで繰り返す。
+    Timed {
+        statements: &'a [(Freq, Statement)],
+        ordered: bool,
+        budget: Duration,
+        // 最初のstatementを取り出した時に決まる
+        deadline: Option<Instant>,
+        index: usize,
+        done: usize,
+        iterations: usize,
+    },
+    // 目印を出し終えたセクション
+    Finished,
 }
 
-pub fn commands_to_real_commands(state: &mut State, commands: Vec<Command>) {
-    for command in commands {
-        match command {
-            Command::NewPut(bytes) => put(state, bytes),
-            Command::Overwrite(bytes) => overwrite(state, bytes),
-            Command::Embed(bytes) => embed(state, bytes),
-            Command::RandomGet => get(state, 0, 100),
-            Command::Get(left, right) => get(state, left, right),
-            Command::RandomDelete => delete(state, 0, 100),
-            Command::Delete(left, right) => delete(state, left, right),
-            Command::DeleteRange(left, right) => delete_range(state, left, right),
-            Command::Times(count, commands) => {
-                let commands = vec![commands; count]
-                    .into_iter()
-                    .flatten()
-                    .collect::<Vec<_>>();
-                commands_to_real_commands(state, commands)
+impl<'a> Plan<'a> {
+    fn new(state: &mut State, section: &'a Section) -> Plan<'a> {
+        use rand::seq::SliceRandom;
+
+        match &section.body {
+            SectionBody::Commands(commands) => Plan::Commands(Some(commands)),
+            SectionBody::Ordered(Repeat::For(budget), v)
+            | SectionBody::Unordered(Repeat::For(budget), v) => Plan::Timed {
+                statements: v,
+                ordered: matches!(section.body, SectionBody::Ordered(..)),
+                budget: *budget,
+                deadline: None,
+                index: 0,
+                done: 0,
+                iterations: 0,
+            },
+            SectionBody::Ordered(Repeat::Times(iter), v) => Plan::Ordered {
+                statements: v,
+                iter: *iter,
+                index: 0,
+                done: 0,
+            },
+            SectionBody::Unordered(Repeat::Times(iter), v) => {
+                let mut order: Vec<u32> = Vec::new();
+                for (i, (freq, _)) in v.iter().enumerate() {
+                    let y = (iter * *freq as usize) / 100;
+                    order.extend(std::iter::repeat_n(i as u32, y));
+                }
+                order.shuffle(&mut state.rng);
+                Plan::Unordered {
+                    statements: v,
+                    order: order.into_iter(),
+                }
             }
         }
     }
-}
 
-pub fn deal_workload(state: &mut State, workload: &Workload) -> Vec<Command> {
-    let mut commands: Vec<Command> = Vec::new();
+    fn next_statement(&mut self, state: &mut State) -> Option<&'a [Command]> {
+        match self {
+            Plan::Commands(commands) => commands.take(),
+            Plan::Ordered {
+                statements,
+                iter,
+                index,
+                done,
+            } => {
+                while let Some((freq, statement)) = statements.get(*index) {
+                    if *done < (*iter * *freq as usize) / 100 {
+                        *done += 1;
+                        return Some(&statement.0);
+                    }
+                    *index += 1;
+                    *done = 0;
+                }
+                None
+            }
+            Plan::Unordered { statements, order } => {
+                order.next().map(|i| &statements[i as usize].1 .0[..])
+            }
+            Plan::Timed {
+                statements,
+                ordered,
+                budget,
+                deadline,
+                index,
+                done,
+                iterations,
+            } => {
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + *budget);
+                let total: usize = statements.iter().map(|(freq, _)| *freq as usize).sum();
+                if total == 0 || Instant::now() >= deadline {
+                    return None;
+                }
+                *iterations += 1;
+                if !*ordered {
+                    let mut r = choose(&mut state.rng, 0, total - 1);
+                    for (freq, statement) in statements.iter() {
+                        if r < *freq as usize {
+                            return Some(&statement.0);
+                        }
+                        r -= *freq as usize;
+                    }
+                    unreachable!("bug");
+                }
+                loop {
+                    if *index == statements.len() {
+                        *index = 0;
+                    }
+                    let (freq, statement) = &statements[*index];
+                    if *done < *freq as usize {
+                        *done += 1;
+                        return Some(&statement.0);
+                    }
+                    *index += 1;
+                    *done = 0;
+                }
+            }
+            Plan::Finished => None,
+        }
+    }
 
-    for section in &workload.sections {
-        commands.append(&mut section_to_commands(state, section));
+    // 時間で区切ったセクションなら、展開したstatementの数
+    fn timed_iterations(&self) -> Option<usize> {
+        match self {
+            Plan::Timed { iterations, .. } => Some(*iterations),
+            _ => None,
+        }
     }
+}
 
-    commands
+// `Times`の展開途中の位置
+struct Frame<'a> {
+    commands: &'a [Command],
+    pos: usize,
+    remaining: usize,
 }
 
-fn section_to_commands(state: &mut State, section: &Section) -> Vec<Command> {
-    use rand::seq::SliceRandom;
+/// ワークロードから`RealCommand`を一つずつ生成するイテレータ.
+///
+/// 全コマンドをメモリ上に展開することはしない.
+/// 同じシードからは常に同じコマンド列が得られる.
+/// 各セクションのコマンドの前には`RealCommand::BeginSection`を挟み、
+/// 時間で区切ったセクションの後には`RealCommand::EndSection`を挟む.
+/// `SideJob/K`を指定したセクションでは、K操作ごとに`RealCommand::SideJob(1)`を挟む.
+pub struct Generator<'a> {
+    state: State,
+    plans: std::vec::IntoIter<(SectionInfo, Option<usize>, Plan<'a>)>,
+    current: Option<Plan<'a>>,
+    stack: Vec<Frame<'a>>,
+    // `Put!`のSyncや`SideJob/K`のように、直前のコマンドに続けて返すコマンド
+    pending: VecDeque<RealCommand>,
+    // 現在のセクションの`SideJob/K`と、そのセクションで生成した操作の数
+    side_job_every: Option<usize>,
+    ops: usize,
+}
 
-    if let Section::Commands(commands) = section {
-        return commands.clone();
+impl<'a> Generator<'a> {
+    pub fn new(workload: &'a Workload) -> Generator<'a> {
+        let mut state = State::new(workload.seed);
+        let plans: Vec<(SectionInfo, Option<usize>, Plan)> = workload
+            .sections
+            .iter()
+            .enumerate()
+            .map(|(index, section)| {
+                let info = SectionInfo {
+                    index,
+                    label: section.label.clone(),
+                    rate: section.rate,
+                };
+                let side_job_every = section.side_job_every;
+                (info, side_job_every, Plan::new(&mut state, section))
+            })
+            .collect();
+        Generator {
+            state,
+            plans: plans.into_iter(),
+            current: None,
+            stack: Vec::new(),
+            pending: VecDeque::new(),
+            side_job_every: None,
+            ops: 0,
+        }
     }
 
-    let (iter, v) = match &section {
-        Section::Ordered(iter, v) => (iter, v),
-        Section::Unordered(iter, v) => (iter, v),
-        _ => panic!("error"),
-    };
+    /// `lumps`が既に存在するストレージに対するコマンド列を生成する.
+    ///
+    /// `Get`や`Delete`は`lumps`も対象にし、新しいlumpには`lumps`のどれよりも大きいidを使う.
+    pub fn with_live_lumps(workload: &'a Workload, lumps: &[(LumpId, Bytes)]) -> Generator<'a> {
+        let mut generator = Generator::new(workload);
+        generator.state.add_live_lumps(lumps);
+        generator
+    }
 
-    let mut statements: Vec<Statement> = Vec::new();
-    let mut commands: Vec<Command> = Vec::new();
+    // 時間で区切ったセクションを空にする. 空回しで時間を使わないようにするため
+    fn skip_timed_sections(&mut self) {
+        let mut plans: Vec<_> = self.plans.by_ref().collect();
+        for (_, _, plan) in &mut plans {
+            if let Plan::Timed { budget, .. } = plan {
+                *budget = Duration::from_secs(0);
+            }
+        }
+        self.plans = plans.into_iter();
+    }
 
-    for (freq, statement) in v {
-        let y = (iter * *freq as usize) / 100;
-        statements.append(&mut vec![statement.clone(); y]);
+    /// ここまでに生成したコマンド列を実行した時の、生存lumpの合計サイズの最大値.
+    pub fn peek_bytes(&self) -> usize {
+        self.state.peek_bytes
     }
+}
+
+impl<'a> Iterator for Generator<'a> {
+    type Item = RealCommand;
 
-    if let Section::Unordered(_, _) = section {
-        statements.shuffle(&mut state.rng);
+    fn next(&mut self) -> Option<RealCommand> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+        let command = self.generate()?;
+        if let Some(every) = self.side_job_every {
+            if command.is_operation() {
+                self.ops += 1;
+                if self.ops.is_multiple_of(every) {
+                    self.pending.push_back(RealCommand::SideJob(1));
+                }
+            }
+        }
+        Some(command)
     }
+}
+
+impl<'a> Generator<'a> {
+    fn generate(&mut self) -> Option<RealCommand> {
+        loop {
+            let frame = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => {
+                    let statement = match &mut self.current {
+                        Some(plan) => plan.next_statement(&mut self.state),
+                        None => None,
+                    };
+                    match statement {
+                        Some(commands) => self.stack.push(Frame {
+                            commands,
+                            pos: 0,
+                            remaining: 1,
+                        }),
+                        None => {
+                            let finished = self.current.replace(Plan::Finished);
+                            if let Some(iterations) =
+                                finished.as_ref().and_then(Plan::timed_iterations)
+                            {
+                                return Some(RealCommand::EndSection(iterations));
+                            }
+                            let (info, side_job_every, plan) = self.plans.next()?;
+                            self.current = Some(plan);
+                            self.side_job_every = side_job_every;
+                            self.ops = 0;
+                            return Some(RealCommand::BeginSection(info));
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if frame.pos == frame.commands.len() {
+                frame.remaining -= 1;
+                frame.pos = 0;
+                if frame.remaining == 0 || frame.commands.is_empty() {
+                    self.stack.pop();
+                }
+                continue;
+            }
 
-    // ここまででstatementsの並び替えが終わっているので
-    // コマンド列として展開する。
-    for mut statement in statements {
-        commands.append(&mut statement.0)
+            let command = &frame.commands[frame.pos];
+            frame.pos += 1;
+            if let Command::Times(count, commands) = command {
+                if *count > 0 {
+                    self.stack.push(Frame {
+                        commands,
+                        pos: 0,
+                        remaining: *count,
+                    });
+                }
+                continue;
+            }
+            if let Command::NewPutSync(dist) = command {
+                self.pending.push_back(RealCommand::Sync);
+                return Some(put(&mut self.state, dist));
+            }
+            if let Some(command) = command_to_real_command(&mut self.state, command) {
+                return Some(command);
+            }
+        }
     }
+}
 
-    commands
+/// 実行せずにワークロードを最後まで生成した結果.
+pub struct DryRun {
+    // 目印と`Reopen`を除いたコマンド数
+    pub commands: usize,
+    pub peek_bytes: usize,
+    // `Reopen`の数
+    pub reopens: usize,
+    // `Sync`の数. `commands`にも含まれる
+    pub syncs: usize,
+    // `JournalGc`と`SideJob`の数. `commands`にも含まれる
+    pub maintenance: usize,
+    // 生存しているlumpがディスク上で占めるバイト数の最大値
+    pub peak: capacity::Usage,
 }
 
-fn put(state: &mut State, bytes: Bytes) {
+/// 時間で区切ったセクションは何も生成しなかったものとして扱う.
+///
+/// ディスク上の大きさはワークロードの`Storage { .. }`のブロックサイズで求める.
+pub fn dry_run(workload: &Workload) -> DryRun {
+    dry_run_with_live_lumps(workload, &[], workload.storage.block_size())
+}
+
+/// `Generator::with_live_lumps`で生成するコマンド列を空回しする.
+///
+/// ディスク上の大きさはブロックサイズ`block_size`で求める.
+pub fn dry_run_with_live_lumps(
+    workload: &Workload,
+    lumps: &[(LumpId, Bytes)],
+    block_size: Bytes,
+) -> DryRun {
+    let mut generator = Generator::with_live_lumps(workload, lumps);
+    generator.skip_timed_sections();
+    let mut estimator = Estimator::new(block_size);
+    estimator.add_live_lumps(lumps);
+    let mut commands = 0;
+    let mut reopens = 0;
+    let mut syncs = 0;
+    let mut maintenance = 0;
+    for command in generator.by_ref() {
+        estimator.observe(&command);
+        match command {
+            RealCommand::Reopen => reopens += 1,
+            RealCommand::Sync => {
+                commands += 1;
+                syncs += 1;
+            }
+            RealCommand::JournalGc | RealCommand::SideJob(_) => {
+                commands += 1;
+                maintenance += 1;
+            }
+            command if !command.is_marker() => commands += 1,
+            _ => {}
+        }
+    }
+    DryRun {
+        commands,
+        peek_bytes: generator.peek_bytes(),
+        reopens,
+        syncs,
+        maintenance,
+        peak: estimator.peak(),
+    }
+}
+
+pub fn workload_to_real_commands(workload: &Workload) -> (Vec<RealCommand>, usize) {
+    let mut generator = Generator::new(workload);
+    let commands = generator.by_ref().collect();
+    (commands, generator.peek_bytes())
+}
+
+fn command_to_real_command(state: &mut State, command: &Command) -> Option<RealCommand> {
+    match command {
+        Command::NewPut(dist) => Some(put(state, dist)),
+        Command::Overwrite(dist, selector) => overwrite(state, dist, selector.as_ref()),
+        Command::Embed(dist) => Some(embed(state, dist)),
+        Command::RandomGet => get(state, &Selector::Range(0, 100)),
+        Command::Get(selector) => get(state, selector),
+        Command::RandomDelete => delete(state, &Selector::Range(0, 100)),
+        Command::Delete(selector) => delete(state, selector),
+        Command::DeleteRange(left, right) => delete_range(state, *left, *right),
+        Command::Reopen => Some(RealCommand::Reopen),
+        Command::Sync => Some(RealCommand::Sync),
+        Command::JournalGc => Some(RealCommand::JournalGc),
+        Command::SideJob(n) => Some(RealCommand::SideJob(*n)),
+        Command::Times(_, _) | Command::NewPutSync(_) => unreachable!("bug"),
+    }
+}
+
+fn put(state: &mut State, dist: &SizeDist) -> RealCommand {
+    let bytes = sample_size(&mut state.rng, dist).min(LumpData::MAX_SIZE);
     let lumpid = state.next.as_u128();
     state.next = LumpId::new(lumpid + 1);
     let lumpid: LumpId = LumpId::new(lumpid);
-    state.live_ids.push((lumpid, bytes));
-    state.commands.push(RealCommand::Put(lumpid, bytes));
+    state.live_ids.push(lumpid, bytes);
     state.current_bytes += bytes;
     state.peek_bytes = max(state.peek_bytes, state.current_bytes);
+    RealCommand::Put(lumpid, bytes)
 }
 
-fn embed(state: &mut State, bytes: Bytes) {
+fn embed(state: &mut State, dist: &SizeDist) -> RealCommand {
+    let bytes = sample_size(&mut state.rng, dist).min(LumpData::MAX_EMBEDDED_SIZE);
     let lumpid = state.next.as_u128();
     state.next = LumpId::new(lumpid + 1);
     let lumpid: LumpId = LumpId::new(lumpid);
-    state.live_ids.push((lumpid, bytes));
-    state.commands.push(RealCommand::Embed(lumpid, bytes));
+    state.live_ids.push(lumpid, bytes);
     state.current_bytes += bytes;
     state.peek_bytes = max(state.peek_bytes, state.current_bytes);
+    RealCommand::Embed(lumpid, bytes)
 }
 
-fn overwrite(state: &mut State, bytes: Bytes) {
+fn overwrite(
+    state: &mut State,
+    dist: &SizeDist,
+    selector: Option<&Selector>,
+) -> Option<RealCommand> {
     if state.live_ids.is_empty() {
-        return;
+        return None;
     }
-    let z = choose(&mut state.rng, 0, state.live_ids.len() - 1);
-    let lumpid = state.live_ids[z].0;
-    state.live_ids[z].1 = bytes;
-    state.commands.push(RealCommand::Put(lumpid, bytes));
-    state.current_bytes -= state.live_ids[z].1;
+    let bytes = sample_size(&mut state.rng, dist).min(LumpData::MAX_SIZE);
+    let z = match selector {
+        Some(selector) => select_index(&mut state.rng, state.live_ids.len(), selector),
+        None => choose(&mut state.rng, 0, state.live_ids.len() - 1),
+    };
+    let (lumpid, old_bytes) = state.live_ids.get(z);
+    state.live_ids.set_bytes(z, bytes);
+    state.current_bytes -= old_bytes;
     state.current_bytes += bytes;
     state.peek_bytes = max(state.peek_bytes, state.current_bytes);
+    Some(RealCommand::Put(lumpid, bytes))
 }
 
-fn get(state: &mut State, left: u8, right: u8) {
+fn get(state: &mut State, selector: &Selector) -> Option<RealCommand> {
     if state.live_ids.is_empty() {
-        return;
+        return None;
     }
-    let z = calc_index(&mut state.rng, state.live_ids.len(), left, right);
-    let (lumpid, bytes) = state.live_ids[z];
-    state.commands.push(RealCommand::Get(lumpid, bytes));
+    let z = select_index(&mut state.rng, state.live_ids.len(), selector);
+    let (lumpid, bytes) = state.live_ids.get(z);
+    Some(RealCommand::Get(lumpid, bytes))
 }
 
-fn delete(state: &mut State, left: u8, right: u8) {
+fn delete(state: &mut State, selector: &Selector) -> Option<RealCommand> {
     if state.live_ids.is_empty() {
-        return;
+        return None;
     }
-    let z = calc_index(&mut state.rng, state.live_ids.len(), left, right);
-    let (lumpid, bytes) = state.live_ids[z];
+    let z = select_index(&mut state.rng, state.live_ids.len(), selector);
+    let (lumpid, bytes) = state.live_ids.remove(z);
     state.current_bytes -= bytes;
-    state.commands.push(RealCommand::Delete(lumpid, bytes));
-    state.live_ids.remove(z);
+    Some(RealCommand::Delete(lumpid, bytes))
 }
 
-fn delete_range(state: &mut State, left: u8, right: u8) {
+fn delete_range(state: &mut State, left: u8, right: u8) -> Option<RealCommand> {
     if state.live_ids.is_empty() {
-        return;
+        return None;
     }
     let l = state.live_ids.len().saturating_sub(1);
     let x = (l * left as usize) / 100;
     let y = (l * right as usize) / 100;
-    let lumpid1 = state.live_ids[x].0;
-    let lumpid2 = state.live_ids[y].0;
-    state
-        .commands
-        .push(RealCommand::DeleteRange(lumpid1, lumpid2));
-    for (_id, bytes) in state.live_ids.drain(x..y) {
-        state.current_bytes -= bytes;
+    let lumpid1 = state.live_ids.get(x).0;
+    let lumpid2 = state.live_ids.get(y).0;
+    state.current_bytes -= state.live_ids.remove_range(x..y);
+    Some(RealCommand::DeleteRange(lumpid1, lumpid2))
+}
+
+// 長さ`len`(> 0)の生存lump列から`selector`に従って添字を一つ選ぶ。
+// 添字が大きいほど新しいlumpである。
+fn select_index<R>(rng: &mut R, len: usize, selector: &Selector) -> usize
+where
+    R: rand::Rng,
+{
+    match selector {
+        Selector::Range(left, right) => calc_index(rng, len, *left, *right),
+        Selector::Zipf(exponent) => len - zipf_rank(rng, len, *exponent),
+        Selector::Hotspot(hot, access) => {
+            let hot_len = max(1, (len * *hot as usize) / 100);
+            let cold_len = len - hot_len;
+            if cold_len == 0 || rng.gen_range(0, 100) < *access as usize {
+                choose(rng, cold_len, len - 1)
+            } else {
+                choose(rng, 0, cold_len - 1)
+            }
+        }
+        Selector::Latest => len - 1,
+    }
+}
+
+// [1; n] の中から、順位kが 1/k^exponent に比例する確率で選ばれるように一つ選ぶ。
+// nが毎回変わるので、前計算の要らない rejection-inversion 法を使う。
+//
+// W. Hörmann and G. Derflinger,
+// "Rejection-inversion to generate variates from monotone discrete distributions", 1996.
+fn zipf_rank<R>(rng: &mut R, n: usize, exponent: f64) -> usize
+where
+    R: rand::Rng,
+{
+    // (exp(x) - 1) / x と log(1 + x) / x を x ~ 0 でも精度良く計算する
+    let helper1 = |x: f64| {
+        if x.abs() > 1e-8 {
+            x.ln_1p() / x
+        } else {
+            1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+        }
+    };
+    let helper2 = |x: f64| {
+        if x.abs() > 1e-8 {
+            x.exp_m1() / x
+        } else {
+            1.0 + x * 0.5 * (1.0 + x / 3.0 * (1.0 + 0.25 * x))
+        }
+    };
+    let h = |x: f64| (-exponent * x.ln()).exp();
+    let h_integral = |x: f64| {
+        let log_x = x.ln();
+        helper2((1.0 - exponent) * log_x) * log_x
+    };
+    let h_integral_inverse = |x: f64| {
+        let t = (x * (1.0 - exponent)).max(-1.0);
+        (helper1(t) * x).exp()
+    };
+
+    let h_integral_x1 = h_integral(1.5) - 1.0;
+    let h_integral_n = h_integral(n as f64 + 0.5);
+    let s = 2.0 - h_integral_inverse(h_integral(2.5) - h(2.0));
+
+    loop {
+        let u = h_integral_n + rng.gen::<f64>() * (h_integral_x1 - h_integral_n);
+        let x = h_integral_inverse(u);
+        let k = (x + 0.5).floor().clamp(1.0, n as f64);
+        if k - x <= s || u >= h_integral(k + 0.5) - h(k) {
+            return k as usize;
+        }
     }
 }
 
@@ -195,3 +608,426 @@ where
 {
     rng.gen_range(start, end + 1)
 }
+
+// `dist`に従ってサイズを一つ選ぶ。
+// `SizeDist::Fixed`の場合は乱数を消費しないので、従来のワークロードの生成結果は変わらない。
+// 正規分布・対数正規分布は負の値を0に切り上げる。
+pub fn sample_size<R>(rng: &mut R, dist: &SizeDist) -> Bytes
+where
+    R: rand::Rng,
+{
+    match dist {
+        SizeDist::Fixed(bytes) => *bytes,
+        SizeDist::Uniform(min, max) => choose(rng, *min, *max),
+        SizeDist::Normal(mean, stddev) => {
+            let x = *mean as f64 + *stddev as f64 * standard_normal(rng);
+            x.round().max(0.0) as Bytes
+        }
+        SizeDist::LogNormal(mean, stddev) => {
+            let (mean, stddev) = (*mean as f64, *stddev as f64);
+            let sigma2 = (1.0 + (stddev * stddev) / (mean * mean)).ln();
+            let mu = mean.ln() - sigma2 / 2.0;
+            let x = (mu + sigma2.sqrt() * standard_normal(rng)).exp();
+            x.round().max(0.0) as Bytes
+        }
+        SizeDist::Choice(choices) => {
+            let total: u64 = choices.iter().map(|(_, weight)| u64::from(*weight)).sum();
+            let mut x = rng.gen_range(0, total);
+            for (bytes, weight) in choices {
+                if x < u64::from(*weight) {
+                    return *bytes;
+                }
+                x -= u64::from(*weight);
+            }
+            unreachable!("bug")
+        }
+    }
+}
+
+// Box-Muller法
+fn standard_normal<R>(rng: &mut R) -> f64
+where
+    R: rand::Rng,
+{
+    let u1: f64 = 1.0 - rng.gen::<f64>(); // (0; 1]
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StorageOptions;
+
+    fn rng() -> rand::rngs::StdRng {
+        rand::rngs::StdRng::seed_from_u64(42)
+    }
+
+    // 前もってすべてのコマンドを生成していた頃の生成器の出力と一致することを確かめる.
+    // 期待値はその生成器で同じワークロードから作ったもの
+    #[test]
+    fn generator_matches_the_materialized_generator() {
+        let w = crate::parse::parse_workload_str(
+            "test",
+            "Seed: 7;
+             Command { 5.times { New(1K); }; }
+             Unordered[20] {
+               <30%> New(2K); <20%> OverWrite(3K); <20%> Get;
+               <10%> Embed(100); <10%> Delete; <10%> DeleteRange(10, 30);
+             }
+             Ordered[6] { <50%> Get(50, 100); <50%> Delete(0, 50); }",
+        )
+        .unwrap();
+        let id = LumpId::new;
+        let commands: Vec<_> = Generator::new(&w).filter(|c| !c.is_marker()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                RealCommand::Put(id(1), 1024),
+                RealCommand::Put(id(2), 1024),
+                RealCommand::Put(id(3), 1024),
+                RealCommand::Put(id(4), 1024),
+                RealCommand::Put(id(5), 1024),
+                RealCommand::Get(id(1), 1024),
+                RealCommand::DeleteRange(id(1), id(2)),
+                RealCommand::Put(id(3), 3072),
+                RealCommand::Get(id(2), 1024),
+                RealCommand::Get(id(2), 1024),
+                RealCommand::Delete(id(2), 1024),
+                RealCommand::Put(id(3), 3072),
+                RealCommand::Put(id(6), 2048),
+                RealCommand::Delete(id(5), 1024),
+                RealCommand::Put(id(7), 2048),
+                RealCommand::Put(id(8), 2048),
+                RealCommand::Put(id(4), 3072),
+                RealCommand::DeleteRange(id(3), id(4)),
+                RealCommand::Put(id(9), 2048),
+                RealCommand::Embed(id(10), 100),
+                RealCommand::Get(id(8), 2048),
+                RealCommand::Put(id(7), 3072),
+                RealCommand::Embed(id(11), 100),
+                RealCommand::Put(id(12), 2048),
+                RealCommand::Put(id(13), 2048),
+                RealCommand::Get(id(12), 2048),
+                RealCommand::Get(id(9), 2048),
+                RealCommand::Get(id(10), 100),
+                RealCommand::Delete(id(4), 3072),
+                RealCommand::Delete(id(8), 2048),
+                RealCommand::Delete(id(9), 2048),
+            ]
+        );
+    }
+
+    #[test]
+    fn generator_expands_sections_lazily_in_order() {
+        let w = Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: vec![
+                SectionBody::Commands(vec![
+                    Command::NewPut(SizeDist::Fixed(1)),
+                    Command::Times(
+                        2,
+                        vec![
+                            Command::NewPut(SizeDist::Fixed(2)),
+                            Command::Get(Selector::Latest),
+                        ],
+                    ),
+                    Command::Times(0, vec![Command::RandomDelete]),
+                ])
+                .into(),
+                Section {
+                    label: Some("mixed".to_owned()),
+                    rate: Some(2000),
+                    side_job_every: None,
+                    body: SectionBody::Ordered(
+                        Repeat::Times(4),
+                        vec![
+                            (50, Statement(vec![Command::Delete(Selector::Latest)])),
+                            (25, Statement(vec![])),
+                            (25, Statement(vec![Command::Embed(SizeDist::Fixed(3))])),
+                        ],
+                    ),
+                },
+            ],
+        };
+        let section = |index, label: Option<&str>, rate| {
+            RealCommand::BeginSection(SectionInfo {
+                index,
+                label: label.map(str::to_owned),
+                rate,
+            })
+        };
+        let id = LumpId::new;
+        let commands: Vec<_> = Generator::new(&w).collect();
+        assert_eq!(
+            commands,
+            vec![
+                section(0, None, None),
+                RealCommand::Put(id(1), 1),
+                RealCommand::Put(id(2), 2),
+                RealCommand::Get(id(2), 2),
+                RealCommand::Put(id(3), 2),
+                RealCommand::Get(id(3), 2),
+                section(1, Some("mixed"), Some(2000)),
+                RealCommand::Delete(id(3), 2),
+                RealCommand::Delete(id(2), 2),
+                RealCommand::Embed(id(4), 3),
+            ]
+        );
+
+        let dry_run = dry_run(&w);
+        assert_eq!(dry_run.commands, commands.len() - 2);
+        assert_eq!(dry_run.peek_bytes, 5);
+    }
+
+    #[test]
+    fn put_sync_is_followed_by_sync() {
+        let w = Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: vec![SectionBody::Commands(vec![
+                Command::Times(2, vec![Command::NewPutSync(SizeDist::Fixed(1))]),
+                Command::Sync,
+                Command::RandomGet,
+            ])
+            .into()],
+        };
+        let commands: Vec<_> = Generator::new(&w).skip(1).collect();
+        let id = LumpId::new;
+        assert_eq!(
+            commands,
+            vec![
+                RealCommand::Put(id(1), 1),
+                RealCommand::Sync,
+                RealCommand::Put(id(2), 1),
+                RealCommand::Sync,
+                RealCommand::Sync,
+                RealCommand::Get(id(1), 1),
+            ]
+        );
+
+        let dry_run = dry_run(&w);
+        assert_eq!(dry_run.commands, 6);
+        assert_eq!(dry_run.syncs, 3);
+    }
+
+    #[test]
+    fn side_jobs_are_interleaved() {
+        let w = crate::parse::parse_workload_str(
+            "test",
+            "Command SideJob/2 { Put!(1); New(1); JournalGc; Get; Reopen; Get; }
+             Command { Get; }",
+        )
+        .unwrap();
+        let commands: Vec<_> = Generator::new(&w)
+            .filter(|c| !matches!(c, RealCommand::Put(..) | RealCommand::Get(..)))
+            .collect();
+        let section = |index| {
+            RealCommand::BeginSection(SectionInfo {
+                index,
+                label: None,
+                rate: None,
+            })
+        };
+        // `Sync`・`JournalGc`・`Reopen`は操作に数えない
+        assert_eq!(
+            commands,
+            vec![
+                section(0),
+                RealCommand::Sync,
+                RealCommand::SideJob(1),
+                RealCommand::JournalGc,
+                RealCommand::Reopen,
+                RealCommand::SideJob(1),
+                section(1),
+            ]
+        );
+        assert_eq!(dry_run(&w).maintenance, 3);
+    }
+
+    #[test]
+    fn live_lumps_are_targeted_and_skipped_for_new_ids() {
+        let w = Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: vec![SectionBody::Commands(vec![
+                Command::Get(Selector::Latest),
+                Command::Delete(Selector::Latest),
+                Command::NewPut(SizeDist::Fixed(1)),
+            ])
+            .into()],
+        };
+        let id = LumpId::new;
+        let lumps = [(id(3), 10), (id(7), 20)];
+        let commands: Vec<_> = Generator::with_live_lumps(&w, &lumps)
+            .filter(|c| !c.is_marker())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                RealCommand::Get(id(7), 20),
+                RealCommand::Delete(id(7), 20),
+                RealCommand::Put(id(8), 1),
+            ]
+        );
+        let dry_run = dry_run_with_live_lumps(&w, &lumps, 512);
+        assert_eq!(dry_run.peek_bytes, 30);
+        assert_eq!(dry_run.peak.data_bytes, 2 * 512);
+    }
+
+    #[test]
+    fn timed_sections_run_until_the_deadline() {
+        let load = Section::from(SectionBody::Commands(vec![Command::Times(
+            10,
+            vec![Command::NewPut(SizeDist::Fixed(1))],
+        )]));
+        let timed = Section::from(SectionBody::Ordered(
+            Repeat::For(Duration::from_millis(20)),
+            vec![
+                (50, Statement(vec![Command::RandomGet])),
+                (50, Statement(vec![Command::NewPut(SizeDist::Fixed(2))])),
+            ],
+        ));
+        let w = Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: vec![load, timed],
+        };
+
+        let start = Instant::now();
+        let commands: Vec<_> = Generator::new(&w).collect();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let iterations = match commands.last() {
+            Some(RealCommand::EndSection(iterations)) => *iterations,
+            c => panic!("{:?}", c),
+        };
+        let section: Vec<_> = commands[12..commands.len() - 1].iter().collect();
+        assert!(iterations > 0);
+        assert_eq!(section.len(), iterations);
+        for (i, command) in section.iter().enumerate() {
+            match (i % 100 < 50, command) {
+                (true, RealCommand::Get(..)) | (false, RealCommand::Put(..)) => {}
+                _ => panic!("{}: {:?}", i, command),
+            }
+        }
+
+        assert_eq!(dry_run(&w).commands, 10);
+    }
+
+    #[test]
+    fn commands_in_timed_sections_are_found() {
+        let timed = Section::from(SectionBody::Unordered(
+            Repeat::For(Duration::from_secs(1)),
+            vec![
+                (50, Statement(vec![Command::Reopen])),
+                (
+                    50,
+                    Statement(vec![Command::Times(2, vec![Command::SideJob(1)])]),
+                ),
+            ],
+        ));
+        let w = Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: vec![timed],
+        };
+
+        // 空回しでは数えられない
+        let dry_run = dry_run(&w);
+        assert_eq!((dry_run.reopens, dry_run.maintenance), (0, 0));
+
+        let section = &w.sections[0];
+        assert!(section.any_command(&|c| *c == Command::Reopen));
+        assert!(section.any_command(&|c| matches!(c, Command::SideJob(_))));
+        assert!(!section.any_command(&|c| *c == Command::JournalGc));
+    }
+
+    #[test]
+    fn zipf_rank_prefers_small_ranks() {
+        let mut rng = rng();
+        let n = 1000;
+        let mut counts = vec![0; n + 1];
+        for _ in 0..100_000 {
+            let k = zipf_rank(&mut rng, n, 0.99);
+            assert!((1..=n).contains(&k));
+            counts[k] += 1;
+        }
+        assert!(counts[1] > counts[2]);
+        assert!(counts[2] > counts[10]);
+        assert!(counts[10] > counts[100]);
+        // 1/H(1000, 0.99) ~ 0.13
+        assert!((12_000..15_000).contains(&counts[1]), "{}", counts[1]);
+    }
+
+    #[test]
+    fn select_index_stays_in_bounds() {
+        let mut rng = rng();
+        let selectors = [
+            Selector::Range(0, 100),
+            Selector::Range(50, 50),
+            Selector::Zipf(1.0),
+            Selector::Zipf(0.5),
+            Selector::Hotspot(10, 90),
+            Selector::Hotspot(100, 50),
+            Selector::Latest,
+        ];
+        for len in 1..50 {
+            for selector in &selectors {
+                for _ in 0..20 {
+                    assert!(select_index(&mut rng, len, selector) < len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hotspot_concentrates_on_newest() {
+        let mut rng = rng();
+        let len = 1000;
+        let hot = (0..10_000)
+            .filter(|_| select_index(&mut rng, len, &Selector::Hotspot(10, 90)) >= 900)
+            .count();
+        assert!((8_700..9_300).contains(&hot), "{}", hot);
+    }
+
+    #[test]
+    fn sample_size_is_deterministic() {
+        let dist = SizeDist::Normal(1000, 100);
+        let v1: Vec<_> = {
+            let mut rng = rng();
+            (0..100).map(|_| sample_size(&mut rng, &dist)).collect()
+        };
+        let v2: Vec<_> = {
+            let mut rng = rng();
+            (0..100).map(|_| sample_size(&mut rng, &dist)).collect()
+        };
+        assert_eq!(v1, v2);
+    }
+
+    #[test]
+    fn sample_size_respects_bounds() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let x = sample_size(&mut rng, &SizeDist::Uniform(10, 20));
+            assert!((10..=20).contains(&x));
+
+            let x = sample_size(&mut rng, &SizeDist::Choice(vec![(1, 1), (7, 0), (3, 5)]));
+            assert!(x == 1 || x == 3);
+        }
+    }
+
+    #[test]
+    fn sample_size_mean_is_close() {
+        let mut rng = rng();
+        for dist in &[
+            SizeDist::Normal(10_000, 1_000),
+            SizeDist::LogNormal(10_000, 3_000),
+        ] {
+            let n = 10_000;
+            let sum: usize = (0..n).map(|_| sample_size(&mut rng, dist)).sum();
+            let mean = sum / n;
+            assert!((9_800..10_200).contains(&mean), "{:?}: {}", dist, mean);
+        }
+    }
+}
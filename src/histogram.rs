@@ -0,0 +1,343 @@
+//! レイテンシを記録する対数線形ヒストグラム.
+//!
+//! 値(ナノ秒)を2の冪ごとの区間に分け、各区間をさらに`2^SUB_BUCKET_BITS`個に等分して数える.
+//! `2^SUB_BUCKET_BITS`未満の値は正確に、それ以上の値は相対誤差`2^-SUB_BUCKET_BITS`
+//! (1%未満)以内で記録される.
+//! 件数・最小値・最大値・合計・二乗和は正確に保持するので、平均と標準偏差は誤差を含まない.
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+// 読み込むヒストグラムの`sub_bucket_bits`として受け付ける最大値
+const MAX_SUB_BUCKET_BITS: u32 = 16;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Sparse", into = "Sparse")]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+    sum_of_squares: u128,
+}
+
+// シリアライズ用の表現. 0でないバケツだけを (添字, 件数) で並べる.
+#[derive(Serialize, Deserialize)]
+struct Sparse {
+    sub_bucket_bits: u32,
+    count: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+    sum_of_squares: u128,
+    buckets: Vec<(usize, u64)>,
+}
+
+impl From<Histogram> for Sparse {
+    fn from(h: Histogram) -> Sparse {
+        Sparse {
+            sub_bucket_bits: SUB_BUCKET_BITS,
+            count: h.count,
+            min: h.min,
+            max: h.max,
+            sum: h.sum,
+            sum_of_squares: h.sum_of_squares,
+            buckets: h
+                .counts
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c > 0)
+                .map(|(i, c)| (i, *c))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<Sparse> for Histogram {
+    type Error = String;
+
+    fn try_from(s: Sparse) -> Result<Histogram, String> {
+        if s.sub_bucket_bits > MAX_SUB_BUCKET_BITS {
+            return Err(format!(
+                "sub_bucket_bits {} is larger than {}",
+                s.sub_bucket_bits, MAX_SUB_BUCKET_BITS
+            ));
+        }
+        // バケツの切り方が異なるものは代表値で数え直す
+        let buckets = bucket_count(s.sub_bucket_bits);
+        let mut counts = Vec::new();
+        for (i, c) in s.buckets {
+            if i >= buckets {
+                return Err(format!("bucket index {} is out of range", i));
+            }
+            let index = if s.sub_bucket_bits == SUB_BUCKET_BITS {
+                i
+            } else {
+                bucket_index(bucket_lowest(i, s.sub_bucket_bits))
+            };
+            if counts.len() <= index {
+                counts.resize(index + 1, 0);
+            }
+            counts[index] += c;
+        }
+        Ok(Histogram {
+            counts,
+            count: s.count,
+            min: s.min,
+            max: s.max,
+            sum: s.sum,
+            sum_of_squares: s.sum_of_squares,
+        })
+    }
+}
+
+// `sub_bucket_bits`で切ったときのバケツの数. u64の値はすべてこれより小さい添字のバケツに入る
+fn bucket_count(sub_bucket_bits: u32) -> usize {
+    (65 - sub_bucket_bits as usize) << sub_bucket_bits
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - SUB_BUCKET_BITS;
+    let sub = (value >> shift) - SUB_BUCKETS;
+    ((shift as u64 + 1) * SUB_BUCKETS + sub) as usize
+}
+
+// 添字`index`のバケツに入る最小の値
+fn bucket_lowest(index: usize, sub_bucket_bits: u32) -> u64 {
+    let sub_buckets = 1usize << sub_bucket_bits;
+    if index < sub_buckets {
+        return index as u64;
+    }
+    let shift = (index / sub_buckets - 1) as u32;
+    let sub = (index % sub_buckets) as u64;
+    (sub_buckets as u64 + sub) << shift
+}
+
+// 添字`index`のバケツに入る最大の値
+fn bucket_highest(index: usize) -> u64 {
+    if index < SUB_BUCKETS as usize {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS as usize - 1) as u32;
+    bucket_lowest(index, SUB_BUCKET_BITS) + ((1 << shift) - 1)
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, d: Duration) {
+        self.record_nanos(d.as_nanos() as u64);
+    }
+
+    pub fn record_nanos(&mut self, value: u64) {
+        let index = bucket_index(value);
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = std::cmp::max(self.max, value);
+        self.count += 1;
+        self.sum += u128::from(value);
+        self.sum_of_squares += u128::from(value) * u128::from(value);
+    }
+
+    /// `other`の記録をすべて取り込む.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c += o;
+        }
+
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        self.max = std::cmp::max(self.max, other.max);
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_of_squares += other.sum_of_squares;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn sum(&self) -> u128 {
+        self.sum
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.count as f64
+    }
+
+    /// 母標準偏差.
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        let mean = self.sum as f64 / n;
+        let variance = self.sum_of_squares as f64 / n - mean * mean;
+        variance.max(0.0).sqrt()
+    }
+
+    /// 値を昇順に並べたときの`ceil(q * count)`番目(1始まり)の値.
+    ///
+    /// 実際の値は返り値以下かつ相対誤差の範囲内にある. 空の場合は0を返す.
+    pub fn quantile(&self, q: f64) -> u64 {
+        assert!((0.0..=1.0).contains(&q), "quantile {} is out of [0, 1]", q);
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank = ((q * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (index, c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return bucket_highest(index).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_bounds_work() {
+        for &v in &[0, 1, 127, 128, 129, 255, 256, 1000, 123_456_789, u64::MAX] {
+            let i = bucket_index(v);
+            assert!(bucket_lowest(i, SUB_BUCKET_BITS) <= v, "{}", v);
+            assert!(v <= bucket_highest(i), "{}", v);
+        }
+        assert_eq!(bucket_highest(bucket_index(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn relative_error_is_bounded() {
+        let mut v: u64 = 1;
+        while v < 1 << 40 {
+            let i = bucket_index(v);
+            let width = bucket_highest(i) - bucket_lowest(i, SUB_BUCKET_BITS);
+            assert!((width as f64) <= v as f64 / SUB_BUCKETS as f64);
+            v = v * 3 / 2 + 1;
+        }
+    }
+
+    #[test]
+    fn quantiles_of_small_samples() {
+        let mut h = Histogram::new();
+        assert_eq!(h.quantile(0.5), 0);
+
+        h.record_nanos(10);
+        assert_eq!(h.quantile(0.0), 10);
+        assert_eq!(h.quantile(0.99), 10);
+
+        for v in &[20, 30, 40] {
+            h.record_nanos(*v);
+        }
+        assert_eq!(h.quantile(0.25), 10);
+        assert_eq!(h.quantile(0.5), 20);
+        assert_eq!(h.quantile(0.51), 30);
+        assert_eq!(h.quantile(1.0), 40);
+        assert_eq!(h.min(), 10);
+        assert_eq!(h.max(), 40);
+        assert_eq!(h.mean(), 25.0);
+        assert!((h.stddev() - 125f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quantiles_of_large_values() {
+        let mut h = Histogram::new();
+        for v in 1..=10_000u64 {
+            h.record_nanos(v * 1000);
+        }
+        for &q in &[0.5, 0.9, 0.99, 0.999] {
+            let expected = (q * 10_000.0) * 1000.0;
+            let actual = h.quantile(q) as f64;
+            assert!(actual >= expected && actual <= expected * 1.01, "{}", q);
+        }
+        assert_eq!(h.quantile(1.0), 10_000_000);
+    }
+
+    #[test]
+    fn merge_equals_recording_everything() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        let mut all = Histogram::new();
+        for v in 0..1000u64 {
+            let v = v * v * 7;
+            if v % 3 == 0 {
+                a.record_nanos(v);
+            } else {
+                b.record_nanos(v);
+            }
+            all.record_nanos(v);
+        }
+        a.merge(&b);
+        a.merge(&Histogram::new());
+        assert_eq!(a, all);
+    }
+
+    #[test]
+    fn serde_round_trip_works() {
+        let mut h = Histogram::new();
+        for v in &[5, 500, 50_000, 5_000_000] {
+            h.record_nanos(*v);
+        }
+        let json = serde_json::to_string(&h).unwrap();
+        let read: Histogram = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, h);
+    }
+
+    #[test]
+    fn malformed_histograms_are_rejected() {
+        let json = |bits: u32, index: usize| {
+            format!(
+                r#"{{"sub_bucket_bits":{},"count":1,"min":1,"max":1,"sum":1,"sum_of_squares":1,"buckets":[[{},1]]}}"#,
+                bits, index
+            )
+        };
+        assert!(serde_json::from_str::<Histogram>(&json(5, 100)).is_ok());
+        assert!(serde_json::from_str::<Histogram>(&json(200, 1)).is_err());
+        assert!(serde_json::from_str::<Histogram>(&json(7, usize::MAX)).is_err());
+        assert!(serde_json::from_str::<Histogram>(&json(7, bucket_count(7))).is_err());
+        assert_eq!(bucket_index(u64::MAX), bucket_count(SUB_BUCKET_BITS) - 1);
+    }
+}
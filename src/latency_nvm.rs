@@ -0,0 +1,273 @@
+//! 読み書きに遅延を加えて遅いデバイスを模擬するための`NonVolatileMemory`のラッパー.
+//!
+//! 読み書きのたびに、固定の遅延・転送量に比例する遅延・直前の読み書きの位置からの距離に比例する遅延を加える.
+//! ヘッドは一つしかないものとし、`split`した領域の間を行き来する読み書きもシークとして扱う.
+use cannyls::block::BlockSize;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::Result;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const MIB: f64 = (1 << 20) as f64;
+const GIB: f64 = (1 << 30) as f64;
+
+/// 模擬するデバイスの遅延. すべて0なら遅延を加えない.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyModel {
+    // 読み書きごとの固定の遅延
+    pub fixed: Duration,
+    // 1MiBの転送にかかる時間
+    pub per_mib: Duration,
+    // 1GiB離れた位置へのシークにかかる時間
+    pub seek_per_gib: Duration,
+    // `sync`ごとの遅延
+    pub sync: Duration,
+}
+
+impl LatencyModel {
+    /// 7200rpmのHDD程度.
+    pub fn hdd() -> LatencyModel {
+        LatencyModel {
+            fixed: Duration::from_micros(4000),
+            per_mib: Duration::from_micros(6700),
+            seek_per_gib: Duration::from_micros(4),
+            sync: Duration::from_millis(10),
+        }
+    }
+
+    /// SATA接続のSSD程度.
+    pub fn ssd() -> LatencyModel {
+        LatencyModel {
+            fixed: Duration::from_micros(80),
+            per_mib: Duration::from_micros(2000),
+            seek_per_gib: Duration::default(),
+            sync: Duration::from_micros(500),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == LatencyModel::default()
+    }
+
+    /// `bytes`バイトを`distance`バイト離れた位置で読み書きする時の遅延.
+    pub fn delay(&self, bytes: usize, distance: u64) -> Duration {
+        self.fixed
+            + self.per_mib.mul_f64(bytes as f64 / MIB)
+            + self.seek_per_gib.mul_f64(distance as f64 / GIB)
+    }
+}
+
+impl FromStr for LatencyModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<LatencyModel, String> {
+        match s {
+            "none" => Ok(LatencyModel::default()),
+            "hdd" => Ok(LatencyModel::hdd()),
+            "ssd" => Ok(LatencyModel::ssd()),
+            _ => Err(format!("unknown device profile: {} (none, hdd or ssd)", s)),
+        }
+    }
+}
+
+impl fmt::Display for LatencyModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fixed={:?} per_mib={:?} seek_per_gib={:?} sync={:?}",
+            self.fixed, self.per_mib, self.seek_per_gib, self.sync
+        )
+    }
+}
+
+/// 遅延を加えるのを一時的に止めるためのスイッチ. 複製したスイッチは同じ状態を共有する.
+#[derive(Debug, Clone, Default)]
+pub struct Pause(Arc<AtomicBool>);
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// `inner`への読み書きに`LatencyModel`の遅延を加えるNVM.
+///
+/// 止めている間の読み書きには遅延を加えず、ヘッドの位置も動かさない.
+#[derive(Debug)]
+pub struct LatencyNvm<N> {
+    inner: N,
+    model: LatencyModel,
+    start: u64,
+    // 直前に読み書きを終えた位置(NVM全体での位置)
+    head: Arc<AtomicU64>,
+    pause: Pause,
+}
+
+impl<N: NonVolatileMemory> LatencyNvm<N> {
+    pub fn new(inner: N, model: LatencyModel) -> LatencyNvm<N> {
+        LatencyNvm {
+            inner,
+            model,
+            start: 0,
+            head: Arc::new(AtomicU64::new(0)),
+            pause: Pause::default(),
+        }
+    }
+
+    /// `pause`で遅延を止められるようにする.
+    pub fn with_pause(mut self, pause: Pause) -> LatencyNvm<N> {
+        self.pause = pause;
+        self
+    }
+
+    fn wait(&self, from: u64, bytes: usize) {
+        if self.model.is_zero() || self.pause.is_set() {
+            return;
+        }
+        let from = self.start + from;
+        let to = from + bytes as u64;
+        let head = self.head.swap(to, Ordering::Relaxed);
+        let distance = head.max(from) - head.min(from);
+        std::thread::sleep(self.model.delay(bytes, distance));
+    }
+}
+
+impl<N: NonVolatileMemory> NonVolatileMemory for LatencyNvm<N> {
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()?;
+        if !self.model.is_zero() && !self.pause.is_set() {
+            std::thread::sleep(self.model.sync);
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    fn capacity(&self) -> u64 {
+        self.inner.capacity()
+    }
+
+    fn block_size(&self) -> BlockSize {
+        self.inner.block_size()
+    }
+
+    fn split(self, position: u64) -> Result<(Self, Self)> {
+        let (left, right) = self.inner.split(position)?;
+        let left = LatencyNvm {
+            inner: left,
+            model: self.model,
+            start: self.start,
+            head: self.head.clone(),
+            pause: self.pause.clone(),
+        };
+        let right = LatencyNvm {
+            inner: right,
+            model: self.model,
+            start: self.start + position,
+            head: self.head,
+            pause: self.pause,
+        };
+        Ok((left, right))
+    }
+}
+
+impl<N: NonVolatileMemory> Read for LatencyNvm<N> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let from = self.inner.position();
+        let read = self.inner.read(buf)?;
+        self.wait(from, read);
+        Ok(read)
+    }
+}
+
+impl<N: NonVolatileMemory> Write for LatencyNvm<N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let from = self.inner.position();
+        let written = self.inner.write(buf)?;
+        self.wait(from, written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<N: NonVolatileMemory> Seek for LatencyNvm<N> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cannyls::nvm::MemoryNvm;
+    use std::time::Instant;
+
+    #[test]
+    fn delay_works() {
+        let model = LatencyModel {
+            fixed: Duration::from_micros(100),
+            per_mib: Duration::from_millis(2),
+            seek_per_gib: Duration::from_millis(1),
+            sync: Duration::from_millis(3),
+        };
+        assert_eq!(model.delay(0, 0), Duration::from_micros(100));
+        assert_eq!(model.delay(1 << 19, 0), Duration::from_micros(1100));
+        assert_eq!(model.delay(0, 1 << 31), Duration::from_micros(2100));
+        assert!("hdd".parse::<LatencyModel>().is_ok());
+        assert!("none".parse::<LatencyModel>().unwrap().is_zero());
+        assert!("tape".parse::<LatencyModel>().is_err());
+    }
+
+    #[test]
+    fn seeks_across_split_regions_are_charged() {
+        let model = LatencyModel {
+            seek_per_gib: Duration::from_secs(1 << 10),
+            ..LatencyModel::default()
+        };
+        let nvm = LatencyNvm::new(MemoryNvm::new(vec![0; 4096]), model);
+        let (mut left, mut right) = nvm.split(2048).unwrap();
+
+        // 1024秒/GiBなので、1024バイト離れるごとに1ミリ秒. 2番目の書き込みは2560バイト離れている
+        let now = Instant::now();
+        left.write_all(&[1; 512]).unwrap();
+        right.seek(SeekFrom::Start(1024)).unwrap();
+        right.write_all(&[1; 512]).unwrap();
+        let elapsed = now.elapsed();
+        assert!(elapsed >= Duration::from_micros(2500), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn paused_nvm_is_not_delayed() {
+        let model = LatencyModel {
+            fixed: Duration::from_millis(100),
+            ..LatencyModel::default()
+        };
+        let pause = Pause::default();
+        let nvm = LatencyNvm::new(MemoryNvm::new(vec![0; 4096]), model).with_pause(pause.clone());
+        let (mut left, _) = nvm.split(2048).unwrap();
+
+        pause.set(true);
+        let now = Instant::now();
+        left.write_all(&[1; 512]).unwrap();
+        left.sync().unwrap();
+        assert!(now.elapsed() < Duration::from_millis(100));
+
+        pause.set(false);
+        let now = Instant::now();
+        left.write_all(&[1; 512]).unwrap();
+        assert!(now.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -3,54 +3,250 @@ extern crate combine;
 
 extern crate cannyls;
 extern crate rand;
+use cannyls::block::BlockSize;
 pub use cannyls::lump::LumpId;
+use std::time::Duration;
 
+pub mod capacity;
+pub mod counting_nvm;
+pub mod faulty_nvm;
 pub mod generator;
+pub mod histogram;
+pub mod latency_nvm;
+pub mod live_set;
+pub mod nvm;
 pub mod parse;
+pub mod report;
 pub mod run_commands;
+pub mod run_device;
+pub mod trace;
+pub mod validate;
 pub mod verifier;
 
 pub type Bytes = usize;
 pub type Perc = u8;
 pub type Freq = u8;
 
+/// `SideJob(n)`で一度に実行する補助タスクの最大回数.
+///
+/// ワークロードとトレースのどちらからも、これより大きい回数は受け付けない.
+pub const MAX_SIDE_JOBS: usize = 1_000_000;
+
+/// Put系コマンドが書き込むデータサイズの分布.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SizeDist {
+    // 常に同じサイズ
+    Fixed(Bytes),
+
+    // [min; max] の一様分布
+    Uniform(Bytes, Bytes),
+
+    // 平均と標準偏差で指定する正規分布
+    Normal(Bytes, Bytes),
+
+    // 平均と標準偏差で指定する対数正規分布
+    LogNormal(Bytes, Bytes),
+
+    // (サイズ, 重み) の中から重みに比例して選ぶ
+    Choice(Vec<(Bytes, u32)>),
+}
+
+/// Get・Delete・OverWriteの対象とする生存lumpの選び方.
+///
+/// 生存しているlumpは作成順に並んでいるものとし、末尾ほど新しい.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    // [left%; right%] 区間から一様に選ぶ
+    Range(Perc, Perc),
+
+    // 新しいものほど選ばれやすいZipf分布（引数は指数）
+    Zipf(f64),
+
+    // 新しい側の hot% のlumpに、アクセスの access% を集中させる
+    Hotspot(Perc, Perc),
+
+    // 最も新しいlump
+    Latest,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Command {
     // Put
-    NewPut(Bytes),
-    Overwrite(Bytes),
+    NewPut(SizeDist),
+    // `Put!(..)`: 新しいlumpをPutした直後にSyncする
+    NewPutSync(SizeDist),
+    // 対象を省略した場合は生存lump全体から一様に選ぶ
+    Overwrite(SizeDist, Option<Selector>),
 
     // Embedding Put
-    Embed(Bytes),
+    Embed(SizeDist),
 
     // Get
     RandomGet,
-    Get(Perc, Perc),
+    Get(Selector),
 
     // Delete
     RandomDelete,
-    Delete(Perc, Perc),
+    Delete(Selector),
 
     // DeleteRange
     DeleteRange(Perc, Perc),
 
     // Times
     Times(usize, Vec<Command>),
+
+    // ストレージを閉じ、同じNVMから開き直す
+    Reopen,
+
+    // ジャーナルをディスクに書き出す
+    Sync,
+
+    // ジャーナル全体のGC
+    JournalGc,
+
+    // 補助タスクを指定した回数だけ実行する
+    SideJob(usize),
+}
+
+impl Command {
+    /// `Times`の中も含めて、`pred`を満たすコマンドがあるかどうか.
+    pub fn any(&self, pred: &dyn Fn(&Command) -> bool) -> bool {
+        match self {
+            Command::Times(_, commands) => commands.iter().any(|c| c.any(pred)),
+            command => pred(command),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Statement(Vec<Command>);
 
+/// `Ordered`・`Unordered`セクションの繰り返し方.
 #[derive(Clone, Debug, PartialEq)]
-pub enum Section {
-    Ordered(usize, Vec<(Freq, Statement)>),
-    Unordered(usize, Vec<(Freq, Statement)>),
+pub enum Repeat {
+    // `[10000]`: 決まった回数だけstatementを展開する
+    Times(usize),
+
+    // `[for 600s]`: 時間が尽きるまでstatementを展開し続ける.
+    // 時間はセクションの最初のstatementを展開した時から測る
+    For(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SectionBody {
+    Ordered(Repeat, Vec<(Freq, Statement)>),
+    Unordered(Repeat, Vec<(Freq, Statement)>),
     Commands(Vec<Command>),
 }
 
+/// `Ordered[100] "warmup" { .. }`のように、セクションにはラベルを付けられる.
+///
+/// `Unordered[10000] @ 2000/s { .. }`のように目標スループットを付けると、
+/// そのセクションは開ループで(前のコマンドの完了を待たずに予定時刻どおりに)実行される.
+///
+/// `Unordered[10000] SideJob/100 { .. }`のようにすると、100操作ごとに`SideJob(1)`を挟む.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub label: Option<String>,
+    // 目標スループット(ops/s)
+    pub rate: Option<u64>,
+    // この操作数ごとに`SideJob(1)`を挟む
+    pub side_job_every: Option<usize>,
+    pub body: SectionBody,
+}
+
+impl Section {
+    /// `[for 600s]`のように時間で区切ったセクションかどうか.
+    pub fn is_timed(&self) -> bool {
+        match &self.body {
+            SectionBody::Ordered(repeat, _) | SectionBody::Unordered(repeat, _) => {
+                matches!(repeat, Repeat::For(_))
+            }
+            SectionBody::Commands(_) => false,
+        }
+    }
+
+    /// セクションの中に`pred`を満たすコマンドがあるかどうか.
+    ///
+    /// 実際に展開されるかどうかは問わないので、時間で区切ったセクションにも使える.
+    pub fn any_command(&self, pred: &dyn Fn(&Command) -> bool) -> bool {
+        match &self.body {
+            SectionBody::Ordered(_, statements) | SectionBody::Unordered(_, statements) => {
+                statements
+                    .iter()
+                    .any(|(_, Statement(commands))| commands.iter().any(|c| c.any(pred)))
+            }
+            SectionBody::Commands(commands) => commands.iter().any(|c| c.any(pred)),
+        }
+    }
+}
+
+impl From<SectionBody> for Section {
+    fn from(body: SectionBody) -> Section {
+        Section {
+            label: None,
+            rate: None,
+            side_job_every: None,
+            body,
+        }
+    }
+}
+
+/// 生成したコマンドがどのセクションに由来するか.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SectionInfo {
+    // 0始まりのセクション番号
+    pub index: usize,
+    pub label: Option<String>,
+    // 目標スループット(ops/s)
+    pub rate: Option<u64>,
+}
+
+impl std::fmt::Display for SectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "#{} \"{}\"", self.index, label),
+            None => write!(f, "#{}", self.index),
+        }
+    }
+}
+
+/// `Storage { .. }`ヘッダやコマンドラインで指定する`StorageBuilder`の設定.
+///
+/// ブロックサイズとジャーナル領域の割合は容量の見積もりにも使うので、
+/// 指定がなければ`DEFAULT_BLOCK_SIZE`・`DEFAULT_JOURNAL_RATIO`を明示的に設定する.
+/// その他の`None`の項目はcannylsの既定値を使う.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageOptions {
+    // ジャーナル領域がストレージ全体に占める割合
+    pub journal_ratio: Option<f64>,
+    // ジャーナルを同期する間隔(レコード数)
+    pub sync_interval: Option<usize>,
+    // ジャーナルGCのキューの長さ
+    pub gc_queue_size: Option<usize>,
+    pub block_size: Option<Bytes>,
+}
+
+impl StorageOptions {
+    /// ブロックサイズの既定値. cannylsの`StorageBuilder`の既定値と同じ.
+    pub const DEFAULT_BLOCK_SIZE: Bytes = BlockSize::MIN as Bytes;
+
+    /// ジャーナル領域の割合の既定値. cannylsの`StorageBuilder`の既定値と同じ.
+    pub const DEFAULT_JOURNAL_RATIO: f64 = 0.01;
+
+    pub fn block_size(&self) -> Bytes {
+        self.block_size.unwrap_or(Self::DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn journal_ratio(&self) -> f64 {
+        self.journal_ratio.unwrap_or(Self::DEFAULT_JOURNAL_RATIO)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Workload {
     pub seed: Option<u64>,
+    pub storage: StorageOptions,
     pub sections: Vec<Section>,
 }
 
@@ -70,4 +266,48 @@ pub enum RealCommand {
 
     // DeleteRange
     DeleteRange(LumpId, LumpId),
+
+    // ここから新しいセクションのコマンドが始まることを表す目印.
+    // ストレージに対しては何もしない.
+    BeginSection(SectionInfo),
+
+    // 時間で区切ったセクションが終わったことを表す目印.
+    // 値は実際に展開したstatementの数. ストレージに対しては何もしない.
+    EndSection(usize),
+
+    // ストレージを閉じ、同じNVMから開き直す
+    Reopen,
+
+    // `Storage::journal_sync`
+    Sync,
+
+    // `Storage::journal_gc`
+    JournalGc,
+
+    // `Storage::run_side_job_once`を指定した回数だけ呼ぶ
+    SideJob(usize),
+}
+
+impl RealCommand {
+    /// ストレージに対して何もしない目印かどうか.
+    pub fn is_marker(&self) -> bool {
+        matches!(
+            self,
+            RealCommand::BeginSection(_) | RealCommand::EndSection(_)
+        )
+    }
+
+    /// lumpを読み書きする操作かどうか.
+    ///
+    /// `SideJob/K`や目標スループットは操作の数で数え、`Sync`などのストレージ全体へのコマンドは数えない.
+    pub fn is_operation(&self) -> bool {
+        matches!(
+            self,
+            RealCommand::Put(..)
+                | RealCommand::Embed(..)
+                | RealCommand::Get(..)
+                | RealCommand::Delete(..)
+                | RealCommand::DeleteRange(..)
+        )
+    }
 }
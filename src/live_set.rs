@@ -0,0 +1,269 @@
+use crate::Bytes;
+use cannyls::lump::LumpId;
+
+/// 生存しているlumpを作成順に保持する列.
+///
+/// 「k番目の生存lump」の参照と削除、連続するk個の生存lumpの削除をいずれも O(log n) で行える.
+/// 削除されたlumpは空きスロットとして残し、各スロットの生死とサイズを
+/// 完全二分木(セグメント木)で数えることで順位を求める.
+/// 範囲の削除は節点に印を付けるだけにして、子孫へは必要になった時に伝える.
+/// 空きスロットが増えすぎたら詰め直す.
+#[derive(Debug, Default)]
+pub struct LiveSet {
+    slots: Vec<(LumpId, Bytes)>,
+    // 節点iの子は2iと2i+1、葉はcap..2cap. 節点が覆うスロットの生存数とサイズの合計
+    count: Vec<usize>,
+    bytes: Vec<Bytes>,
+    // 子孫をまだ空にしていない削除の印. 印のある節点の生存数は常に0
+    cleared: Vec<bool>,
+}
+
+// 詰め直した時に確保する最小の葉の数
+const MIN_CAPACITY: usize = 16;
+
+impl LiveSet {
+    pub fn new() -> LiveSet {
+        LiveSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.count.get(1).copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 最も新しいlumpとして末尾に追加する.
+    pub fn push(&mut self, lumpid: LumpId, bytes: Bytes) {
+        if self.slots.len() == self.capacity() {
+            self.rebuild();
+        }
+        self.slots.push((lumpid, bytes));
+        self.set_leaf(self.slots.len() - 1, 1, bytes);
+    }
+
+    /// `k`番目(0始まり)の生存lump.
+    pub fn get(&self, k: usize) -> (LumpId, Bytes) {
+        self.slots[self.find(k)]
+    }
+
+    pub fn set_bytes(&mut self, k: usize, bytes: Bytes) {
+        let slot = self.find(k);
+        self.slots[slot].1 = bytes;
+        self.set_leaf(slot, 1, bytes);
+    }
+
+    /// `k`番目(0始まり)の生存lumpを取り除く.
+    pub fn remove(&mut self, k: usize) -> (LumpId, Bytes) {
+        let slot = self.find(k);
+        let removed = self.slots[slot];
+        self.set_leaf(slot, 0, 0);
+        self.compact_if_sparse();
+        removed
+    }
+
+    /// `range`番目の生存lumpをまとめて取り除き、それらのサイズの合計を返す.
+    pub fn remove_range(&mut self, range: std::ops::Range<usize>) -> Bytes {
+        if range.start >= range.end {
+            return 0;
+        }
+        let first = self.find(range.start);
+        let last = self.find(range.end - 1);
+        let removed = self.clear(1, 0, self.capacity(), first, last + 1);
+        self.compact_if_sparse();
+        removed
+    }
+
+    fn capacity(&self) -> usize {
+        self.count.len() / 2
+    }
+
+    // k番目(0始まり)の生存lumpが入っているスロットの添字.
+    // 生存数が正の節点には削除の印がないので、子の値をそのまま使える
+    fn find(&self, k: usize) -> usize {
+        assert!(
+            k < self.len(),
+            "index {} out of range (len = {})",
+            k,
+            self.len()
+        );
+
+        let mut node = 1;
+        let mut rest = k;
+        while node < self.capacity() {
+            let left = 2 * node;
+            if self.count[left] > rest {
+                node = left;
+            } else {
+                rest -= self.count[left];
+                node = left + 1;
+            }
+        }
+        node - self.capacity()
+    }
+
+    fn set_leaf(&mut self, slot: usize, count: usize, bytes: Bytes) {
+        let leaf = self.capacity() + slot;
+        for height in (1..=self.capacity().trailing_zeros()).rev() {
+            self.push_down(leaf >> height);
+        }
+        self.count[leaf] = count;
+        self.bytes[leaf] = bytes;
+        let mut node = leaf / 2;
+        while node > 0 {
+            self.pull(node);
+            node /= 2;
+        }
+    }
+
+    // 節点`node`が覆うスロット[node_start; node_end)のうち、[start; end)を空にする
+    fn clear(
+        &mut self,
+        node: usize,
+        node_start: usize,
+        node_end: usize,
+        start: usize,
+        end: usize,
+    ) -> Bytes {
+        if end <= node_start || node_end <= start || self.count[node] == 0 {
+            return 0;
+        }
+        if start <= node_start && node_end <= end {
+            let removed = self.bytes[node];
+            self.count[node] = 0;
+            self.bytes[node] = 0;
+            self.cleared[node] = true;
+            return removed;
+        }
+        self.push_down(node);
+        let mid = (node_start + node_end) / 2;
+        let removed = self.clear(2 * node, node_start, mid, start, end)
+            + self.clear(2 * node + 1, mid, node_end, start, end);
+        self.pull(node);
+        removed
+    }
+
+    fn push_down(&mut self, node: usize) {
+        if !self.cleared[node] {
+            return;
+        }
+        for child in [2 * node, 2 * node + 1] {
+            self.count[child] = 0;
+            self.bytes[child] = 0;
+            self.cleared[child] = true;
+        }
+        self.cleared[node] = false;
+    }
+
+    fn pull(&mut self, node: usize) {
+        self.count[node] = self.count[2 * node] + self.count[2 * node + 1];
+        self.bytes[node] = self.bytes[2 * node] + self.bytes[2 * node + 1];
+    }
+
+    fn compact_if_sparse(&mut self) {
+        let dead = self.slots.len() - self.len();
+        if dead > self.len() + 1024 {
+            self.rebuild();
+        }
+    }
+
+    // 生存しているlumpだけを詰め直し、その倍以上の葉を持つ木を作り直す
+    fn rebuild(&mut self) {
+        let capacity = self.capacity();
+        for node in 1..capacity {
+            self.push_down(node);
+        }
+        let count = &self.count;
+        let live: Vec<_> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(slot, _)| count[capacity + slot] == 1)
+            .map(|(_, lump)| *lump)
+            .collect();
+
+        let capacity = (2 * live.len()).next_power_of_two().max(MIN_CAPACITY);
+        self.count = vec![0; 2 * capacity];
+        self.bytes = vec![0; 2 * capacity];
+        self.cleared = vec![false; 2 * capacity];
+        for (slot, (_, bytes)) in live.iter().enumerate() {
+            self.count[capacity + slot] = 1;
+            self.bytes[capacity + slot] = *bytes;
+        }
+        for node in (1..capacity).rev() {
+            self.pull(node);
+        }
+        self.slots = live;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn live_set_behaves_like_vec() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut set = LiveSet::new();
+        let mut model: Vec<(LumpId, Bytes)> = Vec::new();
+        let mut next = 0;
+
+        for _ in 0..20_000 {
+            match rng.gen_range(0, 10) {
+                0..=3 => {
+                    next += 1;
+                    set.push(LumpId::new(next), next as Bytes);
+                    model.push((LumpId::new(next), next as Bytes));
+                }
+                4 | 5 if !model.is_empty() => {
+                    let k = rng.gen_range(0, model.len());
+                    assert_eq!(set.remove(k), model.remove(k));
+                }
+                6 if !model.is_empty() => {
+                    let x = rng.gen_range(0, model.len());
+                    let y = rng.gen_range(x, (x + 20).min(model.len()) + 1);
+                    let expected: Bytes = model.drain(x..y).map(|(_, bytes)| bytes).sum();
+                    assert_eq!(set.remove_range(x..y), expected);
+                }
+                7 if !model.is_empty() => {
+                    let k = rng.gen_range(0, model.len());
+                    set.set_bytes(k, 42);
+                    model[k].1 = 42;
+                }
+                _ if !model.is_empty() => {
+                    let k = rng.gen_range(0, model.len());
+                    assert_eq!(set.get(k), model[k]);
+                }
+                _ => {}
+            }
+            assert_eq!(set.len(), model.len());
+        }
+
+        for (k, expected) in model.iter().enumerate() {
+            assert_eq!(set.get(k), *expected);
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_order() {
+        let mut set = LiveSet::new();
+        for i in 0..5000 {
+            set.push(LumpId::new(i), 1);
+        }
+        // 5の倍数番目だけ残す
+        for k in 0..1000 {
+            for _ in 0..4 {
+                set.remove(k + 1);
+            }
+        }
+        assert_eq!(set.len(), 1000);
+        assert!(set.slots.len() < 5000);
+        for k in 0..1000 {
+            assert_eq!(set.get(k).0, LumpId::new(5 * k as u128));
+        }
+        set.push(LumpId::new(9999), 1);
+        assert_eq!(set.get(1000).0, LumpId::new(9999));
+    }
+}
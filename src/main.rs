@@ -2,6 +2,7 @@ extern crate cannyls_bencher;
 extern crate fibers_global;
 extern crate fibers_http_server;
 extern crate futures;
+use cannyls::device::DeviceBuilder;
 use cannyls_bencher::generator;
 use cannyls_bencher::*;
 use futures::{lazy, Future};
@@ -9,21 +10,49 @@ use futures::{lazy, Future};
 use fibers_http_server::metrics::{MetricsHandler, WithMetrics};
 use fibers_http_server::ServerBuilder;
 
+use cannyls::lump::LumpId;
+use cannyls::storage::StorageHeader;
 use chrono::Local;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "cannyls_bencher🦀")]
 struct Opt {
     #[structopt(long)]
-    workload: PathBuf,
+    workload: Option<PathBuf>,
 
     #[structopt(long, parse(try_from_str = "parse_with_suffix"))]
     capacity: Option<u64>,
 
     #[structopt(long)]
-    lusfname: PathBuf,
+    lusfname: Option<PathBuf>,
+
+    // ストレージを置くNVM: memory, file(`--lusfname`)、またはブロックデバイスのパス
+    #[structopt(long, default_value = "file")]
+    nvm: nvm::NvmKind,
+
+    // NVMの読み書きに遅延を加え、遅いデバイスを模擬する: none, hdd, ssd.
+    // 下の`--latency-*`で個々の値を上書きできる
+    #[structopt(long, default_value = "none")]
+    emulate: latency_nvm::LatencyModel,
+
+    // 読み書きごとの固定の遅延(マイクロ秒)
+    #[structopt(long = "latency-fixed-us")]
+    latency_fixed_us: Option<f64>,
+
+    // 1MiBの転送にかかる時間(マイクロ秒)
+    #[structopt(long = "latency-per-mib-us")]
+    latency_per_mib_us: Option<f64>,
+
+    // 1GiB離れた位置へのシークにかかる時間(マイクロ秒)
+    #[structopt(long = "latency-seek-per-gib-us")]
+    latency_seek_per_gib_us: Option<f64>,
+
+    // `sync`ごとの遅延(マイクロ秒)
+    #[structopt(long = "latency-sync-us")]
+    latency_sync_us: Option<f64>,
 
     #[structopt(long)]
     verbose: bool,
@@ -31,8 +60,229 @@ struct Opt {
     #[structopt(long)]
     verify_mode: bool,
 
+    // ランダムな時点でクラッシュさせ、開き直したストレージの中身を検証する
+    #[structopt(long = "crash-verify")]
+    crash_verify: bool,
+
+    // クラッシュの仕方: drop(同期していない書き込みを失う), torn(発行した順に途中まで残る), error(I/Oエラー)
+    #[structopt(long, default_value = "drop")]
+    fault: faulty_nvm::Fault,
+
+    // 平均してこのコマンド数ごとにクラッシュさせる
+    #[structopt(long = "crash-every", default_value = "1000")]
+    crash_every: u64,
+
+    // 既存のストレージの扱い: create(常に作り直す), open, create-or-open
+    #[structopt(long = "storage-mode", default_value = "create")]
+    storage_mode: run_commands::StorageMode,
+
+    // セクションの境目と最後にストレージを開き直し、開くのにかかる時間を測る
+    #[structopt(long = "measure-open")]
+    measure_open: bool,
+
     #[structopt(long)]
     block_size: Option<u16>,
+
+    // ジャーナル領域の割合・同期間隔・GCのキューの長さ.
+    // ワークロードの`Storage { .. }`より優先する
+    #[structopt(long = "journal-ratio")]
+    journal_ratio: Option<f64>,
+
+    #[structopt(long = "sync-interval")]
+    sync_interval: Option<usize>,
+
+    #[structopt(long = "gc-queue-size")]
+    gc_queue_size: Option<usize>,
+
+    // ワークロードから生成したコマンド列をファイルに書き出して終了する
+    #[structopt(long = "dump-trace")]
+    dump_trace: Option<PathBuf>,
+
+    #[structopt(long = "trace-format", default_value = "text")]
+    trace_format: trace::TraceFormat,
+
+    // ワークロードの代わりに書き出し済みのコマンド列を実行する
+    #[structopt(long = "trace")]
+    trace: Option<PathBuf>,
+
+    #[structopt(long = "report-json")]
+    report_json: Option<PathBuf>,
+
+    #[structopt(long = "report-csv")]
+    report_csv: Option<PathBuf>,
+
+    // 指定したラベルのセクションを全体の統計から除く（複数指定可）
+    #[structopt(long = "exclude-section", number_of_values = 1)]
+    exclude_section: Vec<String>,
+
+    // ストレージの状態にジャーナルの位置も記録する. ジャーナル領域を読み、バッファのジャーナルを書き出す
+    #[structopt(long = "snapshot-journal-positions")]
+    snapshot_journal_positions: bool,
+
+    // 実行中の区間ごとの統計を集計する間隔（秒・操作数）
+    #[structopt(long = "interval-secs")]
+    interval_secs: Option<f64>,
+
+    #[structopt(long = "interval-ops")]
+    interval_ops: Option<u64>,
+
+    // 区間ごとの統計を書き出すファイル
+    #[structopt(long = "interval-output")]
+    interval_output: Option<PathBuf>,
+
+    #[structopt(long = "interval-format", default_value = "jsonl")]
+    interval_format: run_commands::SeriesFormat,
+
+    // 区間ごとに進捗と残り時間を表示する
+    #[structopt(long)]
+    progress: bool,
+
+    // 開ループで、この目標スループット(ops/s)に合わせてコマンドを発行する.
+    // `@ 2000/s`を指定したセクションではそちらを優先する
+    #[structopt(long = "target-ops-per-sec")]
+    target_ops_per_sec: Option<u64>,
+
+    // ストレージをcannylsのDeviceに載せ、複数のクライアントから並行してコマンドを発行する
+    #[structopt(long = "device-mode")]
+    device_mode: bool,
+
+    #[structopt(long, default_value = "8")]
+    clients: usize,
+
+    // デバイスモードで、lump idごとに振り分けてから完了を待つまでのコマンド数
+    #[structopt(long = "batch-size", default_value = "10000")]
+    batch_size: usize,
+
+    #[structopt(long = "max-queue-len")]
+    max_queue_len: Option<usize>,
+}
+
+// 実行するコマンド列の出どころ
+enum Source {
+    // 開いたストレージに既にあるlumpとともに持つ
+    Workload(PathBuf, Workload, Vec<(LumpId, Bytes)>),
+    Trace(PathBuf),
+}
+
+impl Source {
+    fn commands(&self) -> Box<dyn Iterator<Item = RealCommand> + '_> {
+        match self {
+            Source::Workload(_, w, lumps) => {
+                Box::new(generator::Generator::with_live_lumps(w, lumps))
+            }
+            Source::Trace(path) => Box::new(open_trace(path).map(move |command| {
+                command.unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                })
+            })),
+        }
+    }
+
+    fn metadata(
+        &self,
+        capacity: u64,
+        header: &StorageHeader,
+        options: &StorageOptions,
+    ) -> report::Metadata {
+        let (workload, trace, seed) = match self {
+            Source::Workload(path, w, _) => (
+                Some(path.display().to_string()),
+                None,
+                Some(w.seed.unwrap_or(0)),
+            ),
+            Source::Trace(path) => (None, Some(path.display().to_string()), None),
+        };
+        report::Metadata {
+            workload,
+            trace,
+            seed,
+            capacity,
+            block_size: header.block_size.as_u16(),
+            journal_region_size: header.journal_region_size,
+            journal_sync_interval: options.sync_interval,
+            journal_gc_queue_size: options.gc_queue_size,
+            cannyls_version: report::CANNYLS_VERSION.to_owned(),
+            nvm: String::new(),
+            latency_model: String::new(),
+            storage_mode: String::new(),
+            excluded_sections: Vec::new(),
+            target_ops_per_sec: None,
+            start_time: String::new(),
+            end_time: String::new(),
+        }
+    }
+
+    fn dry_run(&self, block_size: Bytes) -> generator::DryRun {
+        match self {
+            Source::Workload(_, w, lumps) => {
+                generator::dry_run_with_live_lumps(w, lumps, block_size)
+            }
+            Source::Trace(path) => {
+                trace::dry_run(open_trace(path), block_size).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                })
+            }
+        }
+    }
+}
+
+// `--measure-open`: 何か実行した後のセクションの境目と、最後に`Reopen`を挟む
+fn insert_reopens<'a>(
+    commands: Box<dyn Iterator<Item = RealCommand> + 'a>,
+) -> Box<dyn Iterator<Item = RealCommand> + 'a> {
+    let mut dirty = false;
+    let mut commands = commands.fuse();
+    let mut pending = None;
+    Box::new(std::iter::from_fn(move || {
+        if let Some(command) = pending.take() {
+            return Some(command);
+        }
+        match commands.next() {
+            Some(command @ RealCommand::BeginSection(_)) if dirty => {
+                dirty = false;
+                pending = Some(command);
+                Some(RealCommand::Reopen)
+            }
+            Some(command) => {
+                if command == RealCommand::Reopen {
+                    dirty = false;
+                } else if !command.is_marker() {
+                    dirty = true;
+                }
+                Some(command)
+            }
+            None if dirty => {
+                dirty = false;
+                Some(RealCommand::Reopen)
+            }
+            None => None,
+        }
+    }))
+}
+
+// コマンド列は実行時に逐次生成するので、ここでは必要な容量を見積もるために空回しする
+fn print_dry_run(source: &Source, timed: bool, block_size: Bytes) -> generator::DryRun {
+    println!("Start Dry Run @ {}", Local::now());
+    let dry_run = source.dry_run(block_size);
+    println!("Finish Dry Run @ {}", Local::now());
+    println!("Commands = {}", dry_run.commands);
+    println!("Least Required Bytes = {}", dry_run.peek_bytes);
+    if dry_run.reopens > 0 {
+        println!("Reopens = {}", dry_run.reopens);
+    }
+    if timed {
+        println!("[Notice:] time-bounded sections are not included in the dry run");
+    }
+    dry_run
+}
+
+fn open_trace(path: &PathBuf) -> trace::TraceReader<std::io::BufReader<std::fs::File>> {
+    trace::open_trace_file(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(1);
+    })
 }
 
 fn parse_with_suffix(s: &str) -> Result<u64, String> {
@@ -50,15 +300,57 @@ fn parse_with_suffix(s: &str) -> Result<u64, String> {
 }
 
 fn file_to_workload<P: AsRef<std::path::Path>>(filepath: P) -> Workload {
-    use combine::parser::Parser;
+    match parse::parse_workload_file(filepath) {
+        Ok(workload) => workload,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let workload: String = std::fs::read_to_string(filepath).unwrap();
-    let workload: Workload = parse::parse_workload()
-        .parse(workload.as_ref() as &str)
-        .unwrap()
-        .0;
+struct IntervalOpt {
+    every: Option<Duration>,
+    every_ops: Option<u64>,
+    output: Option<PathBuf>,
+    format: run_commands::SeriesFormat,
+    progress: bool,
+    total_commands: usize,
+}
+
+impl IntervalOpt {
+    // 時系列の出力も進捗の表示も不要なら`None`
+    fn reporter(self) -> Option<run_commands::IntervalReporter> {
+        if self.output.is_none() && !self.progress {
+            return None;
+        }
+
+        let mut reporter = run_commands::IntervalReporter::new(self.every, self.every_ops);
+        let format = self.format;
+        if let Some(path) = self.output {
+            let result = std::fs::File::create(&path)
+                .and_then(|file| reporter.output(std::io::BufWriter::new(file), format));
+            if let Err(e) = result {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        if self.progress {
+            reporter.progress(Some(self.total_commands));
+        }
+        Some(reporter)
+    }
+}
 
-    workload
+fn write_report<F>(path: &PathBuf, write: F)
+where
+    F: FnOnce(std::io::BufWriter<std::fs::File>) -> std::io::Result<()>,
+{
+    let result = std::fs::File::create(path).and_then(|file| write(std::io::BufWriter::new(file)));
+    match result {
+        Ok(()) => println!("Wrote report to {}", path.display()),
+        Err(e) => eprintln!("{}: {}", path.display(), e),
+    }
 }
 
 fn main() {
@@ -66,35 +358,419 @@ fn main() {
     let lusfname = opt.lusfname.clone();
     let capacity = opt.capacity;
     let verify_mode = opt.verify_mode;
-    let block_size = opt.block_size;
     println!("{:#?}", opt);
 
-    let w = file_to_workload(opt.workload);
+    let mut source = match (opt.workload, opt.trace) {
+        (None, Some(path)) => Source::Trace(path),
+        (Some(path), None) => {
+            let w = file_to_workload(&path);
+
+            let diagnostics = validate::validate(&w);
+            for d in &diagnostics {
+                eprintln!("{}", d);
+            }
+            if validate::has_errors(&diagnostics) {
+                std::process::exit(1);
+            }
+
+            for label in &opt.exclude_section {
+                if !w.sections.iter().any(|s| s.label.as_ref() == Some(label)) {
+                    eprintln!("warning: no section is labeled \"{}\"", label);
+                }
+            }
+
+            if opt.verbose {
+                println!("{:?}", w);
+            }
+            Source::Workload(path, w, Vec::new())
+        }
+        _ => {
+            eprintln!("exactly one of --workload and --trace is required");
+            std::process::exit(1);
+        }
+    };
+
+    // コマンドラインの指定はワークロードの`Storage { .. }`より優先する
+    let mut storage_options = match &source {
+        Source::Workload(_, w, _) => w.storage.clone(),
+        Source::Trace(_) => StorageOptions::default(),
+    };
+    if let Some(block_size) = opt.block_size {
+        storage_options.block_size = Some(block_size as Bytes);
+    }
+    if let Some(ratio) = opt.journal_ratio {
+        storage_options.journal_ratio = Some(ratio);
+    }
+    if let Some(interval) = opt.sync_interval {
+        storage_options.sync_interval = Some(interval);
+    }
+    if let Some(size) = opt.gc_queue_size {
+        storage_options.gc_queue_size = Some(size);
+    }
+    let errors = validate::validate_storage_options(&storage_options);
+    for e in &errors {
+        eprintln!("error: {}", e);
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
+    }
 
-    if opt.verbose {
-        println!("{:?}", w);
+    let timed = match &source {
+        Source::Workload(_, w, _) => w.sections.iter().any(Section::is_timed),
+        Source::Trace(_) => false,
+    };
+
+    if let Some(path) = opt.dump_trace {
+        if timed {
+            eprintln!("time-bounded sections ([for ..]) cannot be written to a trace");
+            std::process::exit(1);
+        }
+        let result = trace::create_trace_file(&path, opt.trace_format).and_then(|mut writer| {
+            let mut count = 0;
+            for command in source.commands() {
+                writer.write(&command)?;
+                count += 1;
+            }
+            writer.finish().map(|_| count)
+        });
+        match result {
+            Ok(count) => println!("Wrote {} commands to {}", count, path.display()),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
     }
 
-    println!("Start Generating Commands @ {}", Local::now());
-    let (commands, least_required) = generator::workload_to_real_commands(&w);
-    println!("Finish Generating Commands @ {}", Local::now());
-    println!("Least Required Bytes = {}", least_required);
+    if opt.nvm == nvm::NvmKind::File && lusfname.is_none() {
+        eprintln!("--lusfname is required");
+        std::process::exit(1);
+    }
 
-    let mut storage = if let Some(capacity) = capacity {
-        run_commands::make_storage_on_file(lusfname, capacity, block_size)
+    // ブロックデバイスは全体を使う
+    let device_size = match &opt.nvm {
+        nvm::NvmKind::Device(path) => {
+            let size = nvm::is_block_device(path).and_then(|is_block_device| {
+                if is_block_device {
+                    nvm::detect_size(path)
+                } else {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "not a block device (use --nvm file --lusfname for files)",
+                    ))
+                }
+            });
+            match size {
+                Ok(size) => Some(size),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // 既存のストレージを開く場合は、その容量とブロックサイズを使う
+    let storage_path = match &opt.nvm {
+        nvm::NvmKind::Memory => None,
+        nvm::NvmKind::File => Some(lusfname.as_ref().expect("checked above")),
+        nvm::NvmKind::Device(path) => Some(path),
+    };
+    let existing_path = match opt.storage_mode {
+        run_commands::StorageMode::Create => None,
+        _ => storage_path,
+    };
+    let existing = existing_path.and_then(|path| match nvm::storage_header(path) {
+        Ok(header) => header,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    });
+    if existing.is_none() && opt.storage_mode == run_commands::StorageMode::Open {
+        eprintln!("--storage-mode open: no storage to open");
+        std::process::exit(1);
+    }
+    if let Some(header) = &existing {
+        if opt.crash_verify {
+            eprintln!("--crash-verify always creates a new storage (use --storage-mode create)");
+            std::process::exit(1);
+        }
+        if storage_options
+            .block_size
+            .is_some_and(|b| b != header.block_size.as_u16() as Bytes)
+        {
+            eprintln!(
+                "block_size differs from the block size {} of the storage",
+                header.block_size.as_u16()
+            );
+            std::process::exit(1);
+        }
+        if storage_options.journal_ratio.is_some() {
+            eprintln!("warning: journal_ratio is ignored when opening a storage");
+        }
+    }
+
+    // 必要な容量はストレージのブロックサイズとジャーナル領域の割合で見積もる
+    let block_size = match &existing {
+        Some(header) => header.block_size.as_u16() as Bytes,
+        None => storage_options.block_size(),
+    };
+    let journal_ratio = storage_options.journal_ratio();
+
+    // 時間で区切ったセクションは空回しで展開しないので、容量を見積もれない
+    if existing.is_none() && timed && capacity.is_none() && device_size.is_none() {
+        eprintln!(
+            "time-bounded sections ([for ..]) are not included in the capacity estimate; specify --capacity"
+        );
+        std::process::exit(1);
+    }
+
+    // 既存のストレージを開く場合は、既にあるlumpが分かってから空回しする
+    let dry_run = if existing.is_none() {
+        Some(print_dry_run(&source, timed, block_size))
+    } else {
+        None
+    };
+    let capacity = match (&existing, &dry_run) {
+        (Some(header), _) => {
+            if capacity.is_some() {
+                eprintln!("warning: --capacity is ignored when opening a storage");
+            }
+            header.storage_size()
+        }
+        (None, dry_run) => capacity.or(device_size).unwrap_or_else(|| {
+            let peak = dry_run.as_ref().expect("bug").peak;
+            capacity::Estimate::new(peak, block_size, journal_ratio).default_capacity()
+        }),
+    };
+    if let Some(size) = device_size {
+        if capacity > size {
+            eprintln!("--capacity {} exceeds the device size {}", capacity, size);
+            std::process::exit(1);
+        }
+    }
+    let storage_nvm = match storage_path {
+        None => nvm::AnyNvm::memory(capacity),
+        Some(path) => {
+            let nvm = if existing.is_some() {
+                nvm::AnyNvm::file(path, capacity)
+            } else {
+                nvm::AnyNvm::create_file(path, capacity)
+            };
+            nvm.unwrap_or_else(|e| {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            })
+        }
+    };
+    let reopener = storage_nvm.reopener();
+    if opt.crash_verify {
+        if opt.crash_every == 0 {
+            eprintln!("--crash-every must be positive");
+            std::process::exit(1);
+        }
+        let options = verifier::CrashOptions {
+            fault: opt.fault,
+            every: opt.crash_every,
+            seed: match &source {
+                Source::Workload(_, w, _) => w.seed.unwrap_or(0),
+                Source::Trace(_) => 0,
+            },
+        };
+        println!("Start Crash Verifying @ {}", Local::now());
+        let summary = verifier::crash_verify_commands(
+            storage_nvm,
+            &storage_options,
+            source.commands(),
+            &options,
+        );
+        println!("Finish Crash Verifying @ {}", Local::now());
+        println!(
+            "Crashes = {} ({} inside a command)",
+            summary.crashes, summary.crashes_in_command
+        );
+        println!("Checked Lumps = {}", summary.checked_lumps);
+        return;
+    }
+    let micros = |us: f64| match Duration::try_from_secs_f64(us / 1_000_000.0) {
+        Ok(latency) => latency,
+        Err(_) => {
+            eprintln!("latencies must be non-negative and finite (in microseconds)");
+            std::process::exit(1);
+        }
+    };
+    let mut latency_model = opt.emulate;
+    if let Some(us) = opt.latency_fixed_us {
+        latency_model.fixed = micros(us);
+    }
+    if let Some(us) = opt.latency_per_mib_us {
+        latency_model.per_mib = micros(us);
+    }
+    if let Some(us) = opt.latency_seek_per_gib_us {
+        latency_model.seek_per_gib = micros(us);
+    }
+    if let Some(us) = opt.latency_sync_us {
+        latency_model.sync = micros(us);
+    }
+    let latency_pause = latency_nvm::Pause::default();
+    let storage_nvm =
+        latency_nvm::LatencyNvm::new(storage_nvm, latency_model).with_pause(latency_pause.clone());
+    let storage_nvm = counting_nvm::CountingNvm::new(storage_nvm);
+    let io_counter = storage_nvm.counter();
+    let mut storage = if existing.is_some() {
+        run_commands::open_storage(storage_nvm, &storage_options)
     } else {
-        let mbyte = 1024 * 1024;
-        let least_required = ((least_required + (mbyte - 1)) / mbyte) * mbyte;
-        run_commands::make_storage_on_file(
-            lusfname,
-            (1.5 * least_required as f64) as u64,
-            block_size,
-        )
+        run_commands::make_storage(storage_nvm, &storage_options)
+    };
+    let dry_run = match dry_run {
+        Some(dry_run) => dry_run,
+        None => {
+            // 既にあるlumpも`Get`や`Delete`の対象にする
+            let lumps = if verify_mode {
+                verifier::verify_live_lumps(&mut storage)
+            } else {
+                run_commands::live_lumps(&mut storage)
+            };
+            println!("[Notice:] we OPEN the storage ({} lumps)", lumps.len());
+            if let Source::Workload(_, _, live) = &mut source {
+                *live = lumps;
+            }
+            print_dry_run(&source, timed, block_size)
+        }
+    };
+    let estimate = capacity::Estimate::new(dry_run.peak, block_size, journal_ratio);
+    if existing.is_none() {
+        println!("Estimated Capacity = {}", estimate.capacity);
+    }
+    for warning in estimate.check(storage.header()) {
+        eprintln!("warning: {}", warning);
+    }
+    let mut reopen = {
+        let io_counter = io_counter.clone();
+        let latency_pause = latency_pause.clone();
+        let storage_options = storage_options.clone();
+        move || {
+            let nvm = reopener
+                .reopen()
+                .unwrap_or_else(|e| panic!("Reopen Error: {}", e));
+            let nvm =
+                latency_nvm::LatencyNvm::new(nvm, latency_model).with_pause(latency_pause.clone());
+            let nvm = io_counter.wrap(nvm);
+            run_commands::open_storage(nvm, &storage_options)
+        }
+    };
+    let measure_open = opt.measure_open;
+    let probe = if opt.snapshot_journal_positions {
+        let io_counter = io_counter.clone();
+        Some(run_commands::JournalProbe::new(move |paused| {
+            io_counter.pause(paused);
+            latency_pause.set(paused);
+        }))
+    } else {
+        None
+    };
+    let storage_header = storage.header().clone();
+    let mut metadata = source.metadata(
+        storage_header.storage_size(),
+        &storage_header,
+        &storage_options,
+    );
+    metadata.nvm = opt.nvm.to_string();
+    metadata.storage_mode = if existing.is_some() { "open" } else { "create" }.to_owned();
+    if !latency_model.is_zero() {
+        metadata.latency_model = latency_model.to_string();
+    }
+    metadata.excluded_sections = opt.exclude_section;
+    if opt.target_ops_per_sec == Some(0) {
+        eprintln!("--target-ops-per-sec must be positive");
+        std::process::exit(1);
+    }
+    metadata.target_ops_per_sec = opt.target_ops_per_sec;
+    let pacer = run_commands::Pacer::new(opt.target_ops_per_sec);
+    let report_json = opt.report_json;
+    let report_csv = opt.report_csv;
+    let every = opt
+        .interval_secs
+        .map(|secs| match Duration::try_from_secs_f64(secs) {
+            Ok(every) if every > Duration::from_secs(0) => every,
+            _ => {
+                eprintln!("--interval-secs must be a positive number of seconds");
+                std::process::exit(1);
+            }
+        });
+    if opt.interval_ops == Some(0) {
+        eprintln!("--interval-ops must be positive");
+        std::process::exit(1);
+    }
+    if (every.is_some() || opt.interval_ops.is_some())
+        && opt.interval_output.is_none()
+        && !opt.progress
+    {
+        eprintln!("--interval-secs and --interval-ops require --interval-output or --progress");
+        std::process::exit(1);
+    }
+    let interval = IntervalOpt {
+        every,
+        every_ops: opt.interval_ops,
+        output: opt.interval_output,
+        format: opt.interval_format,
+        progress: opt.progress,
+        total_commands: dry_run.commands,
+    };
+    let device = if opt.device_mode {
+        if opt.clients == 0 || opt.batch_size == 0 {
+            eprintln!("--clients and --batch-size must be positive");
+            std::process::exit(1);
+        }
+        if interval.output.is_some() || interval.progress {
+            eprintln!("warning: interval statistics are not supported in the device mode");
+        }
+        if opt.target_ops_per_sec.is_some() {
+            eprintln!("warning: open-loop execution is not supported in the device mode");
+        }
+        // 時間で区切ったセクションは空回しで展開しないので、ワークロードそのものも調べる
+        let (has_reopen, has_maintenance) = match &source {
+            Source::Workload(_, w, _) => (
+                w.sections
+                    .iter()
+                    .any(|s| s.any_command(&|c| *c == Command::Reopen)),
+                w.sections.iter().any(|s| {
+                    s.side_job_every.is_some()
+                        || s.any_command(&|c| matches!(c, Command::JournalGc | Command::SideJob(_)))
+                }),
+            ),
+            Source::Trace(_) => (false, false),
+        };
+        if measure_open || has_reopen || dry_run.reopens > 0 {
+            eprintln!("Reopen is not supported in the device mode");
+            std::process::exit(1);
+        }
+        if has_maintenance || dry_run.maintenance > 0 {
+            eprintln!("JournalGc and SideJob are not supported in the device mode");
+            std::process::exit(1);
+        }
+        if probe.is_some() {
+            eprintln!("warning: journal positions are not recorded in the device mode");
+        }
+        let mut builder = DeviceBuilder::new();
+        if let Some(n) = opt.max_queue_len {
+            builder.max_queue_len(n);
+        }
+        Some((builder, opt.clients, opt.batch_size))
+    } else {
+        None
     };
 
     if verify_mode {
         println!("Start Verifying @ {}", Local::now());
-        verifier::verify_commands(&mut storage, &commands);
+        let mut commands = source.commands();
+        if measure_open {
+            commands = insert_reopens(commands);
+        }
+        verifier::verify_commands(storage, commands, Some(&mut reopen));
         println!("Finish Verifying @ {}", Local::now());
         return;
     }
@@ -108,19 +784,50 @@ fn main() {
 
     let server = builder.finish(fibers_global::handle());
     fibers_global::spawn(server.map_err(|e| panic!("Metrics Server Error: {:?}", e)));
-    fibers_global::execute(
-        lazy(move || {
-            println!("Start Benchmark @ {}", Local::now());
-            let mut summary = run_commands::do_commands(&mut storage, &commands);
-            println!("Finish Benchmark @ {}", Local::now());
-
-            println!("Calculating Statistics...");
-            run_commands::statistics(&mut summary);
-
-            Ok(())
-        })
-        .map(|_| ())
-        .map_err(|_: ()| ()),
-    )
-    .unwrap();
+    io_counter.reset();
+    let start_time = Local::now();
+    println!("Start Benchmark @ {}", start_time);
+    let summary = match device {
+        Some((builder, clients, batch_size)) => run_device::do_commands_on_device(
+            &builder,
+            storage,
+            source.commands(),
+            clients,
+            batch_size,
+        ),
+        None => fibers_global::execute(lazy(move || {
+            let mut commands = source.commands();
+            if measure_open {
+                commands = insert_reopens(commands);
+            }
+            let summary = run_commands::do_commands_paced(
+                storage,
+                commands,
+                pacer,
+                interval.reporter(),
+                Some(&mut reopen),
+                probe,
+            );
+            Ok::<_, ()>(summary)
+        }))
+        .unwrap(),
+    };
+    let end_time = Local::now();
+    println!("Finish Benchmark @ {}", end_time);
+
+    println!("Calculating Statistics...");
+    metadata.start_time = start_time.to_rfc3339();
+    metadata.end_time = end_time.to_rfc3339();
+    let mut report = run_commands::statistics(&summary, metadata);
+    let (written, read) = summary.logical_bytes();
+    report.io = Some(io_counter.report(&storage_header, written, read));
+    report.capacity = Some(estimate.report(&storage_header, &report.snapshots));
+    println!("{}", report);
+
+    if let Some(path) = report_json {
+        write_report(&path, |file| report.write_json(file));
+    }
+    if let Some(path) = report_csv {
+        write_report(&path, |file| report.write_csv(file));
+    }
 }
@@ -0,0 +1,387 @@
+//! ベンチマークに使う`NonVolatileMemory`を選ぶためのモジュール.
+//!
+//! メモリ上のNVMを使えば、ディスクの影響を除いたcannyls自体のCPU負荷を測れる.
+use cannyls::block::BlockSize;
+use cannyls::nvm::{FileNvm, FileNvmBuilder, NonVolatileMemory, SharedMemoryNvm};
+use cannyls::storage::StorageHeader;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// `--nvm`で指定するNVMの種類.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NvmKind {
+    // メモリ上に確保する
+    Memory,
+
+    // `--lusfname`のファイル
+    File,
+
+    // 既存のブロックデバイス. 容量はデバイスのサイズから求める
+    Device(PathBuf),
+}
+
+impl FromStr for NvmKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NvmKind, String> {
+        match s {
+            "memory" => Ok(NvmKind::Memory),
+            "file" => Ok(NvmKind::File),
+            "" => Err("empty NVM (memory, file or a device path)".to_owned()),
+            path => Ok(NvmKind::Device(PathBuf::from(path))),
+        }
+    }
+}
+
+impl fmt::Display for NvmKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NvmKind::Memory => write!(f, "memory"),
+            NvmKind::File => write!(f, "file"),
+            NvmKind::Device(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// いずれかのNVM. ストレージの型を実行時に選ぶために使う.
+///
+/// メモリ上のNVMは`Reopen`で開き直せるように、複製しても中身を共有する.
+#[derive(Debug)]
+pub enum AnyNvm {
+    Memory(SharedMemoryNvm),
+    File(FileNvm, PathBuf),
+}
+
+impl AnyNvm {
+    /// `capacity`バイトのメモリ上のNVM.
+    pub fn memory(capacity: u64) -> AnyNvm {
+        AnyNvm::Memory(SharedMemoryNvm::new(vec![0; capacity as usize]))
+    }
+
+    /// `path`のファイルを開き、存在しなければ`capacity`バイトで作る.
+    pub fn file<P: AsRef<Path>>(path: P, capacity: u64) -> io::Result<AnyNvm> {
+        let path = path.as_ref().to_path_buf();
+        Ok(AnyNvm::File(open_file_nvm(&path, capacity)?, path))
+    }
+
+    /// `path`に`capacity`バイトのファイルを作る. 既にあるファイルは作り直す.
+    pub fn create_file<P: AsRef<Path>>(path: P, capacity: u64) -> io::Result<AnyNvm> {
+        let path = path.as_ref().to_path_buf();
+        Ok(AnyNvm::File(create_file_nvm(&path, capacity)?, path))
+    }
+
+    /// このNVMをもう一度開くためのハンドル. `split`する前に作る.
+    pub fn reopener(&self) -> Reopener {
+        match self {
+            AnyNvm::Memory(nvm) => Reopener::Memory(nvm.clone()),
+            AnyNvm::File(_, path) => Reopener::File(path.clone()),
+        }
+    }
+}
+
+/// 同じNVMをもう一度開くためのハンドル.
+#[derive(Debug, Clone)]
+pub enum Reopener {
+    Memory(SharedMemoryNvm),
+    File(PathBuf),
+}
+
+impl Reopener {
+    /// NVM全体を開き直す.
+    ///
+    /// ファイルには排他ロックを取るので、先に閉じる前のストレージを捨てておくこと.
+    pub fn reopen(&self) -> io::Result<AnyNvm> {
+        match self {
+            Reopener::Memory(nvm) => Ok(AnyNvm::Memory(nvm.clone())),
+            Reopener::File(path) => {
+                let nvm = FileNvmBuilder::new().open(path).map_err(io::Error::other)?;
+                Ok(AnyNvm::File(nvm, path.clone()))
+            }
+        }
+    }
+}
+
+pub(crate) fn open_file_nvm<P: AsRef<Path>>(path: P, capacity: u64) -> io::Result<FileNvm> {
+    let (filenvm, created) = FileNvm::create_if_absent(path, capacity).map_err(io::Error::other)?;
+
+    if created {
+        println!("[Notice:] we CREATE a new file by the given name");
+    } else {
+        println!("[Notice:] we OPEN the file");
+    }
+    Ok(filenvm)
+}
+
+// `create_if_absent`は空でないファイルの容量をヘッダから読むので、通常のファイルは消してから作る.
+// 中身を切り詰めずに消すのは、別のプロセスがロックして使っているファイルを壊さないため.
+// ブロックデバイスは大きさが0に見えるので、そのまま`capacity`で使う
+pub(crate) fn create_file_nvm<P: AsRef<Path>>(path: P, capacity: u64) -> io::Result<FileNvm> {
+    let path = path.as_ref();
+    if path.is_file() {
+        std::fs::remove_file(path)?;
+    }
+    open_file_nvm(path, capacity)
+}
+
+/// `path`に作られているストレージのヘッダ.
+///
+/// ファイルがないか空であれば`None`. それ以外でヘッダを読めなければエラーにする.
+pub fn storage_header<P: AsRef<Path>>(path: P) -> io::Result<Option<StorageHeader>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // ブロックデバイスでは`metadata`が0を返すので、末尾までシークして大きさを求める
+    if file.seek(SeekFrom::End(0))? == 0 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    StorageHeader::read_from(file).map(Some).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not a cannyls storage ({:?})", e.kind()),
+        )
+    })
+}
+
+/// `path`がブロックデバイスかどうか.
+#[cfg(unix)]
+pub fn is_block_device<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+    Ok(std::fs::metadata(path)?.file_type().is_block_device())
+}
+
+#[cfg(not(unix))]
+pub fn is_block_device<P: AsRef<Path>>(_path: P) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// `path`のサイズ. ブロックデバイスでは`metadata`が0を返すので、末尾までシークして求める.
+pub fn detect_size<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    File::open(path)?.seek(SeekFrom::End(0))
+}
+
+impl NonVolatileMemory for AnyNvm {
+    fn sync(&mut self) -> cannyls::Result<()> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.sync(),
+            AnyNvm::File(nvm, _) => nvm.sync(),
+        }
+    }
+
+    fn position(&self) -> u64 {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.position(),
+            AnyNvm::File(nvm, _) => nvm.position(),
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.capacity(),
+            AnyNvm::File(nvm, _) => nvm.capacity(),
+        }
+    }
+
+    fn block_size(&self) -> BlockSize {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.block_size(),
+            AnyNvm::File(nvm, _) => nvm.block_size(),
+        }
+    }
+
+    fn split(self, position: u64) -> cannyls::Result<(Self, Self)> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm
+                .split(position)
+                .map(|(l, r)| (AnyNvm::Memory(l), AnyNvm::Memory(r))),
+            AnyNvm::File(nvm, path) => nvm
+                .split(position)
+                .map(|(l, r)| (AnyNvm::File(l, path.clone()), AnyNvm::File(r, path))),
+        }
+    }
+}
+
+impl Read for AnyNvm {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.read(buf),
+            AnyNvm::File(nvm, _) => nvm.read(buf),
+        }
+    }
+}
+
+impl Write for AnyNvm {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.write(buf),
+            AnyNvm::File(nvm, _) => nvm.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.flush(),
+            AnyNvm::File(nvm, _) => nvm.flush(),
+        }
+    }
+}
+
+impl Seek for AnyNvm {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            AnyNvm::Memory(nvm) => nvm.seek(pos),
+            AnyNvm::File(nvm, _) => nvm.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use crate::parse::parse_workload_str;
+    use crate::report::Metadata;
+    use crate::run_commands::{
+        do_commands, do_commands_paced, make_storage, open_storage, statistics, Pacer,
+    };
+    use crate::verifier::verify_commands;
+    use crate::StorageOptions;
+
+    #[test]
+    fn nvm_kind_from_str_works() {
+        assert_eq!("memory".parse(), Ok(NvmKind::Memory));
+        assert_eq!("file".parse(), Ok(NvmKind::File));
+        assert_eq!(
+            "/dev/nvme0n1".parse(),
+            Ok(NvmKind::Device(PathBuf::from("/dev/nvme0n1")))
+        );
+        assert!("".parse::<NvmKind>().is_err());
+    }
+
+    #[test]
+    fn runners_work_on_memory_nvm() {
+        let w = parse_workload_str("test", "Ordered[100] { <60%> New(4K); <40%> Get; }").unwrap();
+        let mut storage = make_storage(AnyNvm::memory(4 * 1024 * 1024), &StorageOptions::default());
+        do_commands(&mut storage, Generator::new(&w));
+
+        let storage = make_storage(AnyNvm::memory(4 * 1024 * 1024), &StorageOptions::default());
+        verify_commands(storage, Generator::new(&w), None);
+    }
+
+    #[test]
+    fn reopen_continues_on_the_same_nvm() {
+        let w = parse_workload_str(
+            "test",
+            "Ordered[100] { <60%> New(4K); <20%> Embed(128); <20%> Get; }
+             Command { Reopen; }
+             Ordered[100] { <50%> Get; <50%> Delete; }",
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("nvm-reopen-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for nvm in [
+            AnyNvm::memory(4 * 1024 * 1024),
+            AnyNvm::file(&path, 4 * 1024 * 1024).unwrap(),
+        ] {
+            let reopener = nvm.reopener();
+            let options = StorageOptions {
+                sync_interval: Some(64),
+                ..Default::default()
+            };
+            let mut reopen = || open_storage(reopener.reopen().unwrap(), &options);
+            let storage = make_storage(nvm, &options);
+            let summary = do_commands_paced(
+                storage,
+                Generator::new(&w),
+                Pacer::new(None),
+                None,
+                Some(&mut reopen),
+                None,
+            );
+            let report = statistics(&summary, Metadata::default());
+            assert_eq!(report.opens.len(), 1);
+            assert_eq!(report.opens[0].section, 1);
+            assert_eq!(report.opens[0].lumps, 80);
+            assert!(report.opens[0].journal_entries >= 80);
+
+            let storage = make_storage(reopener.reopen().unwrap(), &options);
+            // 開いている間はファイルのロックを持ち続ける
+            if let Reopener::File(_) = reopener {
+                assert!(reopener.reopen().is_err());
+            }
+            verify_commands(storage, Generator::new(&w), Some(&mut reopen));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn storage_header_distinguishes_missing_storages() {
+        let path = std::env::temp_dir().join(format!("nvm-header-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(storage_header(&path).unwrap().is_none());
+
+        std::fs::write(&path, b"").unwrap();
+        assert!(storage_header(&path).unwrap().is_none());
+
+        std::fs::write(&path, vec![0xff; 4096]).unwrap();
+        assert!(storage_header(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        make_storage(
+            AnyNvm::file(&path, 1024 * 1024).unwrap(),
+            &StorageOptions::default(),
+        );
+        let header = storage_header(&path).unwrap().unwrap();
+        assert_eq!(header.storage_size(), 1024 * 1024);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn create_file_recreates_the_file() {
+        let path = std::env::temp_dir().join(format!("nvm-create-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        make_storage(
+            AnyNvm::create_file(&path, 1024 * 1024).unwrap(),
+            &StorageOptions::default(),
+        );
+
+        // 既存のストレージの容量は使わない
+        let storage = make_storage(
+            AnyNvm::create_file(&path, 8 * 1024 * 1024).unwrap(),
+            &StorageOptions::default(),
+        );
+        assert_eq!(storage.header().storage_size(), 8 * 1024 * 1024);
+        drop(storage);
+        assert_eq!(
+            storage_header(&path).unwrap().unwrap().storage_size(),
+            8 * 1024 * 1024
+        );
+
+        // ストレージでないファイルも作り直す
+        std::fs::write(&path, vec![0xff; 4096]).unwrap();
+        assert!(AnyNvm::file(&path, 1024 * 1024).is_err());
+        make_storage(
+            AnyNvm::create_file(&path, 1024 * 1024).unwrap(),
+            &StorageOptions::default(),
+        );
+        assert_eq!(
+            storage_header(&path).unwrap().unwrap().storage_size(),
+            1024 * 1024
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn detect_size_works() {
+        let path = std::env::temp_dir().join(format!("nvm-size-{}", std::process::id()));
+        std::fs::write(&path, vec![0; 4096]).unwrap();
+        assert_eq!(detect_size(&path).unwrap(), 4096);
+        assert!(!is_block_device(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,10 +1,149 @@
 use combine::combinator::{attempt, skip_until};
-use combine::error::ParseError;
+use combine::easy;
+use combine::error::{ParseError, StreamError};
 use combine::parser::char::{digit, spaces, string};
-use combine::{eof, many1, one_of, optional, parser, sep_by, sep_end_by, token, Parser, Stream};
+use combine::stream::state::{SourcePosition, State};
+use combine::stream::StreamErrorFor;
+use combine::{
+    between, choice, eof, many, many1, none_of, one_of, optional, parser, sep_by, sep_end_by,
+    token, Parser, Stream,
+};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use super::*;
 
+/// ワークロードの構文エラー.
+///
+/// 行番号と列番号はいずれも1始まり.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub unexpected: Option<String>,
+    pub expected: Vec<String>,
+    pub messages: Vec<String>,
+    pub snippet: String,
+}
+
+impl SyntaxError {
+    fn new(file: &str, src: &str, errors: easy::Errors<char, &str, SourcePosition>) -> SyntaxError {
+        let line = errors.position.line.max(1) as usize;
+        let column = errors.position.column.max(1) as usize;
+
+        let mut unexpected = None;
+        let mut expected = Vec::new();
+        let mut messages = Vec::new();
+        for error in errors.errors {
+            match error {
+                easy::Error::Unexpected(info) => unexpected = Some(describe(&info)),
+                easy::Error::Expected(info) => {
+                    let info = describe(&info);
+                    // 空白はどこにでも書けるので候補として列挙しても役に立たない
+                    if info.starts_with("whitespace") {
+                        continue;
+                    }
+                    if !expected.contains(&info) {
+                        expected.push(info);
+                    }
+                }
+                easy::Error::Message(info) => messages.push(info.to_string()),
+                easy::Error::Other(e) => messages.push(e.to_string()),
+            }
+        }
+
+        SyntaxError {
+            file: file.to_owned(),
+            line,
+            column,
+            unexpected,
+            expected,
+            messages,
+            snippet: src.lines().nth(line - 1).unwrap_or("").to_owned(),
+        }
+    }
+}
+
+fn describe(info: &easy::Info<char, &str>) -> String {
+    match info {
+        easy::Info::Token('\n') => "newline".to_owned(),
+        easy::Info::Token(c) => format!("`{}`", c),
+        easy::Info::Range(s) => format!("`{}`", s),
+        easy::Info::Owned(s) => s.clone(),
+        easy::Info::Borrowed(s) if s.contains(' ') || s.starts_with("whitespace") => {
+            (*s).to_owned()
+        }
+        easy::Info::Borrowed(s) => format!("`{}`", s),
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: syntax error",
+            self.file, self.line, self.column
+        )?;
+        if let Some(unexpected) = &self.unexpected {
+            write!(f, ": unexpected {}", unexpected)?;
+        }
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", self.expected.join(" or "))?;
+        }
+        for message in &self.messages {
+            write!(f, " ({})", message)?;
+        }
+        writeln!(f)?;
+
+        let lineno = self.line.to_string();
+        let pad = " ".repeat(lineno.len());
+        writeln!(f, "{} |", pad)?;
+        writeln!(f, "{} | {}", lineno, self.snippet)?;
+        write!(f, "{} | {}^", pad, " ".repeat(self.column - 1))
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+#[derive(Debug)]
+pub enum WorkloadFileError {
+    Io(PathBuf, std::io::Error),
+    Syntax(Box<SyntaxError>),
+}
+
+impl fmt::Display for WorkloadFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkloadFileError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            WorkloadFileError::Syntax(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WorkloadFileError {}
+
+/// `src`全体をワークロードとして解析する.
+///
+/// 最後のセクションの後ろに余計な文字列が残っている場合もエラーとなる.
+/// `file`はエラーメッセージ中でのみ使われる.
+pub fn parse_workload_str(file: &str, src: &str) -> Result<Workload, Box<SyntaxError>> {
+    let input = State::with_positioner(src, SourcePosition::new());
+    parse_workload()
+        .skip(eof())
+        .easy_parse(input)
+        .map(|(workload, _)| workload)
+        .map_err(|errors| Box::new(SyntaxError::new(file, src, errors)))
+}
+
+pub fn parse_workload_file<P: AsRef<Path>>(path: P) -> Result<Workload, WorkloadFileError> {
+    let path = path.as_ref();
+    let src =
+        std::fs::read_to_string(path).map_err(|e| WorkloadFileError::Io(path.to_owned(), e))?;
+    parse_workload_str(&path.display().to_string(), &src).map_err(WorkloadFileError::Syntax)
+}
+
 pub fn parse_line_comment<I>() -> impl Parser<Input = I, Output = ()>
 where
     I: Stream<Item = char>,
@@ -27,18 +166,107 @@ where
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
     (
-        spaces().with(attempt(optional(parse_seed()))),
+        spaces().with(optional(attempt(parse_seed()))),
+        spaces_with_comments().with(optional(parse_storage())),
         many1(parse_section()),
     )
-        .map(|(seed, sections)| Workload { seed, sections })
+        .map(|(seed, storage, sections)| Workload {
+            seed,
+            storage: storage.unwrap_or_default(),
+            sections,
+        })
+}
+
+enum StorageItem {
+    JournalRatio(f64),
+    SyncInterval(usize),
+    GcQueueSize(usize),
+    BlockSize(Bytes),
+}
+
+// `journal_ratio:`のような項目名
+fn storage_key<I>(key: &'static str) -> impl Parser<Input = I, Output = ()>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    string(key)
+        .skip(spaces())
+        .skip(token(':'))
+        .skip(spaces())
+        .map(|_| ())
+}
+
+/*
+  Storage {
+    journal_ratio: 0.05;
+    sync_interval: 64;
+    gc_queue_size: 256;
+    block_size: 4K;
+  }
+*/
+pub fn parse_storage<I>() -> impl Parser<Input = I, Output = StorageOptions>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    let item = choice((
+        storage_key("journal_ratio")
+            .with(parse_float())
+            .map(StorageItem::JournalRatio),
+        storage_key("sync_interval")
+            .with(parse_num())
+            .map(StorageItem::SyncInterval),
+        storage_key("gc_queue_size")
+            .with(parse_num())
+            .map(StorageItem::GcQueueSize),
+        storage_key("block_size")
+            .with(parse_bytes())
+            .map(StorageItem::BlockSize),
+    ))
+    .skip(spaces())
+    .skip(token(';'));
+    (
+        attempt(string("Storage")).skip(spaces()).skip(token('{')),
+        spaces_with_comments(),
+        sep_end_by(item, spaces_with_comments()),
+        token('}'),
+    )
+        .map(|(_, _, items, _): (_, _, Vec<StorageItem>, _)| {
+            let mut options = StorageOptions::default();
+            for item in items {
+                match item {
+                    StorageItem::JournalRatio(x) => options.journal_ratio = Some(x),
+                    StorageItem::SyncInterval(x) => options.sync_interval = Some(x),
+                    StorageItem::GcQueueSize(x) => options.gc_queue_size = Some(x),
+                    StorageItem::BlockSize(x) => options.block_size = Some(x),
+                }
+            }
+            options
+        })
 }
 
-fn parse_iter<I>() -> impl Parser<Input = I, Output = usize>
+// `[10000]`のような回数か、`[for 600s]`のような時間(単位は`s`・`m`・`h`)
+fn parse_repeat<I>() -> impl Parser<Input = I, Output = Repeat>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (token('['), parse_num(), token(']')).map(|(_, iter, _)| iter)
+    let unit = choice((
+        token('s').map(|_| 1),
+        token('m').map(|_| 60),
+        token('h').map(|_| 60 * 60),
+    ));
+    let duration = (string("for").skip(spaces()), parse_num::<u64, _>(), unit)
+        .map(|(_, n, unit)| Repeat::For(Duration::from_secs(n.saturating_mul(unit))));
+    let times = parse_num().map(Repeat::Times);
+    (
+        token('['),
+        spaces().with(duration.or(times)),
+        spaces(),
+        token(']'),
+    )
+        .map(|(_, repeat, _, _)| repeat)
 }
 
 pub fn spaces_with_comments<I>() -> impl Parser<Input = I, Output = ()>
@@ -49,6 +277,43 @@ where
     sep_by(spaces(), parse_line_comment())
 }
 
+// `"warmup"`のような省略可能なセクションのラベル
+fn parse_label<I>() -> impl Parser<Input = I, Output = Option<String>>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    optional(attempt(spaces().with(between(
+        token('"'),
+        token('"'),
+        many(none_of("\"\n".chars())),
+    ))))
+}
+
+// `@ 2000/s`のような省略可能な目標スループット
+fn parse_rate<I>() -> impl Parser<Input = I, Output = Option<u64>>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    optional(attempt(
+        spaces()
+            .with(token('@'))
+            .skip(spaces())
+            .with(parse_num())
+            .skip(string("/s")),
+    ))
+}
+
+// `SideJob/100`のような、補助タスクを挟む間隔(操作数)
+fn parse_side_job_every<I>() -> impl Parser<Input = I, Output = Option<usize>>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    optional(attempt(spaces().with(string("SideJob/")).with(parse_num())))
+}
+
 fn parse_section<I>() -> impl Parser<Input = I, Output = Section>
 where
     I: Stream<Item = char>,
@@ -89,15 +354,24 @@ where
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
     (
-        spaces()
-            .with(string("Command"))
+        (
+            spaces().with(string("Command")),
+            parse_label(),
+            parse_rate(),
+            parse_side_job_every(),
+        )
             .skip(spaces())
             .skip(token('{'))
             .skip(spaces()),
         sep_end_by(parse_command().skip(token(';')), spaces()),
         token('}').skip(spaces()),
     )
-        .map(|(_, commands, _)| Section::Commands(commands))
+        .map(|((_, label, rate, side_job_every), commands, _)| Section {
+            label,
+            rate,
+            side_job_every,
+            body: SectionBody::Commands(commands),
+        })
 }
 
 fn parse_ordered<I>() -> impl Parser<Input = I, Output = Section>
@@ -107,12 +381,22 @@ where
 {
     (
         spaces().with(string("Ordered")),
-        parse_iter(),
+        parse_repeat(),
+        parse_label(),
+        parse_rate(),
+        parse_side_job_every(),
         spaces().with(token('{')).skip(spaces()),
         sep_end_by(parse_freq_statement(), spaces()),
         token('}').skip(spaces()),
     )
-        .map(|(_, iter, _, commands, _)| Section::Ordered(iter, commands))
+        .map(
+            |(_, repeat, label, rate, side_job_every, _, commands, _)| Section {
+                label,
+                rate,
+                side_job_every,
+                body: SectionBody::Ordered(repeat, commands),
+            },
+        )
 }
 
 fn parse_unordered<I>() -> impl Parser<Input = I, Output = Section>
@@ -122,46 +406,48 @@ where
 {
     (
         spaces().with(string("Unordered")),
-        parse_iter(),
+        parse_repeat(),
+        parse_label(),
+        parse_rate(),
+        parse_side_job_every(),
         spaces().with(token('{')).skip(spaces()),
         sep_end_by(parse_freq_statement(), spaces()),
         token('}').skip(spaces()),
     )
-        .map(|(_, iter, _, commands, _)| Section::Unordered(iter, commands))
+        .map(
+            |(_, repeat, label, rate, side_job_every, _, commands, _)| Section {
+                label,
+                rate,
+                side_job_every,
+                body: SectionBody::Unordered(repeat, commands),
+            },
+        )
 }
 
 fn parse_freq_statement<I>() -> impl Parser<Input = I, Output = (Freq, Statement)>
-where
-    I: Stream<Item = char>,
-    I::Error: ParseError<I::Item, I::Range, I::Position>,
-{
-    attempt(parse_freq_command().map(|(freq, command)| (freq, Statement(vec![command]))))
-        .or(parse_freq_statement_with_braces())
-}
-
-fn parse_freq_statement_with_braces<I>() -> impl Parser<Input = I, Output = (Freq, Statement)>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
     (
         spaces().with(parse_freq()).skip(spaces()),
-        spaces().with(token('{')).skip(spaces()),
-        sep_end_by(parse_command().skip(token(';')), spaces()),
-        token('}').skip(spaces()).skip(token(';')),
+        parse_braced_statement().or(parse_command()
+            .skip(token(';'))
+            .map(|command| Statement(vec![command]))),
     )
-        .map(|(freq, _, commands, _)| (freq, Statement(commands)))
 }
 
-fn parse_freq_command<I>() -> impl Parser<Input = I, Output = (Freq, Command)>
+fn parse_braced_statement<I>() -> impl Parser<Input = I, Output = Statement>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
     (
-        spaces().with(parse_freq()).skip(spaces()),
-        parse_command().skip(token(';')),
+        token('{').skip(spaces()),
+        sep_end_by(parse_command().skip(token(';')), spaces()),
+        token('}').skip(spaces()).skip(token(';')),
     )
+        .map(|(_, commands, _)| Statement(commands))
 }
 
 fn parse_command_<I>() -> impl Parser<Input = I, Output = Command>
@@ -172,11 +458,16 @@ where
     newput()
         .or(overwrite())
         .or(embed())
-        .or(attempt(get_with_perc()))
+        .or(attempt(get_with_selector()))
         .or(random_get())
-        .or(attempt(delete_with_perc()))
+        .or(attempt(delete_with_selector()))
         .or(attempt(delete_range()))
         .or(random_delete())
+        .or(reopen())
+        .or(attempt(side_job()))
+        .or(sync())
+        .or(journal_gc())
+        .or(newput_sync())
         .or(attempt(parse_times()))
 }
 parser! {
@@ -198,11 +489,14 @@ where
 fn parse_num<U, I>() -> impl Parser<Input = I, Output = U>
 where
     U: std::str::FromStr,
-    U::Err: std::fmt::Debug,
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    many1(digit()).map(|string: String| string.parse::<U>().unwrap())
+    many1(digit()).and_then(|string: String| {
+        string
+            .parse::<U>()
+            .map_err(|_| StreamErrorFor::<I>::message_static_message("number out of range"))
+    })
 }
 
 pub fn parse_bytes_with_suffix<I>() -> impl Parser<Input = I, Output = Bytes>
@@ -210,14 +504,15 @@ where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (many1(digit()), one_of("KMG".chars())).map(|(string, suffix): (String, char)| {
-        let num = string.parse::<usize>().unwrap();
-        match suffix {
-            'K' => num * 1024,
-            'M' => num * 1024 * 1024,
-            'G' => num * 1024 * 1024 * 1024,
+    (parse_num(), one_of("KMG".chars())).and_then(|(num, suffix): (usize, char)| {
+        let unit = match suffix {
+            'K' => 1024,
+            'M' => 1024 * 1024,
+            'G' => 1024 * 1024 * 1024,
             _ => unreachable!("bug"),
-        }
+        };
+        num.checked_mul(unit)
+            .ok_or_else(|| StreamErrorFor::<I>::message_static_message("number out of range"))
     })
 }
 
@@ -229,13 +524,84 @@ where
     attempt(parse_bytes_with_suffix()).or(parse_num())
 }
 
+// (bytes1, bytes2)
+fn parse_bytes_pair<I>() -> impl Parser<Input = I, Output = (Bytes, Bytes)>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    (
+        token('(').skip(spaces()),
+        parse_bytes(),
+        spaces().and(token(',')).and(spaces()),
+        parse_bytes(),
+        spaces().with(token(')')),
+    )
+        .map(|(_, b1, _, b2, _)| (b1, b2))
+}
+
+/*
+  42K
+  uniform(4K, 1M)
+  normal(512K, 64K)
+  lognormal(512K, 64K)
+  choice{4K:70, 1M:30}
+*/
+fn parse_size_dist<I>() -> impl Parser<Input = I, Output = SizeDist>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    let uniform = string("uniform")
+        .with(parse_bytes_pair())
+        .map(|(min, max)| SizeDist::Uniform(min, max));
+    let normal = string("normal")
+        .with(parse_bytes_pair())
+        .map(|(mean, stddev)| SizeDist::Normal(mean, stddev));
+    let lognormal = string("lognormal")
+        .with(parse_bytes_pair())
+        .map(|(mean, stddev)| SizeDist::LogNormal(mean, stddev));
+    let choice = (
+        string("choice")
+            .skip(spaces())
+            .skip(token('{'))
+            .skip(spaces()),
+        sep_by(
+            (
+                parse_bytes().skip(spaces()).skip(token(':')).skip(spaces()),
+                parse_num(),
+            )
+                .skip(spaces()),
+            token(',').skip(spaces()),
+        ),
+        token('}'),
+    )
+        .map(|(_, choices, _)| SizeDist::Choice(choices));
+
+    uniform
+        .or(normal)
+        .or(lognormal)
+        .or(choice)
+        .or(parse_bytes().map(SizeDist::Fixed))
+}
+
 fn newput<I>() -> impl Parser<Input = I, Output = Command>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (string("New"), token('('), parse_bytes(), token(')'))
-        .map(|(_, _, num, _)| Command::NewPut(num))
+    (string("New"), token('('), parse_size_dist(), token(')'))
+        .map(|(_, _, dist, _)| Command::NewPut(dist))
+}
+
+// `Put!(1M)`: Putの後にSyncする
+fn newput_sync<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    (string("Put!"), token('('), parse_size_dist(), token(')'))
+        .map(|(_, _, dist, _)| Command::NewPutSync(dist))
 }
 
 fn overwrite<I>() -> impl Parser<Input = I, Output = Command>
@@ -243,8 +609,21 @@ where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (string("OverWrite"), token('('), parse_bytes(), token(')'))
-        .map(|(_, _, num, _)| Command::Overwrite(num))
+    (
+        string("OverWrite"),
+        token('('),
+        parse_size_dist(),
+        optional(
+            (
+                attempt(spaces().with(token(','))),
+                spaces(),
+                parse_selector(),
+            )
+                .map(|(_, _, selector)| selector),
+        ),
+        token(')'),
+    )
+        .map(|(_, _, dist, selector, _)| Command::Overwrite(dist, selector))
 }
 
 fn embed<I>() -> impl Parser<Input = I, Output = Command>
@@ -252,8 +631,8 @@ where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (string("Embed"), token('('), parse_bytes(), token(')'))
-        .map(|(_, _, num, _)| Command::Embed(num))
+    (string("Embed"), token('('), parse_size_dist(), token(')'))
+        .map(|(_, _, dist, _)| Command::Embed(dist))
 }
 
 fn random_get<I>() -> impl Parser<Input = I, Output = Command>
@@ -272,36 +651,110 @@ where
     string("Delete").map(|_| Command::RandomDelete)
 }
 
-fn get_with_perc<I>() -> impl Parser<Input = I, Output = Command>
+fn reopen<I>() -> impl Parser<Input = I, Output = Command>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (
-        string("Get"),
-        token('('),
-        parse_num(),
-        spaces().and(token(',')).and(spaces()),
-        parse_num(),
-        token(')'),
+    string("Reopen").map(|_| Command::Reopen)
+}
+
+fn sync<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    string("Sync").map(|_| Command::Sync)
+}
+
+fn journal_gc<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    string("JournalGc").map(|_| Command::JournalGc)
+}
+
+// SideJob(n)
+fn side_job<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    let count = parse_num().and_then(|n: usize| {
+        if n > MAX_SIDE_JOBS {
+            Err(StreamErrorFor::<I>::message_static_message(
+                "too many side jobs",
+            ))
+        } else {
+            Ok(n)
+        }
+    });
+    (string("SideJob"), token('('), count, token(')')).map(|(_, _, n, _)| Command::SideJob(n))
+}
+
+fn parse_float<I>() -> impl Parser<Input = I, Output = f64>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    (many1(digit()), optional(token('.').with(many1(digit())))).and_then(
+        |(int, frac): (String, Option<String>)| {
+            let s = match frac {
+                Some(frac) => format!("{}.{}", int, frac),
+                None => int,
+            };
+            s.parse::<f64>()
+                .map_err(|_| StreamErrorFor::<I>::message_static_message("invalid number"))
+        },
     )
-        .map(|(_, _, num1, _, num2, _)| Command::Get(num1, num2))
 }
 
-fn delete_with_perc<I>() -> impl Parser<Input = I, Output = Command>
+/*
+  10, 20
+  zipf 0.99
+  hotspot 10% 90%
+  latest
+*/
+fn parse_selector<I>() -> impl Parser<Input = I, Output = Selector>
 where
     I: Stream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (
-        string("Delete"),
-        token('('),
+    let zipf = (string("zipf"), spaces(), parse_float()).map(|(_, _, s)| Selector::Zipf(s));
+    let hotspot = (
+        string("hotspot").skip(spaces()),
+        parse_num().skip(token('%')).skip(spaces()),
+        parse_num().skip(token('%')),
+    )
+        .map(|(_, hot, access)| Selector::Hotspot(hot, access));
+    let latest = string("latest").map(|_| Selector::Latest);
+    let range = (
         parse_num(),
         spaces().and(token(',')).and(spaces()),
         parse_num(),
-        token(')'),
     )
-        .map(|(_, _, num1, _, num2, _)| Command::Delete(num1, num2))
+        .map(|(left, _, right)| Selector::Range(left, right));
+
+    zipf.or(hotspot).or(latest).or(range)
+}
+
+fn get_with_selector<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    (string("Get"), token('('), parse_selector(), token(')'))
+        .map(|(_, _, selector, _)| Command::Get(selector))
+}
+
+fn delete_with_selector<I>() -> impl Parser<Input = I, Output = Command>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    (string("Delete"), token('('), parse_selector(), token(')'))
+        .map(|(_, _, selector, _)| Command::Delete(selector))
 }
 
 fn delete_range<I>() -> impl Parser<Input = I, Output = Command>
@@ -326,27 +779,65 @@ mod tests {
 
     #[test]
     fn newput_works() {
-        assert_eq!(newput().parse("New(12)"), Ok((Command::NewPut(12), "")));
+        assert_eq!(
+            newput().parse("New(12)"),
+            Ok((Command::NewPut(SizeDist::Fixed(12)), ""))
+        );
         assert_eq!(
             newput().parse("New(12K)"),
-            Ok((Command::NewPut(12 * 1024), ""))
+            Ok((Command::NewPut(SizeDist::Fixed(12 * 1024)), ""))
         );
         assert_eq!(
             newput().parse("New(12M)"),
-            Ok((Command::NewPut(12 * 1024 * 1024), ""))
+            Ok((Command::NewPut(SizeDist::Fixed(12 * 1024 * 1024)), ""))
         );
     }
 
     #[test]
     fn embed_works() {
-        assert_eq!(embed().parse("Embed(12)"), Ok((Command::Embed(12), "")));
+        assert_eq!(
+            embed().parse("Embed(12)"),
+            Ok((Command::Embed(SizeDist::Fixed(12)), ""))
+        );
         assert_eq!(
             embed().parse("Embed(12K)"),
-            Ok((Command::Embed(12 * 1024), ""))
+            Ok((Command::Embed(SizeDist::Fixed(12 * 1024)), ""))
         );
         assert_eq!(
             embed().parse("Embed(12M)"),
-            Ok((Command::Embed(12 * 1024 * 1024), ""))
+            Ok((Command::Embed(SizeDist::Fixed(12 * 1024 * 1024)), ""))
+        );
+    }
+
+    #[test]
+    fn parse_size_dist_works() {
+        assert_eq!(
+            newput().parse("New(uniform(4K, 1M))"),
+            Ok((
+                Command::NewPut(SizeDist::Uniform(4 * 1024, 1024 * 1024)),
+                ""
+            ))
+        );
+        assert_eq!(
+            overwrite().parse("OverWrite(normal(512K, 64K))"),
+            Ok((
+                Command::Overwrite(SizeDist::Normal(512 * 1024, 64 * 1024), None),
+                ""
+            ))
+        );
+        assert_eq!(
+            newput().parse("New(lognormal(512K,64K))"),
+            Ok((
+                Command::NewPut(SizeDist::LogNormal(512 * 1024, 64 * 1024)),
+                ""
+            ))
+        );
+        assert_eq!(
+            embed().parse("Embed(choice{4K:70, 100: 30})"),
+            Ok((
+                Command::Embed(SizeDist::Choice(vec![(4 * 1024, 70), (100, 30)])),
+                ""
+            ))
         );
     }
 
@@ -354,15 +845,18 @@ mod tests {
     fn overwrite_works() {
         assert_eq!(
             overwrite().parse("OverWrite(42)"),
-            Ok((Command::Overwrite(42), ""))
+            Ok((Command::Overwrite(SizeDist::Fixed(42), None), ""))
         );
         assert_eq!(
             overwrite().parse("OverWrite(42K)"),
-            Ok((Command::Overwrite(42 * 1024), ""))
+            Ok((Command::Overwrite(SizeDist::Fixed(42 * 1024), None), ""))
         );
         assert_eq!(
             overwrite().parse("OverWrite(42M)"),
-            Ok((Command::Overwrite(42 * 1024 * 1024), ""))
+            Ok((
+                Command::Overwrite(SizeDist::Fixed(42 * 1024 * 1024), None),
+                ""
+            ))
         );
     }
 
@@ -372,10 +866,48 @@ mod tests {
     }
 
     #[test]
-    fn get_with_perc_works() {
+    fn get_with_selector_works() {
+        assert_eq!(
+            get_with_selector().parse("Get(12, 42)"),
+            Ok((Command::Get(Selector::Range(12, 42)), ""))
+        );
+        assert_eq!(
+            get_with_selector().parse("Get(zipf 0.99)"),
+            Ok((Command::Get(Selector::Zipf(0.99)), ""))
+        );
+        assert_eq!(
+            get_with_selector().parse("Get(hotspot 10% 90%)"),
+            Ok((Command::Get(Selector::Hotspot(10, 90)), ""))
+        );
+        assert_eq!(
+            get_with_selector().parse("Get(latest)"),
+            Ok((Command::Get(Selector::Latest), ""))
+        );
+    }
+
+    #[test]
+    fn delete_with_selector_works() {
         assert_eq!(
-            get_with_perc().parse("Get(12, 42)"),
-            Ok((Command::Get(12, 42), ""))
+            delete_with_selector().parse("Delete(zipf 1)"),
+            Ok((Command::Delete(Selector::Zipf(1.0)), ""))
+        );
+    }
+
+    #[test]
+    fn overwrite_with_selector_works() {
+        assert_eq!(
+            overwrite().parse("OverWrite(1K, zipf 0.99)"),
+            Ok((
+                Command::Overwrite(SizeDist::Fixed(1024), Some(Selector::Zipf(0.99))),
+                ""
+            ))
+        );
+        assert_eq!(
+            overwrite().parse("OverWrite(uniform(1K, 2K), latest)"),
+            Ok((
+                Command::Overwrite(SizeDist::Uniform(1024, 2048), Some(Selector::Latest)),
+                ""
+            ))
         );
     }
 
@@ -384,6 +916,39 @@ mod tests {
         assert_eq!(parse_freq().parse("<42%>"), Ok((42, "")));
     }
 
+    #[test]
+    fn reopen_works() {
+        assert_eq!(reopen().parse("Reopen"), Ok((Command::Reopen, "")));
+    }
+
+    #[test]
+    fn sync_works() {
+        assert_eq!(sync().parse("Sync"), Ok((Command::Sync, "")));
+        assert_eq!(
+            newput_sync().parse("Put!(1M)"),
+            Ok((Command::NewPutSync(SizeDist::Fixed(1024 * 1024)), ""))
+        );
+        assert_eq!(
+            newput_sync().parse("Put!(uniform(1K, 4K))"),
+            Ok((Command::NewPutSync(SizeDist::Uniform(1024, 4096)), ""))
+        );
+    }
+
+    #[test]
+    fn maintenance_commands_work() {
+        assert_eq!(
+            parse_command().parse("JournalGc"),
+            Ok((Command::JournalGc, ""))
+        );
+        assert_eq!(
+            parse_command().parse("SideJob(8)"),
+            Ok((Command::SideJob(8), ""))
+        );
+        let too_many = format!("SideJob({})", MAX_SIDE_JOBS + 1);
+        assert!(parse_command().parse(too_many.as_str()).is_err());
+        assert_eq!(parse_command().parse("Sync"), Ok((Command::Sync, "")));
+    }
+
     #[test]
     fn delete_range_works() {
         assert_eq!(
@@ -404,16 +969,16 @@ mod tests {
     fn parse_command_works() {
         assert_eq!(
             parse_command().parse("New(42)"),
-            Ok((Command::NewPut(42), ""))
+            Ok((Command::NewPut(SizeDist::Fixed(42)), ""))
         );
         assert_eq!(
             parse_command().parse("OverWrite(42)"),
-            Ok((Command::Overwrite(42), ""))
+            Ok((Command::Overwrite(SizeDist::Fixed(42), None), ""))
         );
         assert_eq!(parse_command().parse("Get"), Ok((Command::RandomGet, "")));
         assert_eq!(
             parse_command().parse("Get(42, 84)"),
-            Ok((Command::Get(42, 84), ""))
+            Ok((Command::Get(Selector::Range(42, 84)), ""))
         );
         assert_eq!(
             parse_command().parse("Delete"),
@@ -421,43 +986,51 @@ mod tests {
         );
         assert_eq!(
             parse_command().parse("Delete(42, 84)"),
-            Ok((Command::Delete(42, 84), ""))
+            Ok((Command::Delete(Selector::Range(42, 84)), ""))
         );
         assert_eq!(
             parse_command().parse("DeleteRange(42, 84)"),
             Ok((Command::DeleteRange(42, 84), ""))
         );
+        assert_eq!(parse_command().parse("Sync"), Ok((Command::Sync, "")));
+        assert_eq!(
+            parse_command().parse("Put!(42)"),
+            Ok((Command::NewPutSync(SizeDist::Fixed(42)), ""))
+        );
     }
 
     #[test]
-    fn parse_freq_command_works() {
+    fn parse_freq_statement_works() {
         assert_eq!(
-            parse_freq_command().parse("<10%> New(42);"),
-            Ok(((10, Command::NewPut(42)), ""))
+            parse_freq_statement().parse("<10%> New(42);"),
+            Ok(((10, to_stmt(Command::NewPut(SizeDist::Fixed(42)))), ""))
         );
         assert_eq!(
-            parse_freq_command().parse("<11%> OverWrite(42);"),
-            Ok(((11, Command::Overwrite(42)), ""))
+            parse_freq_statement().parse("<11%> OverWrite(42);"),
+            Ok((
+                (11, to_stmt(Command::Overwrite(SizeDist::Fixed(42), None))),
+                ""
+            ))
         );
         assert_eq!(
-            parse_freq_command().parse("<12%> Get;"),
-            Ok(((12, Command::RandomGet), ""))
+            parse_freq_statement().parse("<12%> Get;"),
+            Ok(((12, to_stmt(Command::RandomGet)), ""))
         );
         assert_eq!(
-            parse_freq_command().parse("<13%> Get(42, 84);"),
-            Ok(((13, Command::Get(42, 84)), ""))
+            parse_freq_statement().parse("<13%> Get(42, 84);"),
+            Ok(((13, to_stmt(Command::Get(Selector::Range(42, 84)))), ""))
         );
         assert_eq!(
-            parse_freq_command().parse("<14%> Delete;"),
-            Ok(((14, Command::RandomDelete), ""))
+            parse_freq_statement().parse("<14%> Delete;"),
+            Ok(((14, to_stmt(Command::RandomDelete)), ""))
         );
         assert_eq!(
-            parse_freq_command().parse("<15%> Delete(42, 84);"),
-            Ok(((15, Command::Delete(42, 84)), ""))
+            parse_freq_statement().parse("<15%> Delete(42, 84);"),
+            Ok(((15, to_stmt(Command::Delete(Selector::Range(42, 84)))), ""))
         );
         assert_eq!(
-            parse_freq_command().parse("<16%> DeleteRange(42, 84);"),
-            Ok(((16, Command::DeleteRange(42, 84)), ""))
+            parse_freq_statement().parse("<16%> DeleteRange(42, 84);"),
+            Ok(((16, to_stmt(Command::DeleteRange(42, 84))), ""))
         );
     }
 
@@ -476,20 +1049,91 @@ Ordered[100] {
   <1%> DeleteRange(99, 100);
 }
 "#;
-        let expected = Section::Ordered(
-            100,
+        let expected = Section::from(SectionBody::Ordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
         assert_eq!(parse_ordered().parse(syntax), Ok((expected, "")));
     }
 
+    #[test]
+    fn parse_section_labels_works() {
+        let syntax = r#"Ordered[10] "warm up" { <100%> New(1K); }"#;
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.label, Some("warm up".to_owned()));
+
+        let syntax = r#"Command "load" { New(1K); }"#;
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.label, Some("load".to_owned()));
+
+        let syntax = "Unordered[10] { <100%> New(1K); }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.label, None);
+        assert_eq!(section.rate, None);
+    }
+
+    #[test]
+    fn parse_timed_sections_works() {
+        let syntax = "Ordered[for 600s] { <100%> New(1K); }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert!(section.is_timed());
+        match section.body {
+            SectionBody::Ordered(Repeat::For(d), _) => assert_eq!(d, Duration::from_secs(600)),
+            body => panic!("{:?}", body),
+        }
+
+        let syntax = r#"Unordered[ for 10m ] "soak" @ 100/s { <100%> Get; }"#;
+        let section = parse_section().parse(syntax).unwrap().0;
+        match section.body {
+            SectionBody::Unordered(Repeat::For(d), _) => assert_eq!(d, Duration::from_secs(600)),
+            body => panic!("{:?}", body),
+        }
+        assert_eq!(section.rate, Some(100));
+
+        assert!(parse_section()
+            .parse("Ordered[for 10] { <100%> Get; }")
+            .is_err());
+    }
+
+    #[test]
+    fn parse_section_rates_works() {
+        let syntax = r#"Unordered[10000] "steady" @ 2000/s { <100%> Get; }"#;
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.label, Some("steady".to_owned()));
+        assert_eq!(section.rate, Some(2000));
+
+        let syntax = "Ordered[10] @ 50/s { <100%> New(1K); }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.rate, Some(50));
+
+        let syntax = "Command @1/s { New(1K); }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.rate, Some(1));
+    }
+
+    #[test]
+    fn parse_section_side_jobs_works() {
+        let syntax = r#"Unordered[10000] "steady" @ 2000/s SideJob/100 { <100%> Get; }"#;
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.rate, Some(2000));
+        assert_eq!(section.side_job_every, Some(100));
+
+        let syntax = "Command SideJob/1 { Get; }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.side_job_every, Some(1));
+
+        let syntax = "Ordered[10] { <100%> Get; }";
+        let section = parse_section().parse(syntax).unwrap().0;
+        assert_eq!(section.side_job_every, None);
+    }
+
     #[test]
     fn parse_unordered_works() {
         let syntax = r#"
@@ -501,16 +1145,16 @@ Unordered[100] {
   <1%> DeleteRange(99, 100);
 }
 "#;
-        let expected = Section::Unordered(
-            100,
+        let expected = Section::from(SectionBody::Unordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
         assert_eq!(parse_unordered().parse(syntax), Ok((expected, "")));
     }
@@ -542,30 +1186,31 @@ Unordered[100] {
 }
 "#;
 
-        let expected1 = Section::Ordered(
-            100,
+        let expected1 = Section::from(SectionBody::Ordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
-        let expected2 = Section::Unordered(
-            100,
+        let expected2 = Section::from(SectionBody::Unordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
         let w = Workload {
             seed: None,
+            storage: StorageOptions::default(),
             sections: vec![expected1, expected2],
         };
 
@@ -596,30 +1241,31 @@ Unordered[100] {
 }
 "#;
 
-        let expected1 = Section::Ordered(
-            100,
+        let expected1 = Section::from(SectionBody::Ordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
-        let expected2 = Section::Unordered(
-            100,
+        let expected2 = Section::from(SectionBody::Unordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::Overwrite(43))),
+                (20, to_stmt(Command::Overwrite(SizeDist::Fixed(43), None))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
         let w = Workload {
             seed: Some(42),
+            storage: StorageOptions::default(),
             sections: vec![expected1, expected2],
         };
 
@@ -634,6 +1280,58 @@ Unordered[100] {
         );
     }
 
+    #[test]
+    fn parse_storage_works() {
+        assert_eq!(
+            parse_storage().parse("Storage {}"),
+            Ok((StorageOptions::default(), ""))
+        );
+        assert_eq!(
+            parse_storage().parse(
+                "Storage {
+                   journal_ratio: 0.05;
+                   # comment
+                   sync_interval: 64;
+                   gc_queue_size : 256;
+                   block_size: 4K;
+                 }"
+            ),
+            Ok((
+                StorageOptions {
+                    journal_ratio: Some(0.05),
+                    sync_interval: Some(64),
+                    gc_queue_size: Some(256),
+                    block_size: Some(4096),
+                },
+                ""
+            ))
+        );
+        assert!(parse_storage()
+            .parse("Storage { journal_buffer: 1; }")
+            .is_err());
+    }
+
+    #[test]
+    fn parse_workload_with_storage_works() {
+        let w = parse_workload_str(
+            "test",
+            "Seed: 42;
+             Storage { sync_interval: 64; }
+             Command { Get; }",
+        )
+        .unwrap();
+        assert_eq!(w.seed, Some(42));
+        assert_eq!(w.storage.sync_interval, Some(64));
+        assert_eq!(w.sections.len(), 1);
+
+        let w = parse_workload_str("test", "Storage { block_size: 4K; } Command { Get; }").unwrap();
+        assert_eq!(w.seed, None);
+        assert_eq!(w.storage.block_size, Some(4096));
+
+        let w = parse_workload_str("test", "Command { Get; }").unwrap();
+        assert_eq!(w.storage, StorageOptions::default());
+    }
+
     #[test]
     fn parse_line_comment_works() {
         assert_eq!(
@@ -689,16 +1387,16 @@ Ordered[100] {
   <1%> DeleteRange(99, 100);
 }
 "#;
-        let expected = Section::Ordered(
-            100,
+        let expected = Section::from(SectionBody::Ordered(
+            Repeat::Times(100),
             vec![
                 (10, to_stmt(Command::RandomGet)),
-                (20, to_stmt(Command::NewPut(43))),
+                (20, to_stmt(Command::NewPut(SizeDist::Fixed(43)))),
                 (30, to_stmt(Command::RandomDelete)),
-                (39, to_stmt(Command::Delete(10, 20))),
+                (39, to_stmt(Command::Delete(Selector::Range(10, 20)))),
                 (1, to_stmt(Command::DeleteRange(99, 100))),
             ],
-        );
+        ));
 
         let r = parse_section().easy_parse(syntax);
 
@@ -713,7 +1411,7 @@ Ordered[100] {
 }
 "#;
 
-        let expected = Command::Times(10, vec![Command::NewPut(1024 * 1024)]);
+        let expected = Command::Times(10, vec![Command::NewPut(SizeDist::Fixed(1024 * 1024))]);
         let r = parse_times().easy_parse(syntax);
 
         assert_eq!(r, Ok((expected, "")));
@@ -738,20 +1436,58 @@ Command {
 }
 "#;
 
-        let expected = Section::Commands(vec![
-            Command::NewPut(1024 * 1024),
-            Command::Times(10, vec![Command::NewPut(5 * 1024), Command::RandomGet]),
+        let expected = Section::from(SectionBody::Commands(vec![
+            Command::NewPut(SizeDist::Fixed(1024 * 1024)),
+            Command::Times(
+                10,
+                vec![
+                    Command::NewPut(SizeDist::Fixed(5 * 1024)),
+                    Command::RandomGet,
+                ],
+            ),
             Command::RandomDelete,
             Command::Times(
                 2,
                 vec![
-                    Command::Times(8, vec![Command::NewPut(5 * 1024 * 1024)]),
+                    Command::Times(8, vec![Command::NewPut(SizeDist::Fixed(5 * 1024 * 1024))]),
                     Command::RandomDelete,
                 ],
             ),
-        ]);
+        ]));
         let r = parse_top_command().easy_parse(syntax);
 
         assert_eq!(r, Ok((expected, "")));
     }
+
+    #[test]
+    fn parse_workload_str_works() {
+        let w = parse_workload_str("w", "Seed: 1;\nOrdered[10] {\n  <100%> New(1K);\n}\n").unwrap();
+        assert_eq!(w.seed, Some(1));
+        assert_eq!(w.sections.len(), 1);
+    }
+
+    #[test]
+    fn parse_workload_str_rejects_trailing_garbage() {
+        let e =
+            parse_workload_str("w", "Ordered[10] {\n  <100%> New(1K);\n}\ngarbage\n").unwrap_err();
+        assert_eq!((e.line, e.column), (4, 1));
+        assert_eq!(e.unexpected, Some("`g`".to_owned()));
+        assert_eq!(e.expected, vec!["end of input".to_owned()]);
+        assert_eq!(e.snippet, "garbage");
+    }
+
+    #[test]
+    fn parse_workload_str_reports_position() {
+        let e = parse_workload_str("w", "Ordered[10] {\n  <100%> New(1K)\n}\n").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 17));
+        assert_eq!(e.expected, vec!["`;`".to_owned()]);
+        assert_eq!(
+            e.to_string(),
+            "w:2:17: syntax error: unexpected newline, expected `;`\n  |\n2 |   <100%> New(1K)\n  |                 ^"
+        );
+
+        let e = parse_workload_str("w", "Ordered[10] {\n  <300%> New(1K);\n}\n").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.messages, vec!["number out of range".to_owned()]);
+    }
 }
@@ -0,0 +1,795 @@
+//! ベンチマーク結果を機械可読な形で出力するためのモジュール.
+//!
+//! 時間はすべてナノ秒単位の整数で表す.
+use crate::histogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// 実行したcannylsのバージョン. `build.rs`がCargo.lockから読み出す.
+pub const CANNYLS_VERSION: &str = env!("CANNYLS_VERSION");
+
+/// 実行条件.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub workload: Option<String>,
+    pub trace: Option<String>,
+    pub seed: Option<u64>,
+    pub capacity: u64,
+    pub block_size: u16,
+    // ジャーナル領域のバイト数
+    #[serde(default)]
+    pub journal_region_size: u64,
+    // ジャーナルを同期する間隔とGCのキューの長さ. 指定しなかった場合(cannylsの既定値)は`None`
+    #[serde(default)]
+    pub journal_sync_interval: Option<usize>,
+    #[serde(default)]
+    pub journal_gc_queue_size: Option<usize>,
+    // memory, file、またはブロックデバイスのパス
+    #[serde(default)]
+    pub nvm: String,
+    // 模擬したデバイスの遅延. 模擬しなかった場合は空
+    #[serde(default)]
+    pub latency_model: String,
+    // ストレージを新しく作ったか(create)、既存のものを開いたか(open)
+    #[serde(default)]
+    pub storage_mode: String,
+    pub cannyls_version: String,
+    // 全体の統計から除いたセクションのラベル
+    #[serde(default)]
+    pub excluded_sections: Vec<String>,
+    // セクションで指定しなかった場合の目標スループット(ops/s)
+    #[serde(default)]
+    pub target_ops_per_sec: Option<u64>,
+    // RFC 3339形式
+    pub start_time: String,
+    pub end_time: String,
+}
+
+/// あるコマンド種別のレイテンシの統計.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub kind: String,
+    pub count: usize,
+    pub min_ns: u64,
+    pub mean_ns: u64,
+    pub stddev_ns: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p95_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+    pub max_ns: u64,
+    #[serde(default)]
+    pub histogram: Histogram,
+}
+
+/// 開ループで実行したコマンドの応答時間(予定した開始時刻から完了までの時間)の統計.
+///
+/// 前のコマンドが遅れたために後ろのコマンドが待たされた時間も含む.
+/// `Report::kinds`などはサービス時間(実際に発行してから完了までの時間)である.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponseStats {
+    pub kinds: Vec<Stats>,
+    pub overall: Stats,
+}
+
+/// あるセクションだけの統計.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SectionReport {
+    pub index: usize,
+    pub label: Option<String>,
+    // 全体の統計から除かれたかどうか
+    pub excluded: bool,
+    // 時間で区切ったセクションで、実際に展開したstatementの数
+    #[serde(default)]
+    pub iterations: Option<usize>,
+    pub kinds: Vec<Stats>,
+    pub overall: Stats,
+    pub total_time_ns: u64,
+    pub ops_per_sec: f64,
+    #[serde(default)]
+    pub response: Option<ResponseStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub metadata: Metadata,
+    // 除外したセクションを除く全セクションの統計
+    pub kinds: Vec<Stats>,
+    pub overall: Stats,
+    // コマンドの実行に費やした時間の合計
+    pub total_time_ns: u64,
+    // overall.count / total_time. デバイスモードと開ループのセクションでは実時間で割る
+    // `overall`は操作だけをまとめた統計で、`Sync`・`JournalGc`・`SideJob`は含めない
+    pub ops_per_sec: f64,
+    #[serde(default)]
+    pub response: Option<ResponseStats>,
+    #[serde(default)]
+    pub sections: Vec<SectionReport>,
+    #[serde(default)]
+    pub io: Option<IoReport>,
+    // `Reopen`でストレージを開き直した記録
+    #[serde(default)]
+    pub opens: Vec<OpenReport>,
+    // `JournalGc`・`SideJob`の記録
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceReport>,
+    // 各セクションの終わりと実行の終わりのストレージの状態
+    #[serde(default)]
+    pub snapshots: Vec<StorageSnapshot>,
+    #[serde(default)]
+    pub capacity: Option<CapacityReport>,
+}
+
+/// `Reopen`でストレージを開き直した時の記録.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenReport {
+    // 開き直したセクションの番号
+    pub section: usize,
+    pub label: Option<String>,
+    // `StorageBuilder::open`にかかった時間. ジャーナルの再生を含む
+    pub open_ns: u64,
+    // 開いた時点でジャーナルに残っていたエントリの数
+    pub journal_entries: usize,
+    // 開いた後のlumpの数
+    pub lumps: usize,
+}
+
+/// `JournalGc`・`SideJob`によるジャーナルの使用量の変化. セクションとコマンドごとにまとめる.
+///
+/// 実行にかかった時間の分布は`kinds`の`JournalGc`・`SideJob`にある.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    pub section: usize,
+    pub label: Option<String>,
+    // JournalGc または SideJob
+    pub kind: String,
+    pub count: u64,
+    pub total_ns: u64,
+    // 実行前後のジャーナルの使用量(バイト数)の差の合計. 減った場合は負
+    pub journal_usage_delta: i64,
+    // 最後に実行した後のジャーナルの使用量
+    pub journal_usage_bytes: u64,
+}
+
+/// ある時点のストレージの状態. レイテンシと使用量の関係を見るために使う.
+///
+/// ジャーナルの位置以外はストレージのメトリクスとヘッダから作るので、記録してもNVMを読み書きしない.
+/// データ領域の使用量はアロケータが割り当てたバイト数から解放したバイト数を引いたもの.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageSnapshot {
+    // 終わったセクションの番号. 実行の終わりでは`None`
+    pub section: Option<usize>,
+    pub label: Option<String>,
+    pub storage_size: u64,
+    pub journal_region_size: u64,
+    pub data_region_size: u64,
+    pub data_usage_bytes: u64,
+    // データ領域の空き領域の断片の数
+    pub free_list_len: usize,
+    pub lumps: usize,
+    pub journal_usage_bytes: u64,
+    // ジャーナル領域を読まないと分からないので、指定した時だけ記録する
+    #[serde(default)]
+    pub journal_positions: Option<JournalPositions>,
+}
+
+/// ジャーナルのリングバッファの位置.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalPositions {
+    pub unreleased_head: u64,
+    pub head: u64,
+    pub tail: u64,
+}
+
+/// 空回しで見積もった使用量と、実行中に記録した使用量の比較.
+///
+/// 実行中の使用量は`snapshots`の最大値なので、セクションの途中での最大値は含まない.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapacityReport {
+    pub estimated_capacity: u64,
+    pub estimated_data_usage_bytes: u64,
+    pub estimated_journal_usage_bytes: u64,
+    pub estimated_lumps: usize,
+    pub data_region_size: u64,
+    pub journal_region_size: u64,
+    // 記録がなければ`None`
+    pub data_usage_bytes: Option<u64>,
+    pub journal_usage_bytes: Option<u64>,
+    pub lumps: Option<usize>,
+}
+
+/// NVMへの読み書きの統計. ストレージの作成にかかった読み書きは含まない.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoReport {
+    // ヘッダ・ジャーナル・データの各領域
+    pub regions: Vec<RegionIo>,
+    // Put・Embedで書き込んだバイト数とGetで読み出したバイト数
+    pub logical_write_bytes: u64,
+    pub logical_read_bytes: u64,
+    // NVMに書き込んだバイト数 / logical_write_bytes
+    pub write_amplification: f64,
+    // NVMから読み出したバイト数 / logical_read_bytes
+    pub read_amplification: f64,
+    pub syncs: u64,
+}
+
+/// ある領域への読み書きの回数.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegionIo {
+    pub region: String,
+    pub reads: u64,
+    pub read_bytes: u64,
+    pub writes: u64,
+    pub write_bytes: u64,
+    pub syncs: u64,
+    pub seeks: u64,
+    // シークで移動した距離の合計
+    pub seek_distance_bytes: u64,
+}
+
+impl RegionIo {
+    pub(crate) fn new(region: &str) -> RegionIo {
+        RegionIo {
+            region: region.to_owned(),
+            reads: 0,
+            read_bytes: 0,
+            writes: 0,
+            write_bytes: 0,
+            syncs: 0,
+            seeks: 0,
+            seek_distance_bytes: 0,
+        }
+    }
+}
+
+/// 実行中に一定間隔で集計した一区間の統計.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IntervalRecord {
+    // 0始まりの区間番号
+    pub window: usize,
+    // 実行開始から区間の終わりまでの経過時間
+    pub elapsed_secs: f64,
+    pub window_secs: f64,
+    // 区間の終わりに実行していたセクションの番号
+    pub section: usize,
+    pub ops: u64,
+    pub ops_per_sec: f64,
+    // Put・Embed・Getで読み書きしたバイト数に基づく
+    pub bytes_per_sec: f64,
+    // 最後の要素は区間全体(`Overall`)
+    pub kinds: Vec<IntervalStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IntervalStats {
+    pub kind: String,
+    pub count: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub max_ns: u64,
+}
+
+impl IntervalStats {
+    pub(crate) fn new(kind: String, h: &Histogram) -> IntervalStats {
+        IntervalStats {
+            kind,
+            count: h.count(),
+            p50_ns: h.quantile(0.5),
+            p90_ns: h.quantile(0.9),
+            p99_ns: h.quantile(0.99),
+            max_ns: h.max(),
+        }
+    }
+}
+
+pub(crate) const INTERVAL_CSV_HEADER: &str =
+    "window,elapsed_secs,window_secs,section,ops,ops_per_sec,bytes_per_sec,\
+     kind,count,p50_ns,p90_ns,p99_ns,max_ns";
+
+impl IntervalRecord {
+    /// 区間の統計をJSON Linesの1行として書き出す.
+    pub fn write_json_line<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, self).map_err(io::Error::from)?;
+        writeln!(writer)
+    }
+
+    /// 区間の統計をコマンド種別ごとに1行のCSVとして書き出す. ヘッダは含まない.
+    pub fn write_csv_rows<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for s in &self.kinds {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                self.window,
+                self.elapsed_secs,
+                self.window_secs,
+                self.section,
+                self.ops,
+                self.ops_per_sec,
+                self.bytes_per_sec,
+                csv_field(&s.kind),
+                s.count,
+                s.p50_ns,
+                s.p90_ns,
+                s.p99_ns,
+                s.max_ns
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn nanos(d: Duration) -> u64 {
+    d.as_nanos() as u64
+}
+
+impl Report {
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+
+    /// コマンド種別ごとに1行のCSVを書き出す. 各グループの最後の行は全体の統計(`Overall`).
+    ///
+    /// 先頭のグループは除外したセクションを除く全体の統計で、`section`列が空になる.
+    /// 続いてセクションごとの統計を並べる.
+    /// 応答時間の統計がある場合は、`latency`列が`response`の行として各グループの後ろに続ける.
+    /// 複数回の結果を連結して比較しやすいように、各行に実行条件も含める.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "workload,trace,seed,capacity,block_size,journal_region_size,journal_sync_interval,\
+             journal_gc_queue_size,nvm,latency_model,storage_mode,cannyls_version,excluded_sections,\
+             target_ops_per_sec,start_time,end_time,section,section_label,excluded,\
+             iterations,total_time_ns,ops_per_sec,latency,\
+             kind,count,min_ns,mean_ns,stddev_ns,p50_ns,p90_ns,p95_ns,p99_ns,p999_ns,max_ns,\
+             write_amplification,read_amplification,syncs"
+        )?;
+
+        let m = &self.metadata;
+        let metadata = [
+            csv_field(m.workload.as_deref().unwrap_or("")),
+            csv_field(m.trace.as_deref().unwrap_or("")),
+            m.seed.map(|s| s.to_string()).unwrap_or_default(),
+            m.capacity.to_string(),
+            m.block_size.to_string(),
+            m.journal_region_size.to_string(),
+            m.journal_sync_interval
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            m.journal_gc_queue_size
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            csv_field(&m.nvm),
+            csv_field(&m.latency_model),
+            csv_field(&m.storage_mode),
+            csv_field(&m.cannyls_version),
+            csv_field(&m.excluded_sections.join(";")),
+            m.target_ops_per_sec
+                .map(|r| r.to_string())
+                .unwrap_or_default(),
+            csv_field(&m.start_time),
+            csv_field(&m.end_time),
+        ]
+        .join(",");
+
+        // NVMへの読み書きは実行全体でしか数えないので、全体の統計の行にだけ書く
+        let io = self
+            .io
+            .as_ref()
+            .map(|io| {
+                format!(
+                    "{},{},{}",
+                    io.write_amplification, io.read_amplification, io.syncs
+                )
+            })
+            .unwrap_or_else(|| ",,".to_owned());
+        let whole = (
+            io.as_str(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            self.total_time_ns,
+            self.ops_per_sec,
+            &self.kinds,
+            &self.overall,
+            &self.response,
+        );
+        let sections = self.sections.iter().map(|s| {
+            (
+                ",,",
+                s.index.to_string(),
+                csv_field(s.label.as_deref().unwrap_or("")),
+                s.excluded.to_string(),
+                s.iterations.map(|n| n.to_string()).unwrap_or_default(),
+                s.total_time_ns,
+                s.ops_per_sec,
+                &s.kinds,
+                &s.overall,
+                &s.response,
+            )
+        });
+        for (
+            io,
+            section,
+            label,
+            excluded,
+            iterations,
+            total_time_ns,
+            ops_per_sec,
+            kinds,
+            overall,
+            response,
+        ) in Some(whole).into_iter().chain(sections)
+        {
+            let service = kinds.iter().chain(Some(overall)).map(|s| ("service", s));
+            let response = response
+                .iter()
+                .flat_map(|r| r.kinds.iter().chain(Some(&r.overall)))
+                .map(|s| ("response", s));
+            for (latency, s) in service.chain(response) {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    metadata,
+                    section,
+                    label,
+                    excluded,
+                    iterations,
+                    total_time_ns,
+                    ops_per_sec,
+                    latency,
+                    csv_field(&s.kind),
+                    s.count,
+                    s.min_ns,
+                    s.mean_ns,
+                    s.stddev_ns,
+                    s.p50_ns,
+                    s.p90_ns,
+                    s.p95_ns,
+                    s.p99_ns,
+                    s.p999_ns,
+                    s.max_ns,
+                    io
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = Duration::from_nanos;
+        write!(
+            f,
+            "count = {}, min = {:?}, mean = {:?}, stddev = {:?}, \
+             50% = {:?}, 90% = {:?}, 95% = {:?}, 99% = {:?}, 99.9% = {:?}, max = {:?}",
+            self.count,
+            d(self.min_ns),
+            d(self.mean_ns),
+            d(self.stddev_ns),
+            d(self.p50_ns),
+            d(self.p90_ns),
+            d(self.p95_ns),
+            d(self.p99_ns),
+            d(self.p999_ns),
+            d(self.max_ns)
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // セクションが一つだけなら全体の統計と同じなので省く
+        if self.sections.len() > 1 {
+            for section in &self.sections {
+                write!(f, "[Section #{}", section.index)?;
+                if let Some(label) = &section.label {
+                    write!(f, " \"{}\"", label)?;
+                }
+                if section.excluded {
+                    write!(f, " (excluded)")?;
+                }
+                writeln!(f, "]")?;
+                for s in &section.kinds {
+                    writeln!(f, "  kind = {}, {}", s.kind, s)?;
+                }
+                writeln!(f, "  [Overall] {}", section.overall)?;
+                if let Some(r) = &section.response {
+                    writeln!(f, "  [Response Time]")?;
+                    for s in &r.kinds {
+                        writeln!(f, "  kind = {}, {}", s.kind, s)?;
+                    }
+                    writeln!(f, "  [Overall] {}", r.overall)?;
+                }
+                writeln!(f, "  Throughput = {:.1} ops/s", section.ops_per_sec)?;
+                if let Some(iterations) = section.iterations {
+                    writeln!(f, "  Iterations = {}", iterations)?;
+                }
+            }
+            writeln!(f, "[All Sections]")?;
+        }
+        for s in &self.kinds {
+            writeln!(f, "kind = {}, {}", s.kind, s)?;
+        }
+        writeln!(f, "[Overall] {}", self.overall)?;
+        if let Some(r) = &self.response {
+            writeln!(f, "[Response Time]")?;
+            for s in &r.kinds {
+                writeln!(f, "kind = {}, {}", s.kind, s)?;
+            }
+            writeln!(f, "[Overall] {}", r.overall)?;
+        }
+        writeln!(
+            f,
+            "Total Elapsed Time by Commands = {:?}",
+            Duration::from_nanos(self.total_time_ns)
+        )?;
+        write!(f, "Throughput = {:.1} ops/s", self.ops_per_sec)?;
+        // セクションが一つだけの場合は、セクションごとの統計を表示しないのでここで表示する
+        if let [section] = self.sections.as_slice() {
+            if let Some(iterations) = section.iterations {
+                write!(f, "\nIterations = {}", iterations)?;
+            }
+        }
+        if let Some(io) = &self.io {
+            write!(f, "\n[I/O]")?;
+            for r in &io.regions {
+                write!(
+                    f,
+                    "\nregion = {}, reads = {}, read bytes = {}, writes = {}, write bytes = {}, \
+                     syncs = {}, seeks = {}, seek distance = {}",
+                    r.region,
+                    r.reads,
+                    r.read_bytes,
+                    r.writes,
+                    r.write_bytes,
+                    r.syncs,
+                    r.seeks,
+                    r.seek_distance_bytes
+                )?;
+            }
+            write!(
+                f,
+                "\nWrite Amplification = {:.3}, Read Amplification = {:.3}, Syncs = {}",
+                io.write_amplification, io.read_amplification, io.syncs
+            )?;
+        }
+        if !self.opens.is_empty() {
+            write!(f, "\n[Reopen]")?;
+            for open in &self.opens {
+                write!(f, "\nsection = #{}", open.section)?;
+                if let Some(label) = &open.label {
+                    write!(f, " \"{}\"", label)?;
+                }
+                write!(
+                    f,
+                    ", open time = {:?}, journal entries = {}, lumps = {}",
+                    Duration::from_nanos(open.open_ns),
+                    open.journal_entries,
+                    open.lumps
+                )?;
+            }
+        }
+        if !self.maintenance.is_empty() {
+            write!(f, "\n[Maintenance]")?;
+            for m in &self.maintenance {
+                write!(f, "\nsection = #{}", m.section)?;
+                if let Some(label) = &m.label {
+                    write!(f, " \"{}\"", label)?;
+                }
+                write!(
+                    f,
+                    ", {} x {}, total time = {:?}, journal usage = {:+} bytes (now {} bytes)",
+                    m.kind,
+                    m.count,
+                    Duration::from_nanos(m.total_ns),
+                    m.journal_usage_delta,
+                    m.journal_usage_bytes
+                )?;
+            }
+        }
+        if !self.snapshots.is_empty() {
+            write!(f, "\n[Storage]")?;
+            for s in &self.snapshots {
+                match s.section {
+                    Some(section) => write!(f, "\nsection = #{}", section)?,
+                    None => write!(f, "\nend")?,
+                }
+                if let Some(label) = &s.label {
+                    write!(f, " \"{}\"", label)?;
+                }
+                write!(
+                    f,
+                    ", lumps = {}, data usage = {}/{} bytes, free list = {}, \
+                     journal usage = {}/{} bytes",
+                    s.lumps,
+                    s.data_usage_bytes,
+                    s.data_region_size,
+                    s.free_list_len,
+                    s.journal_usage_bytes,
+                    s.journal_region_size,
+                )?;
+                if let Some(p) = &s.journal_positions {
+                    write!(
+                        f,
+                        " (unreleased head = {}, head = {}, tail = {})",
+                        p.unreleased_head, p.head, p.tail
+                    )?;
+                }
+            }
+        }
+        if let Some(c) = &self.capacity {
+            write!(f, "\n[Capacity]")?;
+            write!(f, "\nestimated capacity = {} bytes", c.estimated_capacity)?;
+            let observed = |v: Option<u64>| v.map_or("-".to_owned(), |v| v.to_string());
+            write!(
+                f,
+                "\ndata usage: estimated = {}, observed = {}, region = {} bytes",
+                c.estimated_data_usage_bytes,
+                observed(c.data_usage_bytes),
+                c.data_region_size
+            )?;
+            write!(
+                f,
+                "\njournal usage: estimated = {}, observed = {}, region = {} bytes",
+                c.estimated_journal_usage_bytes,
+                observed(c.journal_usage_bytes),
+                c.journal_region_size
+            )?;
+            write!(
+                f,
+                "\nlumps: estimated = {}, observed = {}",
+                c.estimated_lumps,
+                observed(c.lumps.map(|n| n as u64))
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(kind: &str) -> Stats {
+        Stats {
+            kind: kind.to_owned(),
+            count: 2,
+            min_ns: 1,
+            mean_ns: 2,
+            stddev_ns: 1,
+            p50_ns: 1,
+            p90_ns: 3,
+            p95_ns: 3,
+            p99_ns: 3,
+            p999_ns: 3,
+            max_ns: 3,
+            histogram: Histogram::new(),
+        }
+    }
+
+    fn report() -> Report {
+        Report {
+            metadata: Metadata {
+                workload: Some("dir,with comma/w".to_owned()),
+                seed: Some(42),
+                capacity: 1024,
+                block_size: 512,
+                journal_region_size: 10240,
+                journal_sync_interval: Some(64),
+                nvm: "file".to_owned(),
+                storage_mode: "create".to_owned(),
+                cannyls_version: CANNYLS_VERSION.to_owned(),
+                excluded_sections: vec!["warmup".to_owned()],
+                start_time: "2019-01-01T00:00:00+09:00".to_owned(),
+                end_time: "2019-01-01T00:00:01+09:00".to_owned(),
+                ..Default::default()
+            },
+            kinds: vec![stats("Put(<=1K)"), stats("Delete")],
+            overall: stats("Overall"),
+            total_time_ns: 4,
+            ops_per_sec: 1.0,
+            response: None,
+            sections: vec![SectionReport {
+                index: 0,
+                label: Some("warmup".to_owned()),
+                excluded: true,
+                iterations: Some(7),
+                kinds: vec![stats("Put(<=1K)")],
+                overall: stats("Overall"),
+                total_time_ns: 2,
+                ops_per_sec: 1.0,
+                response: Some(ResponseStats {
+                    kinds: vec![stats("Put(<=1K)")],
+                    overall: stats("Overall"),
+                }),
+            }],
+            io: Some(IoReport {
+                regions: vec![RegionIo::new("journal")],
+                logical_write_bytes: 1024,
+                logical_read_bytes: 0,
+                write_amplification: 1.5,
+                read_amplification: 0.0,
+                syncs: 3,
+            }),
+            opens: vec![OpenReport {
+                section: 0,
+                label: Some("warmup".to_owned()),
+                open_ns: 5,
+                journal_entries: 6,
+                lumps: 7,
+            }],
+            maintenance: vec![MaintenanceReport {
+                section: 0,
+                label: Some("warmup".to_owned()),
+                kind: "JournalGc".to_owned(),
+                count: 2,
+                total_ns: 8,
+                journal_usage_delta: -1024,
+                journal_usage_bytes: 512,
+            }],
+            snapshots: vec![StorageSnapshot {
+                section: None,
+                label: None,
+                storage_size: 1024,
+                journal_region_size: 256,
+                data_region_size: 512,
+                data_usage_bytes: 128,
+                free_list_len: 1,
+                lumps: 3,
+                journal_usage_bytes: 32,
+                journal_positions: Some(JournalPositions {
+                    unreleased_head: 0,
+                    head: 16,
+                    tail: 48,
+                }),
+            }],
+            capacity: Some(CapacityReport {
+                estimated_capacity: 2048,
+                estimated_data_usage_bytes: 512,
+                estimated_journal_usage_bytes: 84,
+                estimated_lumps: 3,
+                data_region_size: 512,
+                journal_region_size: 256,
+                data_usage_bytes: Some(128),
+                journal_usage_bytes: None,
+                lumps: Some(3),
+            }),
+        }
+    }
+
+    #[test]
+    fn json_round_trip_works() {
+        let mut buf = Vec::new();
+        report().write_json(&mut buf).unwrap();
+        let read: Report = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(read, report());
+    }
+
+    #[test]
+    fn csv_has_one_row_per_kind() {
+        let mut buf = Vec::new();
+        report().write_csv(&mut buf).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].starts_with("\"dir,with comma/w\",,42,1024,512,10240,64,,file,,create,"));
+        assert!(lines[3].contains(",,,,,4,1,service,Overall,2,1,2,1,1,3,3,3,3,3,1.5,0,3"));
+        assert!(lines[5].contains(",0,warmup,true,7,2,1,service,Overall,"));
+        assert!(lines[5].ends_with(",3,,,"));
+        assert!(lines[7].contains(",0,warmup,true,7,2,1,response,Overall,"));
+    }
+}
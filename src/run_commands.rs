@@ -1,13 +1,22 @@
-use crate::{Bytes, RealCommand};
-use cannyls::lump;
+use crate::histogram::Histogram;
+use crate::nvm::open_file_nvm;
+use crate::report::{
+    nanos, IntervalRecord, IntervalStats, JournalPositions, MaintenanceReport, Metadata,
+    OpenReport, Report, ResponseStats, SectionReport, Stats, StorageSnapshot, INTERVAL_CSV_HEADER,
+};
+use crate::{Bytes, RealCommand, SectionInfo, StorageOptions};
+use cannyls::lump::{self, LumpId};
+use cannyls::metrics::StorageMetrics;
 use cannyls::nvm::{FileNvm, NonVolatileMemory};
 use cannyls::storage::{Storage, StorageBuilder};
 use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
 use std::ops::Range;
 use std::time::{Duration, Instant};
 
-#[derive(Debug, PartialOrd, PartialEq, Eq, Ord)]
-enum CommandKind {
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Eq, Ord)]
+pub(crate) enum CommandKind {
     Put(Bytes),
 
     Embed(Bytes),
@@ -17,82 +26,750 @@ enum CommandKind {
     Delete,
 
     DeleteRange,
+
+    Sync,
+
+    JournalGc,
+
+    SideJob,
 }
 
-pub struct Summary {
-    result: BTreeMap<CommandKind, Vec<Duration>>,
+impl fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandKind::Put(class) => write!(f, "Put(<={})", human_bytes(*class)),
+            CommandKind::Embed(class) => write!(f, "Embed(<={})", human_bytes(*class)),
+            CommandKind::Get(class) => write!(f, "Get(<={})", human_bytes(*class)),
+            CommandKind::Delete => write!(f, "Delete"),
+            CommandKind::DeleteRange => write!(f, "DeleteRange"),
+            CommandKind::Sync => write!(f, "Sync"),
+            CommandKind::JournalGc => write!(f, "JournalGc"),
+            CommandKind::SideJob => write!(f, "SideJob"),
+        }
+    }
+}
+
+impl CommandKind {
+    // lumpを読み書きする操作かどうか.
+    // `Sync`・`JournalGc`・`SideJob`は種別ごとの統計にだけ現れ、全体の統計やスループットには含めない
+    fn is_operation(&self) -> bool {
+        !matches!(
+            self,
+            CommandKind::Sync | CommandKind::JournalGc | CommandKind::SideJob
+        )
+    }
+}
+
+// サイズに分布を使うとサイズの種類が膨大になるので、
+// 2の冪に切り上げたサイズクラスごとに集計する。
+pub(crate) fn size_class(bytes: Bytes) -> Bytes {
+    bytes.next_power_of_two()
+}
+
+fn human_bytes(bytes: Bytes) -> String {
+    for (unit, suffix) in &[(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")] {
+        if bytes >= *unit && bytes.is_multiple_of(*unit) {
+            return format!("{}{}", bytes / unit, suffix);
+        }
+    }
+    bytes.to_string()
+}
+
+// 一つのセクションの集計
+#[derive(Default)]
+struct SectionSummary {
+    result: BTreeMap<CommandKind, Histogram>,
+    // 開ループで実行したコマンドの、予定時刻から完了までの時間
+    response: BTreeMap<CommandKind, Histogram>,
     total_time: Duration,
+    // 時間で区切ったセクションで、実際に展開したstatementの数
+    iterations: Option<usize>,
+    // Put・Embedで書き込んだバイト数とGetで読み出したバイト数
+    written_bytes: u64,
+    read_bytes: u64,
+    // 複数のクライアントが並行して実行した場合の実時間.
+    // 設定されていればスループットはこれを基に計算する.
+    wall_time: Option<Duration>,
 }
 
-impl Default for Summary {
-    fn default() -> Summary {
-        Summary {
-            result: BTreeMap::new(),
-            total_time: Duration::new(0, 0),
+impl SectionSummary {
+    fn merge(&mut self, other: &SectionSummary) {
+        for (kind, h) in &other.result {
+            self.result.entry(*kind).or_default().merge(h);
+        }
+        for (kind, h) in &other.response {
+            self.response.entry(*kind).or_default().merge(h);
+        }
+        // 実時間のないものはコマンドの実行時間の合計で代用する
+        if self.wall_time.is_some() || other.wall_time.is_some() {
+            self.wall_time = Some(self.throughput_time() + other.throughput_time());
         }
+        self.total_time += other.total_time;
+        self.written_bytes += other.written_bytes;
+        self.read_bytes += other.read_bytes;
+        self.iterations = match (self.iterations, other.iterations) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
     }
+
+    fn throughput_time(&self) -> Duration {
+        self.wall_time.unwrap_or(self.total_time)
+    }
+}
+
+/// セクションごとの集計結果.
+///
+/// `BeginSection`より前のコマンドは、ラベルのない0番目のセクションに数える.
+#[derive(Default)]
+pub struct Summary {
+    sections: BTreeMap<SectionInfo, SectionSummary>,
+    current: SectionInfo,
+    interval: Option<IntervalReporter>,
+    pacer: Option<Pacer>,
+    // 実行中のコマンドの予定開始時刻
+    intended: Option<Instant>,
+    // 実行中のセクションを始めた時刻. 設定されていれば、セクションを終えた時に実時間を記録する
+    section_start: Option<Instant>,
+    opens: Vec<OpenReport>,
+    maintenance: Vec<MaintenanceReport>,
+    snapshots: Vec<StorageSnapshot>,
+    probe: Option<JournalProbe>,
 }
 
-fn percentile(v: &[Duration], p: u8) -> Duration {
-    // assert!(v.is_sorted());
-    assert!(p <= 100);
+impl Summary {
+    pub(crate) fn begin_section(&mut self, info: &SectionInfo) {
+        self.end_timed_section();
+        self.start_section(info);
+    }
+
+    fn start_section(&mut self, info: &SectionInfo) {
+        self.sections.entry(info.clone()).or_default();
+        self.current = info.clone();
+        if let Some(pacer) = &mut self.pacer {
+            pacer.begin_section(info);
+        }
+    }
+
+    pub(crate) fn end_section(&mut self, iterations: usize) {
+        self.sections
+            .entry(self.current.clone())
+            .or_default()
+            .iterations = Some(iterations);
+    }
+
+    // スループットはコマンドの実行時間の合計ではなく、セクションの実時間から求める
+    fn end_timed_section(&mut self) {
+        if let Some(start) = self.section_start.take() {
+            let current = self.current.clone();
+            self.add_wall_time(&current, start.elapsed());
+        }
+    }
+
+    pub(crate) fn record(&mut self, kind: CommandKind, bytes: Bytes, elapsed: Duration) {
+        if let Some(interval) = &mut self.interval {
+            interval.record(self.current.index, kind, bytes, elapsed);
+        }
+        if !self.sections.contains_key(&self.current) {
+            self.sections
+                .insert(self.current.clone(), SectionSummary::default());
+        }
+        let section = self.sections.get_mut(&self.current).expect("bug");
+        section.total_time += elapsed;
+        section.result.entry(kind).or_default().record(elapsed);
+        match kind {
+            CommandKind::Put(_) | CommandKind::Embed(_) => section.written_bytes += bytes as u64,
+            CommandKind::Get(_) => section.read_bytes += bytes as u64,
+            CommandKind::Delete
+            | CommandKind::DeleteRange
+            | CommandKind::Sync
+            | CommandKind::JournalGc
+            | CommandKind::SideJob => {}
+        }
+        if let Some(intended) = self.intended.take() {
+            section
+                .response
+                .entry(kind)
+                .or_default()
+                .record(intended.elapsed());
+        }
+    }
+
+    // セクション`info`の実行に要した実時間を記録する. 何も実行しなかったセクションは無視する
+    pub(crate) fn add_wall_time(&mut self, info: &SectionInfo, elapsed: Duration) {
+        if let Some(section) = self.sections.get_mut(info) {
+            section.wall_time = Some(section.wall_time.unwrap_or_default() + elapsed);
+        }
+    }
+
+    fn record_open(&mut self, elapsed: Duration, journal_entries: usize, lumps: usize) {
+        self.opens.push(OpenReport {
+            section: self.current.index,
+            label: self.current.label.clone(),
+            open_ns: nanos(elapsed),
+            journal_entries,
+            lumps,
+        });
+    }
+
+    // `JournalGc`・`SideJob`の前後のジャーナルの使用量を記録する
+    fn record_maintenance(
+        &mut self,
+        kind: CommandKind,
+        elapsed: Duration,
+        before: u64,
+        after: u64,
+    ) {
+        self.record(kind, 0, elapsed);
+        let kind = kind.to_string();
+        let index = self.current.index;
+        let m = match self
+            .maintenance
+            .iter_mut()
+            .find(|m| m.section == index && m.kind == kind)
+        {
+            Some(m) => m,
+            None => {
+                self.maintenance.push(MaintenanceReport {
+                    section: index,
+                    label: self.current.label.clone(),
+                    kind,
+                    count: 0,
+                    total_ns: 0,
+                    journal_usage_delta: 0,
+                    journal_usage_bytes: 0,
+                });
+                self.maintenance.last_mut().expect("bug")
+            }
+        };
+        m.count += 1;
+        m.total_ns += nanos(elapsed);
+        m.journal_usage_delta += after as i64 - before as i64;
+        m.journal_usage_bytes = after;
+    }
+
+    // `storage`の今の状態を返す. `probe`があればジャーナルの位置も読む
+    fn snapshot<N>(&self, storage: &mut Storage<N>) -> StorageSnapshot
+    where
+        N: NonVolatileMemory,
+    {
+        let mut snapshot = storage_snapshot(storage.metrics());
+        if let Some(probe) = &self.probe {
+            (probe.pause)(true);
+            let positions = journal_positions(storage);
+            (probe.pause)(false);
+            let positions = positions.unwrap_or_else(|e| panic!("Snapshot Error: {:?}", e));
+            snapshot.journal_positions = Some(positions);
+        }
+        snapshot
+    }
+
+    // 今のセクションが終わった時点のストレージの状態を記録する. 何も実行しなかったセクションは無視する
+    fn record_section_snapshot<N>(&mut self, storage: &mut Storage<N>)
+    where
+        N: NonVolatileMemory,
+    {
+        if self.sections.contains_key(&self.current) {
+            let snapshot = self.snapshot(storage);
+            let current = self.current.clone();
+            self.add_section_snapshot(&current, snapshot);
+        }
+    }
+
+    // 最後のセクションと実行全体の終わりの状態を記録する. どちらも同じ時点なので一度だけ取る
+    fn record_final_snapshots<N>(&mut self, storage: &mut Storage<N>)
+    where
+        N: NonVolatileMemory,
+    {
+        let snapshot = self.snapshot(storage);
+        let current = self.current.clone();
+        self.add_section_snapshot(&current, snapshot.clone());
+        self.add_final_snapshot(snapshot);
+    }
+
+    // セクション`info`が終わった時点の状態`snapshot`を記録する. 何も実行しなかったセクションは無視する
+    pub(crate) fn add_section_snapshot(&mut self, info: &SectionInfo, snapshot: StorageSnapshot) {
+        if self.sections.contains_key(info) {
+            self.snapshots.push(StorageSnapshot {
+                section: Some(info.index),
+                label: info.label.clone(),
+                ..snapshot
+            });
+        }
+    }
+
+    // 実行全体の終わりの状態`snapshot`を記録する
+    pub(crate) fn add_final_snapshot(&mut self, snapshot: StorageSnapshot) {
+        self.snapshots.push(snapshot);
+    }
+
+    /// 全セクションで、Put・Embedで書き込んだバイト数とGetで読み出したバイト数.
+    pub fn logical_bytes(&self) -> (u64, u64) {
+        self.sections.values().fold((0, 0), |(written, read), s| {
+            (written + s.written_bytes, read + s.read_bytes)
+        })
+    }
 
-    let pos: usize = (p as usize * v.len()) / 100;
-    v[pos.saturating_sub(1)]
+    /// 別のスレッドや別の実行で集計した結果を取り込む.
+    pub fn merge(&mut self, other: &Summary) {
+        for (info, section) in &other.sections {
+            self.sections
+                .entry(info.clone())
+                .or_default()
+                .merge(section);
+        }
+        self.opens.extend(other.opens.iter().cloned());
+        self.maintenance.extend(other.maintenance.iter().cloned());
+        self.snapshots.extend(other.snapshots.iter().cloned());
+    }
 }
 
-pub fn statistics(s: &mut Summary) {
-    let mut overall = Vec::new();
+fn stats(kind: String, h: &Histogram) -> Stats {
+    Stats {
+        kind,
+        count: h.count() as usize,
+        min_ns: h.min(),
+        mean_ns: h.mean() as u64,
+        stddev_ns: h.stddev() as u64,
+        p50_ns: h.quantile(0.5),
+        p90_ns: h.quantile(0.9),
+        p95_ns: h.quantile(0.95),
+        p99_ns: h.quantile(0.99),
+        p999_ns: h.quantile(0.999),
+        max_ns: h.max(),
+        histogram: h.clone(),
+    }
+}
 
-    for (kind, v) in &mut s.result {
-        v.sort();
+fn ops_per_sec(count: usize, total_time: Duration) -> f64 {
+    let total_secs = total_time.as_secs_f64();
+    if total_secs > 0.0 {
+        count as f64 / total_secs
+    } else {
+        0.0
+    }
+}
 
-        let p50 = percentile(v, 50);
-        let p90 = percentile(v, 90);
-        let p95 = percentile(v, 95);
-        let p99 = percentile(v, 99);
-        println!(
-            "kind = {:?}, count = {}, 50% = {:?}, 90% = {:?}, 95% = {:?}, 99% = {:?}",
-            kind,
-            v.len(),
-            p50,
-            p90,
-            p95,
-            p99
-        );
+// コマンド種別ごとの統計と、操作をまとめた統計
+fn kind_stats(result: &BTreeMap<CommandKind, Histogram>) -> (Vec<Stats>, Stats) {
+    let mut kinds = Vec::new();
+    let mut overall = Histogram::new();
+    for (kind, h) in result {
+        kinds.push(stats(kind.to_string(), h));
+        if kind.is_operation() {
+            overall.merge(h);
+        }
+    }
+    (kinds, stats("Overall".to_owned(), &overall))
+}
 
-        overall.append(v);
+fn response_stats(response: &BTreeMap<CommandKind, Histogram>) -> Option<ResponseStats> {
+    if response.is_empty() {
+        return None;
     }
+    let (kinds, overall) = kind_stats(response);
+    Some(ResponseStats { kinds, overall })
+}
+
+/// 集計結果から`Report`を作る.
+///
+/// `metadata.excluded_sections`にラベルが含まれるセクションは、
+/// セクションごとの統計には現れるが全体の統計からは除かれる.
+pub fn statistics(s: &Summary, metadata: Metadata) -> Report {
+    let mut sections = Vec::new();
+    let mut whole = SectionSummary::default();
+
+    for (info, section) in &s.sections {
+        let excluded = info
+            .label
+            .as_ref()
+            .is_some_and(|label| metadata.excluded_sections.contains(label));
+        if !excluded {
+            whole.merge(section);
+        }
+
+        let (kinds, overall) = kind_stats(&section.result);
+        sections.push(SectionReport {
+            index: info.index,
+            label: info.label.clone(),
+            excluded,
+            iterations: section.iterations,
+            response: response_stats(&section.response),
+            kinds,
+            ops_per_sec: ops_per_sec(overall.count, section.throughput_time()),
+            overall,
+            total_time_ns: nanos(section.total_time),
+        });
+    }
+
+    let (kinds, overall) = kind_stats(&whole.result);
+    Report {
+        metadata,
+        response: response_stats(&whole.response),
+        kinds,
+        ops_per_sec: ops_per_sec(overall.count, whole.throughput_time()),
+        overall,
+        total_time_ns: nanos(whole.total_time),
+        sections,
+        io: None,
+        opens: s.opens.clone(),
+        maintenance: s.maintenance.clone(),
+        snapshots: s.snapshots.clone(),
+        capacity: None,
+    }
+}
+
+/// 時系列の出力形式.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeriesFormat {
+    Csv,
+    JsonLines,
+}
+
+impl std::str::FromStr for SeriesFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SeriesFormat, String> {
+        match s {
+            "csv" => Ok(SeriesFormat::Csv),
+            "jsonl" => Ok(SeriesFormat::JsonLines),
+            _ => Err(format!("unknown series format `{}` (csv or jsonl)", s)),
+        }
+    }
+}
+
+// 集計中の区間
+#[derive(Default)]
+struct Window {
+    ops: u64,
+    bytes: u64,
+    result: BTreeMap<CommandKind, Histogram>,
+}
+
+/// 実行中、一定の時間または操作数ごとに区間の統計を出力する.
+///
+/// 区間の統計は時系列ファイルに書き出すか、進捗として標準エラー出力に表示する.
+pub struct IntervalReporter {
+    every: Option<Duration>,
+    every_ops: Option<u64>,
+    output: Option<(Box<dyn Write + Send>, SeriesFormat)>,
+    progress: bool,
+    total_commands: Option<usize>,
+    start: Instant,
+    window_start: Instant,
+    windows: usize,
+    done: u64,
+    section: usize,
+    window: Window,
+}
 
+impl IntervalReporter {
+    /// 経過時間は作成した時点から測る.
+    ///
+    /// どちらも指定しなければ1秒ごとに区切る. 両方指定した場合は先に満たした方で区切る.
+    pub fn new(every: Option<Duration>, every_ops: Option<u64>) -> IntervalReporter {
+        let every = if every.is_none() && every_ops.is_none() {
+            Some(Duration::from_secs(1))
+        } else {
+            every
+        };
+        let now = Instant::now();
+        IntervalReporter {
+            every,
+            every_ops,
+            output: None,
+            progress: false,
+            total_commands: None,
+            start: now,
+            window_start: now,
+            windows: 0,
+            done: 0,
+            section: 0,
+            window: Window::default(),
+        }
+    }
+
+    /// 区間の統計を`writer`に書き出す.
+    pub fn output<W>(&mut self, mut writer: W, format: SeriesFormat) -> io::Result<()>
+    where
+        W: Write + Send + 'static,
     {
-        overall.sort();
-
-        let p50 = percentile(&overall, 50);
-        let p90 = percentile(&overall, 90);
-        let p95 = percentile(&overall, 95);
-        let p99 = percentile(&overall, 99);
-        println!(
-            "[Overall {}] 50% = {:?}, 90% = {:?}, 95% = {:?}, 99% = {:?}",
-            overall.len(),
-            p50,
-            p90,
-            p95,
-            p99,
+        if format == SeriesFormat::Csv {
+            writeln!(writer, "{}", INTERVAL_CSV_HEADER)?;
+        }
+        self.output = Some((Box::new(writer), format));
+        Ok(())
+    }
+
+    /// 区間ごとに進捗を表示する. `total_commands`が分かれば残り時間も表示する.
+    pub fn progress(&mut self, total_commands: Option<usize>) {
+        self.progress = true;
+        self.total_commands = total_commands;
+    }
+
+    fn record(&mut self, section: usize, kind: CommandKind, bytes: Bytes, elapsed: Duration) {
+        self.done += 1;
+        self.section = section;
+        if kind.is_operation() {
+            self.window.ops += 1;
+        }
+        self.window.bytes += bytes as u64;
+        self.window.result.entry(kind).or_default().record(elapsed);
+
+        let full = self.every_ops.is_some_and(|n| self.window.ops >= n);
+        let now = Instant::now();
+        if full || self.every.is_some_and(|d| now - self.window_start >= d) {
+            self.close_window(now);
+        }
+    }
+
+    fn close_window(&mut self, now: Instant) {
+        let window = std::mem::take(&mut self.window);
+        let window_secs = (now - self.window_start).as_secs_f64();
+        let per_sec = |n: u64| {
+            if window_secs > 0.0 {
+                n as f64 / window_secs
+            } else {
+                0.0
+            }
+        };
+
+        let mut kinds = Vec::new();
+        let mut overall = Histogram::new();
+        for (kind, h) in &window.result {
+            kinds.push(IntervalStats::new(kind.to_string(), h));
+            if kind.is_operation() {
+                overall.merge(h);
+            }
+        }
+        kinds.push(IntervalStats::new("Overall".to_owned(), &overall));
+
+        let record = IntervalRecord {
+            window: self.windows,
+            elapsed_secs: (now - self.start).as_secs_f64(),
+            window_secs,
+            section: self.section,
+            ops: window.ops,
+            ops_per_sec: per_sec(window.ops),
+            bytes_per_sec: per_sec(window.bytes),
+            kinds,
+        };
+        self.windows += 1;
+        self.window_start = now;
+
+        if let Some((writer, format)) = &mut self.output {
+            let result = match format {
+                SeriesFormat::Csv => record.write_csv_rows(writer),
+                SeriesFormat::JsonLines => record.write_json_line(writer),
+            };
+            if let Err(e) = result {
+                eprintln!("failed to write the time series: {}", e);
+                self.output = None;
+            }
+        }
+        if self.progress {
+            self.print_progress(&record);
+        }
+    }
+
+    fn print_progress(&self, record: &IntervalRecord) {
+        let mut line = format!("[{}] {} ops", format_secs(record.elapsed_secs), self.done);
+        if let Some(total) = self.total_commands.filter(|total| *total > 0) {
+            let ratio = self.done as f64 / total as f64;
+            line += &format!("/{} ({:.1}%)", total, 100.0 * ratio);
+            if self.done > 0 {
+                let rest = record.elapsed_secs * (1.0 / ratio - 1.0);
+                line += &format!(", ETA {}", format_secs(rest.max(0.0)));
+            }
+        }
+        line += &format!(
+            ", {:.0} ops/s, {}/s, section #{}",
+            record.ops_per_sec,
+            human_bytes_approx(record.bytes_per_sec),
+            record.section
         );
-        println!("Total Elapsed Time by Commands = {:?}", s.total_time);
+        eprint!("\r{:<100}", line);
+    }
+
+    // 途中の区間を出力して終える
+    fn finish(mut self) {
+        if !self.window.result.is_empty() {
+            self.close_window(Instant::now());
+        }
+        if let Some((writer, _)) = &mut self.output {
+            if let Err(e) = writer.flush() {
+                eprintln!("failed to write the time series: {}", e);
+            }
+        }
+        if self.progress {
+            eprintln!();
+        }
+    }
+}
+
+fn format_secs(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn human_bytes_approx(bytes: f64) -> String {
+    for (unit, suffix) in &[(1u64 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")] {
+        if bytes >= *unit as f64 {
+            return format!("{:.1}{}", bytes / *unit as f64, suffix);
+        }
+    }
+    format!("{:.0}B", bytes)
+}
+
+/// スナップショットにジャーナルの位置も記録するための設定.
+///
+/// ジャーナルの位置はジャーナル領域を読まないと分からず、読む前にバッファのジャーナルも書き出される.
+/// その読み書きをNVMへの読み書きの統計や遅延の模擬に含めないよう、`pause(true)`と`pause(false)`の間で読む.
+pub struct JournalProbe {
+    pause: Box<dyn Fn(bool) + Send>,
+}
+
+impl JournalProbe {
+    pub fn new<F>(pause: F) -> JournalProbe
+    where
+        F: Fn(bool) + Send + 'static,
+    {
+        JournalProbe {
+            pause: Box::new(pause),
+        }
+    }
+}
+
+/// 開ループ実行で、各コマンドを発行する予定時刻を決める.
+///
+/// 目標スループットはセクションの指定(`@ 2000/s`)を優先し、なければ`default_rate`を使う.
+/// どちらもなければ、前のコマンドの完了後すぐに次を発行する(閉ループ).
+/// 予定時刻はセクションの始まりから等間隔で、遅れても詰め直さない.
+pub struct Pacer {
+    default_rate: Option<u64>,
+    rate: Option<u64>,
+    start: Instant,
+    issued: u64,
+}
+
+impl Pacer {
+    pub fn new(default_rate: Option<u64>) -> Pacer {
+        Pacer {
+            default_rate,
+            rate: default_rate,
+            start: Instant::now(),
+            issued: 0,
+        }
+    }
+
+    fn begin_section(&mut self, info: &SectionInfo) {
+        self.rate = info.rate.or(self.default_rate);
+        self.start = Instant::now();
+        self.issued = 0;
+    }
+
+    // 次のコマンドの予定時刻まで待ち、その時刻を返す
+    fn wait(&mut self) -> Option<Instant> {
+        let rate = self.rate?;
+        let intended = self.start + Duration::from_secs_f64(self.issued as f64 / rate as f64);
+        self.issued += 1;
+        let now = Instant::now();
+        if now < intended {
+            std::thread::sleep(intended - now);
+        }
+        Some(intended)
+    }
+}
+
+pub fn do_commands<N, I>(storage: &mut Storage<N>, commands: I) -> Summary
+where
+    N: NonVolatileMemory,
+    I: IntoIterator<Item = RealCommand>,
+{
+    let mut summary = Summary {
+        section_start: Some(Instant::now()),
+        ..Default::default()
+    };
+
+    for command in commands {
+        do_command(storage, &command, &mut summary)
+    }
+
+    summary.end_timed_section();
+    summary.record_final_snapshots(storage);
+    summary
+}
+
+/// `do_commands`と同じだが、実行中の区間ごとの統計を`reporter`で出力する.
+pub fn do_commands_with_interval<N, I>(
+    storage: &mut Storage<N>,
+    commands: I,
+    reporter: IntervalReporter,
+) -> Summary
+where
+    N: NonVolatileMemory,
+    I: IntoIterator<Item = RealCommand>,
+{
+    let mut summary = Summary {
+        interval: Some(reporter),
+        section_start: Some(Instant::now()),
+        ..Default::default()
+    };
+
+    for command in commands {
+        do_command(storage, &command, &mut summary)
+    }
+
+    summary.end_timed_section();
+    summary.record_final_snapshots(storage);
+    if let Some(reporter) = summary.interval.take() {
+        reporter.finish();
     }
+    summary
 }
 
-pub fn do_commands<N>(storage: &mut Storage<N>, commands: &[RealCommand]) -> Summary
+/// 開ループで実行する. 各コマンドは`pacer`が決めた予定時刻に発行し、
+/// 予定時刻から完了までの応答時間をサービス時間とは別に集計する.
+///
+/// `reporter`があれば`do_commands_with_interval`と同様に区間ごとの統計も出力する.
+/// `Reopen`では`reopen`で同じNVMからストレージを開き直し、そのストレージで続きを実行する.
+/// `probe`があれば、スナップショットにジャーナルの位置も記録する.
+pub fn do_commands_paced<N, I>(
+    mut storage: Storage<N>,
+    commands: I,
+    pacer: Pacer,
+    reporter: Option<IntervalReporter>,
+    mut reopen: Option<&mut dyn FnMut() -> Storage<N>>,
+    probe: Option<JournalProbe>,
+) -> Summary
 where
     N: NonVolatileMemory,
+    I: IntoIterator<Item = RealCommand>,
 {
-    let mut summary: Summary = Default::default();
+    let mut summary = Summary {
+        interval: reporter,
+        pacer: Some(pacer),
+        section_start: Some(Instant::now()),
+        probe,
+        ..Default::default()
+    };
 
     for command in commands {
-        do_command(storage, command, &mut summary)
+        match (&command, reopen.as_mut()) {
+            (RealCommand::Reopen, Some(reopen)) => {
+                let (reopened, elapsed, journal_entries, lumps) = reopen_storage(storage, reopen);
+                storage = reopened;
+                summary.record_open(elapsed, journal_entries, lumps);
+            }
+            _ => do_command(&mut storage, &command, &mut summary),
+        }
     }
 
+    summary.end_timed_section();
+    summary.record_final_snapshots(&mut storage);
+    if let Some(reporter) = summary.interval.take() {
+        reporter.finish();
+    }
     summary
 }
 
@@ -100,50 +777,39 @@ pub fn do_command<N>(storage: &mut Storage<N>, command: &RealCommand, summary: &
 where
     N: NonVolatileMemory,
 {
+    // `Put!`のSyncのようなストレージ全体へのコマンドは、直前の操作に続けてすぐに実行する
+    if command.is_operation() {
+        if let Some(pacer) = &mut summary.pacer {
+            summary.intended = pacer.wait();
+        }
+    }
+
     match command {
         RealCommand::Put(lumpid, bytes) => {
             let lump = storage.allocate_lump_data(*bytes).unwrap();
             {
                 let now = Instant::now();
-                let _ = storage.put(&lumpid, &lump).unwrap();
+                let _ = storage.put(lumpid, &lump).unwrap();
                 let elapsed = now.elapsed();
 
-                summary.total_time += elapsed;
-
-                if let Some(v) = summary.result.get_mut(&CommandKind::Put(*bytes)) {
-                    v.push(elapsed);
-                } else {
-                    summary
-                        .result
-                        .insert(CommandKind::Put(*bytes), vec![elapsed]);
-                }
+                summary.record(CommandKind::Put(size_class(*bytes)), *bytes, elapsed);
             }
         }
         RealCommand::Embed(lumpid, bytes) => {
             let lump = lump::LumpData::new_embedded(vec![0; *bytes]).unwrap();
             {
                 let now = Instant::now();
-                let _ = storage.put(&lumpid, &lump).unwrap();
+                let _ = storage.put(lumpid, &lump).unwrap();
                 let elapsed = now.elapsed();
 
-                summary.total_time += elapsed;
-
-                if let Some(v) = summary.result.get_mut(&CommandKind::Embed(*bytes)) {
-                    v.push(elapsed);
-                } else {
-                    summary
-                        .result
-                        .insert(CommandKind::Embed(*bytes), vec![elapsed]);
-                }
+                summary.record(CommandKind::Embed(size_class(*bytes)), *bytes, elapsed);
             }
         }
         RealCommand::Get(lumpid, bytes) => {
             let now = Instant::now();
-            let lump = storage.get(&lumpid).unwrap();
+            let lump = storage.get(lumpid).unwrap();
             let elapsed = now.elapsed();
 
-            summary.total_time += elapsed;
-
             assert!(lump.is_some());
 
             let lump: lump::LumpData = lump.unwrap();
@@ -157,30 +823,18 @@ where
                 );
             }
 
-            if let Some(v) = summary.result.get_mut(&CommandKind::Get(*bytes)) {
-                v.push(elapsed);
-            } else {
-                summary
-                    .result
-                    .insert(CommandKind::Get(*bytes), vec![elapsed]);
-            }
+            summary.record(CommandKind::Get(size_class(*bytes)), *bytes, elapsed);
         }
         RealCommand::Delete(lumpid, _) => {
             let now = Instant::now();
-            let existed = storage.delete(&lumpid).unwrap();
+            let existed = storage.delete(lumpid).unwrap();
             let elapsed = now.elapsed();
 
-            summary.total_time += elapsed;
-
             if !existed {
                 panic!("Delete Error: Lumpid = {} does not exist", lumpid);
             }
 
-            if let Some(v) = summary.result.get_mut(&CommandKind::Delete) {
-                v.push(elapsed);
-            } else {
-                summary.result.insert(CommandKind::Delete, vec![elapsed]);
-            }
+            summary.record(CommandKind::Delete, 0, elapsed);
         }
         RealCommand::DeleteRange(start, end) => {
             let now = Instant::now();
@@ -192,34 +846,548 @@ where
                 .unwrap();
             let elapsed = now.elapsed();
 
-            summary.total_time += elapsed;
+            summary.record(CommandKind::DeleteRange, 0, elapsed);
+        }
+        RealCommand::Sync => {
+            let now = Instant::now();
+            storage.journal_sync().unwrap();
+            let elapsed = now.elapsed();
+
+            summary.record(CommandKind::Sync, 0, elapsed);
+        }
+        RealCommand::JournalGc => {
+            let before = journal_usage(storage);
+            let now = Instant::now();
+            storage.journal_gc().unwrap();
+            let elapsed = now.elapsed();
 
-            if let Some(v) = summary.result.get_mut(&CommandKind::DeleteRange) {
-                v.push(elapsed);
-            } else {
-                summary
-                    .result
-                    .insert(CommandKind::DeleteRange, vec![elapsed]);
+            let after = journal_usage(storage);
+            summary.record_maintenance(CommandKind::JournalGc, elapsed, before, after);
+        }
+        RealCommand::SideJob(n) => {
+            let before = journal_usage(storage);
+            let now = Instant::now();
+            for _ in 0..*n {
+                storage.run_side_job_once().unwrap();
+            }
+            let elapsed = now.elapsed();
+
+            let after = journal_usage(storage);
+            summary.record_maintenance(CommandKind::SideJob, elapsed, before, after);
+        }
+        RealCommand::BeginSection(info) => {
+            // 前のセクションの実時間を確定させてから、その終わりの状態を記録する
+            let timed = summary.section_start.is_some();
+            summary.end_timed_section();
+            summary.record_section_snapshot(storage);
+            summary.start_section(info);
+            if timed {
+                summary.section_start = Some(Instant::now());
             }
         }
+        RealCommand::EndSection(iterations) => summary.end_section(*iterations),
+        RealCommand::Reopen => panic!("Reopen Error: the NVM cannot be reopened here"),
+    }
+}
+
+// ジャーナル領域の使用量(バイト数)
+fn journal_usage<N>(storage: &Storage<N>) -> u64
+where
+    N: NonVolatileMemory,
+{
+    storage.metrics().journal_region().queue().usage_bytes()
+}
+
+/// ストレージのメトリクス`metrics`から今の状態を返す. `section`と`label`、ジャーナルの位置は空にしておく.
+///
+/// NVMを読み書きしないので、実行中に呼んでも計測に影響しない.
+pub fn storage_snapshot(metrics: &StorageMetrics) -> StorageSnapshot {
+    let header = metrics.header();
+    StorageSnapshot {
+        section: None,
+        label: None,
+        storage_size: header.storage_size(),
+        journal_region_size: header.journal_region_size,
+        data_region_size: header.data_region_size,
+        data_usage_bytes: metrics.data_region().usage_bytes(),
+        free_list_len: metrics.data_region().allocator().free_list_len(),
+        lumps: metrics.lumps(),
+        journal_usage_bytes: metrics.journal_region().queue().usage_bytes(),
+        journal_positions: None,
     }
 }
 
-pub fn make_storage_on_file<P>(filepath: P, capacity: u64, block_size: Option<u16>) -> Storage<FileNvm>
+/// `storage`のジャーナルの位置を返す.
+///
+/// ジャーナル領域を読み、その前にバッファに溜まったジャーナルを書き出す.
+pub fn journal_positions<N>(storage: &mut Storage<N>) -> cannyls::Result<JournalPositions>
+where
+    N: NonVolatileMemory,
+{
+    let journal = storage.journal_snapshot()?;
+    Ok(JournalPositions {
+        unreleased_head: journal.unreleased_head,
+        head: journal.head,
+        tail: journal.tail,
+    })
+}
+
+/// `storage`を閉じ、`open`で同じNVMからストレージを開き直す.
+///
+/// 開き直したストレージと、`open`にかかった時間、開いた時点でジャーナルに残っていたエントリの数、
+/// 開いた後のlumpの数を返す. 時間にはNVMを開き直す時間も含まれる.
+/// `open`は閉じたストレージを捨ててから呼ぶので、ファイルのロックを取り直せる.
+pub fn reopen_storage<N, F>(
+    mut storage: Storage<N>,
+    open: F,
+) -> (Storage<N>, Duration, usize, usize)
+where
+    N: NonVolatileMemory,
+    F: FnOnce() -> Storage<N>,
+{
+    // 閉じる時と同じく、バッファに残っているジャーナルを書き出しておく
+    storage.journal_sync().unwrap();
+    drop(storage);
+
+    let now = Instant::now();
+    let mut reopened = open();
+    let elapsed = now.elapsed();
+
+    let journal_entries = reopened.journal_snapshot().unwrap().entries.len();
+    let lumps = reopened.list().len();
+    (reopened, elapsed, journal_entries, lumps)
+}
+
+pub fn make_storage_on_file<P>(
+    filepath: P,
+    capacity: u64,
+    options: &StorageOptions,
+) -> Storage<FileNvm>
 where
     P: AsRef<std::path::Path>,
 {
-    use cannyls::block::BlockSize;
-    
-    let (filenvm, created) = FileNvm::create_if_absent(filepath, capacity).unwrap();
+    make_storage(open_file_nvm(filepath, capacity).unwrap(), options)
+}
 
-    if created {
-        println!("[Notice:] we CREATE a new file by the given name");
-    } else {
-        println!("[Notice:] we OPEN the file");
+/// 既存のストレージをどう扱うか.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageMode {
+    // 常に新しく作る. 既存のファイルは作り直す
+    Create,
+
+    // 既存のストレージを開く. なければエラー
+    Open,
+
+    // 既存のストレージがあれば開き、なければ作る
+    CreateOrOpen,
+}
+
+impl std::str::FromStr for StorageMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StorageMode, String> {
+        match s {
+            "create" => Ok(StorageMode::Create),
+            "open" => Ok(StorageMode::Open),
+            "create-or-open" => Ok(StorageMode::CreateOrOpen),
+            _ => Err(format!(
+                "unknown storage mode `{}` (create, open or create-or-open)",
+                s
+            )),
+        }
     }
-    
+}
+
+/// `nvm`に作られているストレージを開く.
+///
+/// ジャーナル領域の割合とブロックサイズはストレージのヘッダに従う.
+pub fn open_storage<N>(nvm: N, options: &StorageOptions) -> Storage<N>
+where
+    N: NonVolatileMemory,
+{
+    storage_builder(options).open(nvm).unwrap()
+}
+
+/// ストレージにあるlumpとそのサイズ.
+///
+/// `head`で分かるのは近似的なサイズだけなので、すべてのlumpを一度読み出して正確なサイズを得る.
+pub fn live_lumps<N>(storage: &mut Storage<N>) -> Vec<(LumpId, Bytes)>
+where
+    N: NonVolatileMemory,
+{
+    storage
+        .list()
+        .into_iter()
+        .map(|lumpid| {
+            let lump = storage.get(&lumpid).unwrap().unwrap_or_else(|| {
+                panic!("GET Error[Lumpid = {}]: the lump does not exist", lumpid)
+            });
+            (lumpid, lump.as_bytes().len())
+        })
+        .collect()
+}
+
+/// `nvm`上にストレージを新しく作る.
+pub fn make_storage<N>(nvm: N, options: &StorageOptions) -> Storage<N>
+where
+    N: NonVolatileMemory,
+{
+    storage_builder(options).create(nvm).unwrap()
+}
+
+/// `options`の設定を反映した`StorageBuilder`.
+pub fn storage_builder(options: &StorageOptions) -> StorageBuilder {
+    use cannyls::block::BlockSize;
+
     let mut builder = StorageBuilder::new();
-    let blocksize = BlockSize::new(block_size.unwrap_or(512)).expect("failed");
-    builder.block_size(blocksize).create(filenvm).unwrap()
+    builder.journal_region_ratio(options.journal_ratio());
+    let block_size = BlockSize::new(options.block_size() as u16).expect("failed");
+    builder.block_size(block_size);
+    if let Some(interval) = options.sync_interval {
+        builder.journal_sync_interval(interval);
+    }
+    if let Some(size) = options.gc_queue_size {
+        builder.journal_gc_queue_size(size);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting_nvm::CountingNvm;
+    use crate::generator::Generator;
+    use crate::parse::parse_workload_str;
+    use crate::report::IoReport;
+    use cannyls::nvm::{MemoryNvm, SharedMemoryNvm};
+
+    #[test]
+    fn statistics_breaks_out_sections() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command "warmup" { 10.times { New(1K); }; }
+               Ordered[10] { <50%> Get; <50%> Delete; }"#,
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+
+        let metadata = Metadata {
+            excluded_sections: vec!["warmup".to_owned()],
+            ..Default::default()
+        };
+        let report = statistics(&summary, metadata);
+        assert_eq!(report.sections.len(), 2);
+        assert_eq!(report.sections[0].label, Some("warmup".to_owned()));
+        assert!(report.sections[0].excluded);
+        assert_eq!(report.sections[0].overall.count, 10);
+        assert_eq!(report.sections[1].overall.count, 10);
+        assert_eq!(report.overall.count, 10);
+        let kinds: Vec<&str> = report.kinds.iter().map(|s| s.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["Get(<=1K)", "Delete"]);
+    }
+
+    #[test]
+    fn throughput_is_computed_from_wall_time() {
+        let w = parse_workload_str(
+            "test",
+            "Command { 100.times { New(1K); }; } Ordered[100] { <100%> Get; }",
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+        let start = Instant::now();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+        let elapsed = start.elapsed().as_secs_f64();
+        let report = statistics(&summary, Metadata::default());
+
+        // 実時間はコマンドの実行時間の合計以上で、全体の実行時間以下
+        let count = report.overall.count as f64;
+        assert_eq!(count, 200.0);
+        assert!(report.ops_per_sec <= count / (report.total_time_ns as f64 / 1e9));
+        assert!(report.ops_per_sec >= count / elapsed);
+        for section in &report.sections {
+            let count = section.overall.count as f64;
+            assert!(section.ops_per_sec <= count / (section.total_time_ns as f64 / 1e9));
+        }
+    }
+
+    #[test]
+    fn syncs_are_reported_as_their_own_kind() {
+        let w = parse_workload_str("test", "Command { 10.times { Put!(1K); }; Sync; }").unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+        let report = statistics(&summary, Metadata::default());
+        let counts: Vec<_> = report
+            .kinds
+            .iter()
+            .map(|k| (k.kind.as_str(), k.count))
+            .collect();
+        assert_eq!(counts, vec![("Put(<=1K)", 10), ("Sync", 11)]);
+        assert_eq!(report.overall.count, 10);
+        assert_eq!(report.sections[0].overall.count, 10);
+    }
+
+    #[test]
+    fn maintenance_is_reported_with_journal_usage() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command { 100.times { New(1K); }; DeleteRange(0, 100); }
+               Command "gc" { JournalGc; SideJob(4); JournalGc; }"#,
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+        let report = statistics(&summary, Metadata::default());
+
+        let kinds: Vec<_> = report
+            .maintenance
+            .iter()
+            .map(|m| (m.kind.as_str(), m.count))
+            .collect();
+        assert_eq!(kinds, vec![("JournalGc", 2), ("SideJob", 1)]);
+        let gc = &report.maintenance[0];
+        assert_eq!(gc.section, 1);
+        assert_eq!(gc.label, Some("gc".to_owned()));
+        assert!(gc.journal_usage_delta < 0, "{:?}", gc);
+        assert_eq!(gc.journal_usage_bytes, journal_usage(&storage));
+        assert!(report.kinds.iter().any(|k| k.kind == "SideJob"));
+        // 補助タスクは全体の統計に含めない
+        assert_eq!(report.overall.count, 101);
+        assert_eq!(report.sections[1].overall.count, 0);
+    }
+
+    #[test]
+    fn snapshots_are_taken_after_each_section() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command "load" { 100.times { New(2K); }; }
+               Command "drop" { DeleteRange(0, 50); }"#,
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+        let summary = do_commands(&mut storage, Generator::new(&w));
+        let report = statistics(&summary, Metadata::default());
+
+        let sections: Vec<_> = report
+            .snapshots
+            .iter()
+            .map(|s| (s.section, s.label.as_deref(), s.lumps))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (Some(0), Some("load"), 100),
+                (Some(1), Some("drop"), 51),
+                (None, None, 51),
+            ]
+        );
+        let (load, drop) = (&report.snapshots[0], &report.snapshots[1]);
+        assert!(load.data_usage_bytes > drop.data_usage_bytes, "{:?}", drop);
+        assert!(load.journal_usage_bytes > 0, "{:?}", load);
+        assert!(load.journal_positions.is_none());
+        assert_eq!(load.storage_size, storage.header().storage_size());
+    }
+
+    // `workload`を実行し、ストレージの状態とNVMへの読み書きの統計を返す
+    fn run_with_probe(workload: &str, probe: bool) -> (Vec<StorageSnapshot>, IoReport) {
+        let w = parse_workload_str("test", workload).unwrap();
+        let nvm = CountingNvm::new(MemoryNvm::new(vec![0; 4 * 1024 * 1024]));
+        let counter = nvm.counter();
+        let storage = StorageBuilder::new().create(nvm).unwrap();
+        let header = storage.header().clone();
+        counter.reset();
+        let probe = if probe {
+            let counter = counter.clone();
+            Some(JournalProbe::new(move |paused| counter.pause(paused)))
+        } else {
+            None
+        };
+        let summary = do_commands_paced(
+            storage,
+            Generator::new(&w),
+            Pacer::new(None),
+            None,
+            None,
+            probe,
+        );
+        let (written, read) = summary.logical_bytes();
+        let report = statistics(&summary, Metadata::default());
+        (report.snapshots, counter.report(&header, written, read))
+    }
+
+    #[test]
+    fn journal_positions_are_read_without_counting() {
+        let workload = r#"Command "load" { 100.times { New(2K); }; }
+                          Command "read" { 100.times { Get; }; }"#;
+        let (snapshots, io) = run_with_probe(workload, false);
+        assert!(snapshots.iter().all(|s| s.journal_positions.is_none()));
+        let (probed, probed_io) = run_with_probe(workload, true);
+
+        let load = probed[0].journal_positions.as_ref().unwrap();
+        assert!(load.tail > load.unreleased_head, "{:?}", load);
+        assert_eq!(
+            load.tail - load.unreleased_head,
+            probed[0].journal_usage_bytes
+        );
+        // ジャーナルの位置を読むための読み出しは数えない
+        assert_eq!(probed_io.regions[1].read_bytes, io.regions[1].read_bytes);
+        assert_eq!(probed_io.regions[2], io.regions[2]);
+    }
+
+    #[test]
+    fn storage_options_are_applied() {
+        let options = StorageOptions {
+            journal_ratio: Some(0.05),
+            block_size: Some(4096),
+            ..Default::default()
+        };
+        let storage = make_storage(MemoryNvm::new(vec![0; 4 * 1024 * 1024]), &options);
+        let header = storage.header();
+        assert_eq!(header.block_size.as_u16(), 4096);
+        assert_eq!(header.journal_region_size % 4096, 0);
+        let ratio = header.journal_region_size as f64 / header.storage_size() as f64;
+        assert!(0.04 < ratio && ratio < 0.06, "{}", ratio);
+    }
+
+    #[test]
+    fn default_options_match_cannyls() {
+        let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let storage = make_storage(nvm, &StorageOptions::default());
+        let nvm = MemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let expected = StorageBuilder::new().create(nvm).unwrap();
+        let (header, expected) = (storage.header(), expected.header());
+        assert_eq!(header.block_size, expected.block_size);
+        assert_eq!(header.journal_region_size, expected.journal_region_size);
+        assert_eq!(header.data_region_size, expected.data_region_size);
+    }
+
+    #[test]
+    fn opened_storage_continues_with_live_lumps() {
+        let load =
+            parse_workload_str("load", "Ordered[50] { <100%> New(uniform(1, 8K)); }").unwrap();
+        let read = parse_workload_str("read", "Ordered[100] { <50%> Get; <50%> Delete; }").unwrap();
+        let nvm = SharedMemoryNvm::new(vec![0; 4 * 1024 * 1024]);
+        let mut storage = make_storage(nvm.clone(), &StorageOptions::default());
+        do_commands(&mut storage, Generator::new(&load));
+        storage.journal_sync().unwrap();
+
+        let mut storage = open_storage(nvm, &StorageOptions::default());
+        let lumps = live_lumps(&mut storage);
+        assert_eq!(lumps.len(), 50);
+        // 存在しないlumpへの`Get`や`Delete`はpanicする
+        let summary = do_commands(&mut storage, Generator::with_live_lumps(&read, &lumps));
+        let report = statistics(&summary, Metadata::default());
+        assert_eq!(report.overall.count, 100);
+        assert_eq!(storage.list().len(), 0);
+    }
+
+    #[test]
+    fn paced_sections_record_response_times() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command { 10.times { New(1K); }; }
+               Ordered[20] @ 1000/s { <100%> Get; }"#,
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = StorageBuilder::new().create(nvm).unwrap();
+        let start = Instant::now();
+        let summary = do_commands_paced(
+            storage,
+            Generator::new(&w),
+            Pacer::new(None),
+            None,
+            None,
+            None,
+        );
+        assert!(start.elapsed() >= Duration::from_millis(19));
+
+        let report = statistics(&summary, Metadata::default());
+        assert!(report.sections[0].response.is_none());
+        let response = report.sections[1].response.as_ref().unwrap();
+        assert_eq!(response.overall.count, 20);
+        assert!(response.overall.max_ns >= report.sections[1].overall.max_ns);
+        assert!(report.sections[1].ops_per_sec <= 1100.0);
+        assert_eq!(report.response.as_ref().unwrap().overall.count, 20);
+    }
+
+    #[test]
+    fn syncs_do_not_take_paced_slots() {
+        let w = parse_workload_str("test", "Ordered[20] @ 100/s { <100%> Put!(1K); }").unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let storage = StorageBuilder::new().create(nvm).unwrap();
+        let start = Instant::now();
+        let summary = do_commands_paced(
+            storage,
+            Generator::new(&w),
+            Pacer::new(None),
+            None,
+            None,
+            None,
+        );
+        // `Sync`も予定時刻を使うなら、40個目の予定時刻は390ms後になる
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190));
+        assert!(elapsed < Duration::from_millis(380));
+
+        let report = statistics(&summary, Metadata::default());
+        assert_eq!(report.sections[0].overall.count, 20);
+        let syncs = report.kinds.iter().find(|k| k.kind == "Sync").unwrap();
+        assert_eq!(syncs.count, 20);
+        let response = report.sections[0].response.as_ref().unwrap();
+        assert_eq!(response.overall.count, 20);
+    }
+
+    // テスト用に、書き込まれた内容を後から読めるwriter
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn interval_reporter_writes_windows() {
+        let w = parse_workload_str("test", "Ordered[25] { <80%> New(1K); <20%> Get; }").unwrap();
+        let nvm = MemoryNvm::new(vec![0; 1024 * 1024]);
+        let mut storage = StorageBuilder::new().create(nvm).unwrap();
+
+        let buf = SharedBuf::default();
+        let mut reporter = IntervalReporter::new(None, Some(10));
+        reporter
+            .output(buf.clone(), SeriesFormat::JsonLines)
+            .unwrap();
+        do_commands_with_interval(&mut storage, Generator::new(&w), reporter);
+
+        let lines = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<IntervalRecord> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let ops: Vec<u64> = records.iter().map(|r| r.ops).collect();
+        assert_eq!(ops, vec![10, 10, 5]);
+        assert_eq!(records[2].window, 2);
+        assert_eq!(records[0].kinds.last().unwrap().kind, "Overall");
+        assert_eq!(records[0].kinds[0].kind, "Put(<=1K)");
+        assert!(records[0].bytes_per_sec > 0.0);
+
+        let buf = SharedBuf::default();
+        let mut reporter = IntervalReporter::new(None, Some(10));
+        reporter.output(buf.clone(), SeriesFormat::Csv).unwrap();
+        do_commands_with_interval(&mut storage, Generator::new(&w), reporter);
+        let csv = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(csv.starts_with(INTERVAL_CSV_HEADER));
+        // ヘッダと、3区間それぞれのコマンド種別・Overallの行
+        assert_eq!(csv.lines().filter(|l| l.contains(",Overall,")).count(), 3);
+    }
 }
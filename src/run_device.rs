@@ -0,0 +1,373 @@
+//! cannylsの`Device`に複数のクライアントから並行してコマンドを発行するモジュール.
+//!
+//! コマンド列を`batch_size`個ずつの区間に区切り、区間の中ではlump idごとにクライアントへ振り分ける.
+//! 同じlump idへのコマンドは同じクライアントが順に発行するので、lumpごとの順序は保たれる.
+//! `DeleteRange`とセクションの境界は区切りとして扱い、それまでに発行したコマンドの完了を待つ.
+//!
+//! `Sync`も区切りとして扱い、直前の書き込みに`journal_sync`を付けて他のコマンドの完了後に単独で発行する.
+//! 直前が書き込みでなければ、空の範囲の`DeleteRange`に`journal_sync`を付けて発行する.
+//! デバイスは応答を返してから同期するので、`Sync`のレイテンシは記録しない.
+//!
+//! レイテンシはリクエストの発行から完了までを測るので、デバイスのキューでの待ち時間を含む.
+//! スループットはセクションごとの実時間から求める.
+//! ストレージの状態はデバイスのメトリクスから記録するので、ジャーナルの位置は含まない.
+use crate::report::StorageSnapshot;
+use crate::run_commands::{size_class, storage_snapshot, CommandKind, Summary};
+use crate::{Bytes, RealCommand, SectionInfo};
+use cannyls::device::{DeviceBuilder, DeviceHandle};
+use cannyls::lump::{LumpData, LumpId};
+use cannyls::metrics::StorageMetrics;
+use cannyls::nvm::NonVolatileMemory;
+use cannyls::storage::Storage;
+use cannyls::Error;
+use futures::future::join_all;
+use futures::{stream, Future, Stream};
+use std::mem;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+type Issued = Box<dyn Future<Item = (CommandKind, Bytes, Duration), Error = Error> + Send>;
+
+/// `storage`を`builder`で起動したデバイスに載せ、`clients`個のクライアントから`commands`を実行する.
+pub fn do_commands_on_device<N, I>(
+    builder: &DeviceBuilder,
+    storage: Storage<N>,
+    commands: I,
+    clients: usize,
+    batch_size: usize,
+) -> Summary
+where
+    N: NonVolatileMemory + Send + 'static,
+    I: IntoIterator<Item = RealCommand>,
+{
+    assert!(clients > 0, "the number of clients must be positive");
+    assert!(batch_size > 0, "the batch size must be positive");
+
+    // `DeviceHandle`のメトリクスからはストレージのメトリクスを取れないので、渡す前に複製しておく.
+    // 複製したメトリクスはデバイス上のストレージと値を共有する
+    let metrics = storage.metrics().clone();
+    let device = fibers_global::execute(builder.spawn(move || Ok(storage)).wait_for_running())
+        .unwrap_or_else(|e| panic!("Device Error: {:?}", e));
+
+    let mut runner = Runner {
+        handle: device.handle(),
+        metrics,
+        summaries: (0..clients).map(|_| Summary::default()).collect(),
+        batch: (0..clients).map(|_| Vec::new()).collect(),
+        batch_len: 0,
+        batch_size,
+        current: SectionInfo::default(),
+        section_start: Instant::now(),
+        wall_times: Vec::new(),
+        snapshots: Vec::new(),
+        last: None,
+    };
+    for command in commands {
+        match command {
+            RealCommand::BeginSection(info) => {
+                runner.flush();
+                runner.begin_section(info);
+            }
+            RealCommand::EndSection(iterations) => {
+                runner.flush();
+                runner.summaries[0].end_section(iterations);
+            }
+            RealCommand::DeleteRange(..) => {
+                runner.flush();
+                runner.run_alone(command);
+            }
+            RealCommand::Reopen => panic!("Reopen Error: not supported in the device mode"),
+            RealCommand::Sync => runner.sync(),
+            RealCommand::JournalGc | RealCommand::SideJob(_) => {
+                panic!("{:?}: not supported in the device mode", command)
+            }
+            command => runner.push(command),
+        }
+    }
+    runner.flush();
+    let summary = runner.finish();
+
+    device.stop(Default::default());
+    let _ = fibers_global::execute(device);
+    summary
+}
+
+struct Runner {
+    handle: DeviceHandle,
+    metrics: StorageMetrics,
+    summaries: Vec<Summary>,
+    // クライアントごとの、まだ発行していないコマンド
+    batch: Vec<Vec<RealCommand>>,
+    batch_len: usize,
+    batch_size: usize,
+    current: SectionInfo,
+    section_start: Instant,
+    wall_times: Vec<(SectionInfo, Duration)>,
+    // 終わったセクションと、その時点のストレージの状態
+    snapshots: Vec<(SectionInfo, StorageSnapshot)>,
+    // 最後に`batch`へ加えたコマンドのクライアント
+    last: Option<usize>,
+}
+
+impl Runner {
+    fn push(&mut self, command: RealCommand) {
+        let client = match &command {
+            RealCommand::Put(lumpid, _)
+            | RealCommand::Embed(lumpid, _)
+            | RealCommand::Get(lumpid, _)
+            | RealCommand::Delete(lumpid, _) => {
+                (lumpid.as_u128() % self.batch.len() as u128) as usize
+            }
+            _ => 0,
+        };
+        // 続く`Sync`を最後のコマンドに付けられるよう、溢れる時に発行する
+        if self.batch_len >= self.batch_size {
+            self.flush();
+        }
+        self.batch[client].push(command);
+        self.batch_len += 1;
+        self.last = Some(client);
+    }
+
+    fn sync(&mut self) {
+        let write = match self.last.map(|client| &mut self.batch[client]) {
+            Some(commands) => match commands.last() {
+                Some(RealCommand::Put(..))
+                | Some(RealCommand::Embed(..))
+                | Some(RealCommand::Delete(..)) => commands.pop(),
+                _ => None,
+            },
+            None => None,
+        };
+        if write.is_some() {
+            self.batch_len -= 1;
+        }
+        self.flush();
+        match write {
+            Some(command) => {
+                let (kind, bytes, elapsed) =
+                    fibers_global::execute(issue(&self.handle, command, true))
+                        .unwrap_or_else(|e| panic!("Device Error: {:?}", e));
+                self.summaries[0].record(kind, bytes, elapsed);
+            }
+            None => {
+                let empty = RealCommand::DeleteRange(LumpId::new(0), LumpId::new(0));
+                fibers_global::execute(issue(&self.handle, empty, true))
+                    .unwrap_or_else(|e| panic!("Device Error: {:?}", e));
+            }
+        }
+    }
+
+    // 溜めたコマンドを各クライアントから発行し、すべての完了を待つ
+    fn flush(&mut self) {
+        self.last = None;
+        if self.batch_len == 0 {
+            return;
+        }
+        let handle = &self.handle;
+        let clients: Vec<_> = self
+            .batch
+            .iter_mut()
+            .zip(mem::take(&mut self.summaries))
+            .map(|(commands, summary)| {
+                let handle = handle.clone();
+                stream::iter_ok::<_, Error>(mem::take(commands)).fold(
+                    summary,
+                    move |mut summary, command| {
+                        issue(&handle, command, false).map(move |(kind, bytes, elapsed)| {
+                            summary.record(kind, bytes, elapsed);
+                            summary
+                        })
+                    },
+                )
+            })
+            .collect();
+        self.summaries = fibers_global::execute(join_all(clients))
+            .unwrap_or_else(|e| panic!("Device Error: {:?}", e));
+        self.batch_len = 0;
+    }
+
+    // 他のコマンドと並行させずに実行する
+    fn run_alone(&mut self, command: RealCommand) {
+        let (kind, bytes, elapsed) = fibers_global::execute(issue(&self.handle, command, false))
+            .unwrap_or_else(|e| panic!("Device Error: {:?}", e));
+        self.summaries[0].record(kind, bytes, elapsed);
+    }
+
+    fn begin_section(&mut self, info: SectionInfo) {
+        self.end_section();
+        for summary in &mut self.summaries {
+            summary.begin_section(&info);
+        }
+        self.current = info;
+    }
+
+    fn end_section(&mut self) {
+        let now = Instant::now();
+        self.wall_times
+            .push((self.current.clone(), now - self.section_start));
+        self.snapshots
+            .push((self.current.clone(), storage_snapshot(&self.metrics)));
+        self.section_start = Instant::now();
+    }
+
+    fn finish(mut self) -> Summary {
+        self.end_section();
+        let mut summary = Summary::default();
+        for s in &self.summaries {
+            summary.merge(s);
+        }
+        for (info, elapsed) in &self.wall_times {
+            summary.add_wall_time(info, *elapsed);
+        }
+        for (info, snapshot) in self.snapshots.drain(..) {
+            summary.add_section_snapshot(&info, snapshot);
+        }
+        summary.add_final_snapshot(storage_snapshot(&self.metrics));
+        summary
+    }
+}
+
+// `sync`ならコマンドの実行後にジャーナルを同期するよう依頼する
+fn issue(handle: &DeviceHandle, command: RealCommand, sync: bool) -> Issued {
+    let mut request = handle.request();
+    if sync {
+        request.journal_sync();
+    }
+    match command {
+        RealCommand::Put(lumpid, bytes) => {
+            let lump = handle.allocate_lump_data(bytes).unwrap();
+            let now = Instant::now();
+            Box::new(
+                request
+                    .put(lumpid, lump)
+                    .map(move |_| (CommandKind::Put(size_class(bytes)), bytes, now.elapsed())),
+            )
+        }
+        RealCommand::Embed(lumpid, bytes) => {
+            let lump = LumpData::new_embedded(vec![0; bytes]).unwrap();
+            let now = Instant::now();
+            Box::new(
+                request
+                    .put(lumpid, lump)
+                    .map(move |_| (CommandKind::Embed(size_class(bytes)), bytes, now.elapsed())),
+            )
+        }
+        RealCommand::Get(lumpid, bytes) => {
+            let now = Instant::now();
+            Box::new(request.get(lumpid).map(move |lump| {
+                let elapsed = now.elapsed();
+                let lump = lump.unwrap_or_else(|| {
+                    panic!("GET Error[Lumpid = {}]: the lump does not exist", lumpid)
+                });
+                if lump.as_bytes().len() != bytes {
+                    panic!(
+                        "GET Error[Lumpid = {}]: size = {}, expected size = {}",
+                        lumpid,
+                        lump.as_bytes().len(),
+                        bytes
+                    );
+                }
+                (CommandKind::Get(size_class(bytes)), bytes, elapsed)
+            }))
+        }
+        RealCommand::Delete(lumpid, _) => {
+            let now = Instant::now();
+            Box::new(request.delete(lumpid).map(move |existed| {
+                let elapsed = now.elapsed();
+                if !existed {
+                    panic!("Delete Error: Lumpid = {} does not exist", lumpid);
+                }
+                (CommandKind::Delete, 0, elapsed)
+            }))
+        }
+        RealCommand::DeleteRange(start, end) => {
+            let now = Instant::now();
+            Box::new(
+                request
+                    .delete_range(Range { start, end })
+                    .map(move |_| (CommandKind::DeleteRange, 0, now.elapsed())),
+            )
+        }
+        RealCommand::BeginSection(_)
+        | RealCommand::EndSection(_)
+        | RealCommand::Reopen
+        | RealCommand::Sync
+        | RealCommand::JournalGc
+        | RealCommand::SideJob(_) => {
+            unreachable!("sections and storage-wide commands are handled by the runner")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting_nvm::CountingNvm;
+    use crate::generator::Generator;
+    use crate::parse::parse_workload_str;
+    use crate::report::Metadata;
+    use crate::run_commands::statistics;
+    use cannyls::nvm::MemoryNvm;
+    use cannyls::storage::StorageBuilder;
+
+    #[test]
+    fn concurrent_clients_run_every_command() {
+        let w = parse_workload_str(
+            "test",
+            r#"Command "load" { 200.times { New(1K); }; }
+               Unordered[300] { <40%> Get; <30%> OverWrite(2K); <30%> Delete; }
+               Command { DeleteRange(0, 50); }"#,
+        )
+        .unwrap();
+        let nvm = MemoryNvm::new(vec![0; 8 * 1024 * 1024]);
+        let storage = StorageBuilder::new().create(nvm).unwrap();
+        let summary =
+            do_commands_on_device(&DeviceBuilder::new(), storage, Generator::new(&w), 4, 16);
+
+        let report = statistics(&summary, Metadata::default());
+        let counts: Vec<usize> = report.sections.iter().map(|s| s.overall.count).collect();
+        assert_eq!(counts, vec![200, 300, 1]);
+        assert!(report.ops_per_sec > 0.0);
+
+        // ストレージの状態はデバイスのメトリクスから記録する
+        let sections: Vec<_> = report.snapshots.iter().map(|s| s.section).collect();
+        assert_eq!(sections, vec![Some(0), Some(1), Some(2), None]);
+        assert_eq!(report.snapshots[0].lumps, 200);
+        assert_eq!(report.snapshots[2].lumps, report.snapshots[3].lumps);
+        assert!(report
+            .snapshots
+            .iter()
+            .all(|s| s.journal_positions.is_none()));
+    }
+
+    // `workload`をデバイスで実行し、(コマンドの数, ジャーナル領域への同期の回数)を返す
+    fn run_counting_syncs(workload: &str) -> (usize, u64) {
+        let w = parse_workload_str("test", workload).unwrap();
+        let nvm = CountingNvm::new(MemoryNvm::new(vec![0; 8 * 1024 * 1024]));
+        let counter = nvm.counter();
+        let storage = StorageBuilder::new().create(nvm).unwrap();
+        let header = storage.header().clone();
+        counter.reset();
+        let summary =
+            do_commands_on_device(&DeviceBuilder::new(), storage, Generator::new(&w), 4, 16);
+
+        let report = statistics(&summary, Metadata::default());
+        let io = counter.report(&header, 0, 0);
+        (report.sections[0].overall.count, io.regions[1].syncs)
+    }
+
+    #[test]
+    fn syncs_reach_the_journal() {
+        let (count, syncs) = run_counting_syncs(
+            "Command { 10.times { Put!(1K); }; 10.times { New(1K); }; Get; Sync; }",
+        );
+        let (base_count, base_syncs) =
+            run_counting_syncs("Command { 20.times { New(1K); }; Get; }");
+
+        // `Sync`のレイテンシは記録しない
+        assert_eq!(count, 21);
+        assert_eq!(base_count, 21);
+        assert_eq!(syncs - base_syncs, 11);
+    }
+}
@@ -0,0 +1,891 @@
+//! 生成済みの`RealCommand`列をファイルに書き出し、読み戻すためのモジュール.
+//!
+//! 同じコマンド列を`rand`やcannylsのバージョン、マシンをまたいで再生するために使う.
+//! 形式は次の二つで、読み込み時には先頭のマジックナンバーで自動判別する.
+//!
+//! - テキスト形式: 1行1コマンド. lump idは16進数、サイズは10進数.
+//!   `#`から行末まではコメント.
+//!   ```text
+//!   Put 1 1024
+//!   Embed 2 512
+//!   Get 1 1024
+//!   Delete 2 512
+//!   DeleteRange 1 3
+//!   Section 1 "warmup"
+//!   Section 2 "steady" @ 2000/s
+//!   Reopen
+//!   Sync
+//!   JournalGc
+//!   SideJob 4
+//!   ```
+//!   `Section`行はセクションの始まり(`RealCommand::BeginSection`)で、ラベルと目標スループットは省略できる.
+//!   書き出す時は先頭に`# cannyls_bencher trace v1`の行を置き、読み込む時はこの行があればバージョンを確かめる.
+//! - バイナリ形式: マジックナンバー`CLBTRACE`とバージョン(1バイト)の後に、
+//!   タグ(1バイト)・lump id・サイズを並べる.
+//!   `DeleteRange`だけはサイズの代わりに範囲の終端のlump idを置く.
+//!   セクションの始まりはタグの後にセクション番号、ラベルのバイト数+1(ラベルがなければ0)、
+//!   ラベル(UTF-8)を置く. 目標スループットのあるセクションは別のタグを使い、最後に目標スループットを置く.
+//!   `Reopen`・`Sync`・`JournalGc`はタグだけで、`SideJob`はタグの後に回数を置く.
+//!   数値はすべてLEB128形式の可変長整数.
+use crate::capacity::Estimator;
+use crate::generator::DryRun;
+use crate::{Bytes, RealCommand, SectionInfo, MAX_SIDE_JOBS};
+use cannyls::lump::{LumpData, LumpId};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &[u8; 8] = b"CLBTRACE";
+const TEXT_HEADER: &str = "# cannyls_bencher trace v";
+const VERSION: u8 = 1;
+
+const TAG_PUT: u8 = 0;
+const TAG_EMBED: u8 = 1;
+const TAG_GET: u8 = 2;
+const TAG_DELETE: u8 = 3;
+const TAG_DELETE_RANGE: u8 = 4;
+const TAG_SECTION: u8 = 5;
+const TAG_SECTION_WITH_RATE: u8 = 6;
+const TAG_REOPEN: u8 = 7;
+const TAG_SYNC: u8 = 8;
+const TAG_JOURNAL_GC: u8 = 9;
+const TAG_SIDE_JOB: u8 = 10;
+
+/// バイナリ形式で扱えるセクションのラベルの最大長(バイト).
+///
+/// 壊れたトレースの長さをそのまま信じて巨大な領域を確保しないための上限.
+pub const MAX_LABEL_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format `{}` (text or binary)", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    // テキスト形式の`line`行目(1始まり)が読めない
+    InvalidLine { line: usize, message: String },
+    // バイナリ形式の`record`番目(0始まり)のコマンドが読めない
+    InvalidRecord { record: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => e.fmt(f),
+            TraceError::InvalidLine { line, message } => write!(f, "line {}: {}", line, message),
+            TraceError::InvalidRecord { record, message } => {
+                write!(f, "record #{}: {}", record, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> TraceError {
+        TraceError::Io(e)
+    }
+}
+
+pub struct TraceWriter<W: Write> {
+    inner: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// ヘッダを書き込んでwriterを作る.
+    pub fn new(mut inner: W, format: TraceFormat) -> io::Result<TraceWriter<W>> {
+        match format {
+            TraceFormat::Text => writeln!(inner, "{}{}", TEXT_HEADER, VERSION)?,
+            TraceFormat::Binary => {
+                inner.write_all(MAGIC)?;
+                inner.write_all(&[VERSION])?;
+            }
+        }
+        Ok(TraceWriter { inner, format })
+    }
+
+    /// `command`を書き出す.
+    ///
+    /// `EndSection`は実行ごとに変わる情報なので書き出さない.
+    pub fn write(&mut self, command: &RealCommand) -> io::Result<()> {
+        if let RealCommand::SideJob(n) = command {
+            if *n > MAX_SIDE_JOBS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("more than {} side jobs", MAX_SIDE_JOBS),
+                ));
+            }
+        }
+        match self.format {
+            TraceFormat::Text => self.write_text(command),
+            TraceFormat::Binary => self.write_binary(command),
+        }
+    }
+
+    fn write_text(&mut self, command: &RealCommand) -> io::Result<()> {
+        let (name, lumpid, arg) = match command {
+            RealCommand::Put(lumpid, bytes) => ("Put", lumpid, *bytes as u128),
+            RealCommand::Embed(lumpid, bytes) => ("Embed", lumpid, *bytes as u128),
+            RealCommand::Get(lumpid, bytes) => ("Get", lumpid, *bytes as u128),
+            RealCommand::Delete(lumpid, bytes) => ("Delete", lumpid, *bytes as u128),
+            RealCommand::DeleteRange(start, end) => {
+                return writeln!(
+                    self.inner,
+                    "DeleteRange {:x} {:x}",
+                    start.as_u128(),
+                    end.as_u128()
+                );
+            }
+            RealCommand::EndSection(_) => return Ok(()),
+            RealCommand::Reopen => return writeln!(self.inner, "Reopen"),
+            RealCommand::Sync => return writeln!(self.inner, "Sync"),
+            RealCommand::JournalGc => return writeln!(self.inner, "JournalGc"),
+            RealCommand::SideJob(n) => return writeln!(self.inner, "SideJob {}", n),
+            RealCommand::BeginSection(info) => {
+                write!(self.inner, "Section {}", info.index)?;
+                if let Some(label) = &info.label {
+                    write!(self.inner, " {:?}", label)?;
+                }
+                if let Some(rate) = info.rate {
+                    write!(self.inner, " @ {}/s", rate)?;
+                }
+                return writeln!(self.inner);
+            }
+        };
+        writeln!(self.inner, "{} {:x} {}", name, lumpid.as_u128(), arg)
+    }
+
+    fn write_binary(&mut self, command: &RealCommand) -> io::Result<()> {
+        let (tag, lumpid, bytes) = match command {
+            RealCommand::Put(lumpid, bytes) => (TAG_PUT, lumpid, *bytes),
+            RealCommand::Embed(lumpid, bytes) => (TAG_EMBED, lumpid, *bytes),
+            RealCommand::Get(lumpid, bytes) => (TAG_GET, lumpid, *bytes),
+            RealCommand::Delete(lumpid, bytes) => (TAG_DELETE, lumpid, *bytes),
+            RealCommand::DeleteRange(start, end) => {
+                self.inner.write_all(&[TAG_DELETE_RANGE])?;
+                write_varint(&mut self.inner, start.as_u128())?;
+                return write_varint(&mut self.inner, end.as_u128());
+            }
+            RealCommand::EndSection(_) => return Ok(()),
+            RealCommand::Reopen => return self.inner.write_all(&[TAG_REOPEN]),
+            RealCommand::Sync => return self.inner.write_all(&[TAG_SYNC]),
+            RealCommand::JournalGc => return self.inner.write_all(&[TAG_JOURNAL_GC]),
+            RealCommand::SideJob(n) => {
+                self.inner.write_all(&[TAG_SIDE_JOB])?;
+                return write_varint(&mut self.inner, *n as u128);
+            }
+            RealCommand::BeginSection(info) => {
+                let tag = if info.rate.is_some() {
+                    TAG_SECTION_WITH_RATE
+                } else {
+                    TAG_SECTION
+                };
+                self.inner.write_all(&[tag])?;
+                write_varint(&mut self.inner, info.index as u128)?;
+                match &info.label {
+                    Some(label) if label.len() > MAX_LABEL_BYTES => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("section label is longer than {} bytes", MAX_LABEL_BYTES),
+                        ));
+                    }
+                    Some(label) => {
+                        write_varint(&mut self.inner, label.len() as u128 + 1)?;
+                        self.inner.write_all(label.as_bytes())?;
+                    }
+                    None => write_varint(&mut self.inner, 0)?,
+                }
+                return match info.rate {
+                    Some(rate) => write_varint(&mut self.inner, u128::from(rate)),
+                    None => Ok(()),
+                };
+            }
+        };
+        self.inner.write_all(&[tag])?;
+        write_varint(&mut self.inner, lumpid.as_u128())?;
+        write_varint(&mut self.inner, bytes as u128)
+    }
+
+    /// バッファを書き出して内部のwriterを返す.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// トレースを先頭から読み出すイテレータ.
+pub struct TraceReader<R: BufRead> {
+    inner: R,
+    format: TraceFormat,
+    // テキスト形式では読んだ行数、バイナリ形式では読んだコマンド数
+    position: usize,
+    line: String,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// 先頭のマジックナンバーを見て形式を判別する.
+    pub fn new(mut inner: R) -> Result<TraceReader<R>, TraceError> {
+        let format = if inner.fill_buf()?.starts_with(MAGIC) {
+            inner.consume(MAGIC.len());
+            let mut version = [0];
+            inner.read_exact(&mut version)?;
+            if version[0] != VERSION {
+                return Err(TraceError::InvalidRecord {
+                    record: 0,
+                    message: format!("unsupported trace version {}", version[0]),
+                });
+            }
+            TraceFormat::Binary
+        } else {
+            TraceFormat::Text
+        };
+        Ok(TraceReader {
+            inner,
+            format,
+            position: 0,
+            line: String::new(),
+        })
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    fn read_text(&mut self) -> Result<Option<RealCommand>, TraceError> {
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.position += 1;
+            if self.position == 1 {
+                if let Some(version) = self.line.trim().strip_prefix(TEXT_HEADER) {
+                    if version.parse() != Ok(VERSION) {
+                        return Err(TraceError::InvalidLine {
+                            line: 1,
+                            message: format!("unsupported trace version {}", version),
+                        });
+                    }
+                }
+            }
+            // ラベルには`#`が含まれうるので、`Section`行からはコメントを取り除かない
+            let line = self.line.trim();
+            let line = if line.starts_with("Section") {
+                line
+            } else {
+                line.split('#').next().unwrap_or("").trim()
+            };
+            if !line.is_empty() {
+                return parse_line(line)
+                    .map(Some)
+                    .map_err(|message| TraceError::InvalidLine {
+                        line: self.position,
+                        message,
+                    });
+            }
+        }
+    }
+
+    fn read_binary(&mut self) -> Result<Option<RealCommand>, TraceError> {
+        let mut tag = [0];
+        if self.inner.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let record = self.position;
+        self.position += 1;
+        let invalid = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => {
+                TraceError::InvalidRecord {
+                    record,
+                    message: e.to_string(),
+                }
+            }
+            _ => TraceError::Io(e),
+        };
+
+        if tag[0] == TAG_REOPEN {
+            return Ok(Some(RealCommand::Reopen));
+        }
+        if tag[0] == TAG_SYNC {
+            return Ok(Some(RealCommand::Sync));
+        }
+        if tag[0] == TAG_JOURNAL_GC {
+            return Ok(Some(RealCommand::JournalGc));
+        }
+        if tag[0] == TAG_SIDE_JOB {
+            let n = read_bounded(&mut self.inner, MAX_SIDE_JOBS as u128).map_err(invalid)?;
+            return Ok(Some(RealCommand::SideJob(n as usize)));
+        }
+        if tag[0] == TAG_SECTION || tag[0] == TAG_SECTION_WITH_RATE {
+            let index =
+                read_bounded(&mut self.inner, usize::MAX as u128).map_err(invalid)? as usize;
+            let label_len = MAX_LABEL_BYTES as u128 + 1;
+            let label = match read_bounded(&mut self.inner, label_len).map_err(invalid)? as usize {
+                0 => None,
+                n => {
+                    let mut label = vec![0; n - 1];
+                    self.inner.read_exact(&mut label).map_err(invalid)?;
+                    let label =
+                        String::from_utf8(label).map_err(|_| TraceError::InvalidRecord {
+                            record,
+                            message: "section label is not valid UTF-8".to_owned(),
+                        })?;
+                    Some(label)
+                }
+            };
+            let rate = if tag[0] == TAG_SECTION_WITH_RATE {
+                Some(read_bounded(&mut self.inner, u128::from(u64::MAX)).map_err(invalid)? as u64)
+            } else {
+                None
+            };
+            return Ok(Some(RealCommand::BeginSection(SectionInfo {
+                index,
+                label,
+                rate,
+            })));
+        }
+
+        let max_size = match tag[0] {
+            TAG_DELETE_RANGE => 0,
+            TAG_EMBED => LumpData::MAX_EMBEDDED_SIZE,
+            TAG_PUT | TAG_GET | TAG_DELETE => LumpData::MAX_SIZE,
+            tag => {
+                return Err(TraceError::InvalidRecord {
+                    record,
+                    message: format!("unknown tag {}", tag),
+                });
+            }
+        };
+        let lumpid = LumpId::new(read_varint(&mut self.inner).map_err(invalid)?);
+        if tag[0] == TAG_DELETE_RANGE {
+            let end = LumpId::new(read_varint(&mut self.inner).map_err(invalid)?);
+            return Ok(Some(RealCommand::DeleteRange(lumpid, end)));
+        }
+
+        let bytes = read_bounded(&mut self.inner, max_size as u128).map_err(invalid)? as Bytes;
+        match tag[0] {
+            TAG_PUT => Ok(Some(RealCommand::Put(lumpid, bytes))),
+            TAG_EMBED => Ok(Some(RealCommand::Embed(lumpid, bytes))),
+            TAG_GET => Ok(Some(RealCommand::Get(lumpid, bytes))),
+            _ => Ok(Some(RealCommand::Delete(lumpid, bytes))),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<RealCommand, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.format {
+            TraceFormat::Text => self.read_text(),
+            TraceFormat::Binary => self.read_binary(),
+        };
+        result.transpose()
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut n: u128) -> io::Result<()> {
+    let mut buf = [0; 19];
+    let mut len = 0;
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len])
+}
+
+fn read_varint<R: BufRead>(reader: &mut R) -> io::Result<u128> {
+    let mut n: u128 = 0;
+    for shift in (0..128).step_by(7) {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated record",
+            ));
+        }
+        n |= u128::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "integer too long",
+    ))
+}
+
+// 長さや個数として読む整数. `max`を超える値は壊れたレコードとして扱う
+fn read_bounded<R: BufRead>(reader: &mut R, max: u128) -> io::Result<u128> {
+    let n = read_varint(reader)?;
+    if n > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("value {} is larger than {}", n, max),
+        ));
+    }
+    Ok(n)
+}
+
+fn parse_line(line: &str) -> Result<RealCommand, String> {
+    if let Some(rest) = line.strip_prefix("Section") {
+        return parse_section_line(rest.trim());
+    }
+    if line == "Reopen" {
+        return Ok(RealCommand::Reopen);
+    }
+    if line == "Sync" {
+        return Ok(RealCommand::Sync);
+    }
+    if line == "JournalGc" {
+        return Ok(RealCommand::JournalGc);
+    }
+    if let Some(n) = line.strip_prefix("SideJob") {
+        return match n.trim().parse() {
+            Ok(n) if n <= MAX_SIDE_JOBS => Ok(RealCommand::SideJob(n)),
+            _ => Err(format!("invalid side job count `{}`", n.trim())),
+        };
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err(format!(
+            "expected `<command> <lump id> <arg>`, found `{}`",
+            line
+        ));
+    }
+    let lumpid = |s: &str| LumpId::from_str(s).map_err(|_| format!("invalid lump id `{}`", s));
+    // 実行時に巨大な領域を確保しないよう、lumpに収まらないサイズは受け付けない
+    let bytes = |s: &str, max: Bytes| match s.parse::<Bytes>() {
+        Ok(bytes) if bytes <= max => Ok(bytes),
+        Ok(_) => Err(format!("size {} is larger than {}", s, max)),
+        Err(_) => Err(format!("invalid size `{}`", s)),
+    };
+    let max = LumpData::MAX_SIZE;
+
+    match fields[0] {
+        "Put" => Ok(RealCommand::Put(lumpid(fields[1])?, bytes(fields[2], max)?)),
+        "Embed" => Ok(RealCommand::Embed(
+            lumpid(fields[1])?,
+            bytes(fields[2], LumpData::MAX_EMBEDDED_SIZE)?,
+        )),
+        "Get" => Ok(RealCommand::Get(lumpid(fields[1])?, bytes(fields[2], max)?)),
+        "Delete" => Ok(RealCommand::Delete(
+            lumpid(fields[1])?,
+            bytes(fields[2], max)?,
+        )),
+        "DeleteRange" => Ok(RealCommand::DeleteRange(
+            lumpid(fields[1])?,
+            lumpid(fields[2])?,
+        )),
+        name => Err(format!("unknown command `{}`", name)),
+    }
+}
+
+// `Section`に続く `<index> ["label"] [@ <rate>/s]`
+fn parse_section_line(rest: &str) -> Result<RealCommand, String> {
+    // ラベルには`@`が含まれうるので、閉じ引用符より後ろから探す
+    let label_end = rest.rfind('"').map_or(0, |pos| pos + 1);
+    let (rest, rate) = match rest[label_end..].find('@') {
+        Some(pos) => {
+            let (rest, rate) = rest.split_at(label_end + pos);
+            let rate = rate[1..].trim();
+            let rate = rate
+                .strip_suffix("/s")
+                .and_then(|r| r.trim().parse().ok())
+                .ok_or_else(|| format!("invalid section rate `{}`", rate))?;
+            (rest.trim(), Some(rate))
+        }
+        None => (rest, None),
+    };
+    let (index, label) = match rest.find(char::is_whitespace) {
+        Some(pos) => (&rest[..pos], Some(rest[pos..].trim())),
+        None => (rest, None),
+    };
+    let index = index
+        .parse()
+        .map_err(|_| format!("invalid section index `{}`", index))?;
+    let label = match label {
+        None => None,
+        Some(label) => {
+            let unquoted = label
+                .strip_prefix('"')
+                .and_then(|l| l.strip_suffix('"'))
+                .and_then(unescape_label)
+                .ok_or_else(|| format!("invalid section label `{}`", label))?;
+            Some(unquoted)
+        }
+    };
+    Ok(RealCommand::BeginSection(SectionInfo {
+        index,
+        label,
+        rate,
+    }))
+}
+
+// `{:?}`で書き出したラベルのエスケープを戻す. 引用符で囲む前の中身を受け取る
+fn unescape_label(escaped: &str) -> Option<String> {
+    let mut label = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => {}
+            c => {
+                label.push(c);
+                continue;
+            }
+        }
+        let c = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ ('\\' | '"' | '\'') => c,
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let code = u32::from_str_radix(&rest[..end], 16).ok()?;
+                chars = rest[end + 1..].chars();
+                char::from_u32(code)?
+            }
+            _ => return None,
+        };
+        label.push(c);
+    }
+    Some(label)
+}
+
+pub fn create_trace_file<P: AsRef<Path>>(
+    path: P,
+    format: TraceFormat,
+) -> io::Result<TraceWriter<BufWriter<File>>> {
+    TraceWriter::new(BufWriter::new(File::create(path)?), format)
+}
+
+pub fn open_trace_file<P: AsRef<Path>>(
+    path: P,
+) -> Result<TraceReader<BufReader<File>>, TraceError> {
+    TraceReader::new(BufReader::new(File::open(path)?))
+}
+
+/// トレースを最後まで読み、コマンド数と必要な容量を求める.
+///
+/// 必要な容量は`generator::dry_run`と同じく、生存しているlumpのサイズの合計の最大値.
+/// ディスク上の大きさはブロックサイズ`block_size`で求める.
+pub fn dry_run<I>(commands: I, block_size: Bytes) -> Result<DryRun, TraceError>
+where
+    I: IntoIterator<Item = Result<RealCommand, TraceError>>,
+{
+    let mut estimator = Estimator::new(block_size);
+    let mut count = 0;
+    let mut reopens = 0;
+    let mut syncs = 0;
+    let mut maintenance = 0;
+
+    for command in commands {
+        let command = command?;
+        if command == RealCommand::Reopen {
+            reopens += 1;
+        } else if !command.is_marker() {
+            count += 1;
+        }
+        match command {
+            RealCommand::Sync => syncs += 1,
+            RealCommand::JournalGc | RealCommand::SideJob(_) => maintenance += 1,
+            _ => {}
+        }
+        estimator.observe(&command);
+    }
+
+    let peak = estimator.peak();
+    Ok(DryRun {
+        commands: count,
+        peek_bytes: peak.payload_bytes as usize,
+        reopens,
+        syncs,
+        maintenance,
+        peak,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator;
+    use crate::parse::parse_workload_str;
+
+    fn commands() -> Vec<RealCommand> {
+        let w = parse_workload_str(
+            "test",
+            "Ordered[100] \"load # 1\" {
+               <30%> New(uniform(1, 4K));
+               <20%> Embed(128);
+               <20%> Get;
+               <20%> Delete;
+               <10%> DeleteRange(10, 30);
+             }
+             Command \"at @ 1/s\" @ 500/s SideJob/2 { Get; Reopen; Put!(1K); Sync; JournalGc; SideJob(3); }",
+        )
+        .unwrap();
+        generator::Generator::new(&w).collect()
+    }
+
+    fn round_trip(format: TraceFormat) {
+        let commands = commands();
+        let mut writer = TraceWriter::new(Vec::new(), format).unwrap();
+        for command in &commands {
+            writer.write(command).unwrap();
+        }
+        let buf = writer.finish().unwrap();
+
+        let reader = TraceReader::new(&buf[..]).unwrap();
+        assert_eq!(reader.format(), format);
+        let read: Vec<RealCommand> = reader.map(Result::unwrap).collect();
+        assert_eq!(read, commands);
+    }
+
+    #[test]
+    fn text_round_trip_works() {
+        round_trip(TraceFormat::Text);
+    }
+
+    #[test]
+    fn binary_round_trip_works() {
+        round_trip(TraceFormat::Binary);
+    }
+
+    #[test]
+    fn varint_round_trip_works() {
+        for &n in &[0, 1, 127, 128, 300, u64::MAX as u128, u128::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, n).unwrap();
+            assert_eq!(read_varint(&mut &buf[..]).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn text_reader_skips_comments() {
+        let src = "# header\n\nSection 0\nPut ff 10 # comment\nDeleteRange 1 ff\n";
+        let read: Vec<RealCommand> = TraceReader::new(src.as_bytes())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            read,
+            vec![
+                RealCommand::BeginSection(SectionInfo {
+                    index: 0,
+                    label: None,
+                    rate: None,
+                }),
+                RealCommand::Put(LumpId::new(0xff), 10),
+                RealCommand::DeleteRange(LumpId::new(1), LumpId::new(0xff)),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_traces_are_errors() {
+        let mut reader = TraceReader::new("Put 1 10\nPut xyz 10\n".as_bytes()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next().unwrap() {
+            Err(TraceError::InvalidLine { line: 2, .. }) => {}
+            r => panic!("{:?}", r),
+        }
+
+        let mut reader = TraceReader::new("Section 1 \"a\" @ fast\n".as_bytes()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend_from_slice(&[TAG_PUT, 0x81, 0x80]);
+        let mut reader = TraceReader::new(&buf[..]).unwrap();
+        match reader.next().unwrap() {
+            Err(TraceError::InvalidRecord { record: 0, .. }) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn labels_with_escapes_round_trip() {
+        for label in &[
+            "say \"hi\"",
+            "C:\\tmp\\",
+            "ラベル #1 @ 2/s",
+            "tab\tnew\nline\u{7f}",
+            "",
+        ] {
+            let info = SectionInfo {
+                index: 3,
+                label: Some(label.to_string()),
+                rate: Some(10),
+            };
+            for &format in &[TraceFormat::Text, TraceFormat::Binary] {
+                let mut writer = TraceWriter::new(Vec::new(), format).unwrap();
+                writer
+                    .write(&RealCommand::BeginSection(info.clone()))
+                    .unwrap();
+                let buf = writer.finish().unwrap();
+                let read: Vec<RealCommand> = TraceReader::new(&buf[..])
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .collect();
+                assert_eq!(read, vec![RealCommand::BeginSection(info.clone())]);
+            }
+        }
+
+        let mut reader = TraceReader::new("Section 1 \"a\"b\"\n".as_bytes()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        let mut reader = TraceReader::new("Section 1 \"a\\\"\n".as_bytes()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn oversized_labels_are_rejected() {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend_from_slice(&[TAG_SECTION, 0]);
+        write_varint(&mut buf, u128::from(u64::MAX)).unwrap();
+        let mut reader = TraceReader::new(&buf[..]).unwrap();
+        match reader.next().unwrap() {
+            Err(TraceError::InvalidRecord { record: 0, .. }) => {}
+            r => panic!("{:?}", r),
+        }
+
+        // `usize`に切り詰めると0(ラベルなし)になる値
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend_from_slice(&[TAG_SECTION, 0]);
+        write_varint(&mut buf, 1 << 64).unwrap();
+        let mut reader = TraceReader::new(&buf[..]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        let label = "a".repeat(MAX_LABEL_BYTES + 1);
+        let mut writer = TraceWriter::new(Vec::new(), TraceFormat::Binary).unwrap();
+        let section = RealCommand::BeginSection(SectionInfo {
+            index: 0,
+            label: Some(label),
+            rate: None,
+        });
+        assert!(writer.write(&section).is_err());
+    }
+
+    #[test]
+    fn oversized_side_jobs_are_rejected() {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.push(TAG_SIDE_JOB);
+        write_varint(&mut buf, MAX_SIDE_JOBS as u128 + 1).unwrap();
+        let mut reader = TraceReader::new(&buf[..]).unwrap();
+        match reader.next().unwrap() {
+            Err(TraceError::InvalidRecord { record: 0, .. }) => {}
+            r => panic!("{:?}", r),
+        }
+
+        let line = format!("SideJob {}", MAX_SIDE_JOBS + 1);
+        assert!(parse_line(&line).is_err());
+        let line = format!("SideJob {}", MAX_SIDE_JOBS);
+        assert_eq!(parse_line(&line), Ok(RealCommand::SideJob(MAX_SIDE_JOBS)));
+
+        for format in &[TraceFormat::Text, TraceFormat::Binary] {
+            let mut writer = TraceWriter::new(Vec::new(), *format).unwrap();
+            assert!(writer
+                .write(&RealCommand::SideJob(MAX_SIDE_JOBS + 1))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_lump_sizes_are_rejected() {
+        let cases = [
+            (TAG_PUT, "Put", LumpData::MAX_SIZE),
+            (TAG_GET, "Get", LumpData::MAX_SIZE),
+            (TAG_DELETE, "Delete", LumpData::MAX_SIZE),
+            (TAG_EMBED, "Embed", LumpData::MAX_EMBEDDED_SIZE),
+        ];
+        for &(tag, name, max) in &cases {
+            for &(bytes, ok) in &[(max, true), (max + 1, false), (1 << 40, false)] {
+                let mut buf = MAGIC.to_vec();
+                buf.extend_from_slice(&[VERSION, tag, 1]);
+                write_varint(&mut buf, bytes as u128).unwrap();
+                let mut reader = TraceReader::new(&buf[..]).unwrap();
+                match reader.next().unwrap() {
+                    Ok(_) if ok => {}
+                    Err(TraceError::InvalidRecord { record: 0, .. }) if !ok => {}
+                    r => panic!("{} {}: {:?}", name, bytes, r),
+                }
+
+                let line = format!("{} 1 {}", name, bytes);
+                assert_eq!(parse_line(&line).is_ok(), ok, "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn text_header_version_is_checked() {
+        let src = format!("{}{}\nSync\n", TEXT_HEADER, VERSION);
+        let mut reader = TraceReader::new(src.as_bytes()).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), RealCommand::Sync);
+
+        let src = format!("{}{}\nSync\n", TEXT_HEADER, VERSION + 1);
+        let mut reader = TraceReader::new(src.as_bytes()).unwrap();
+        match reader.next().unwrap() {
+            Err(TraceError::InvalidLine { line: 1, .. }) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn dry_run_matches_generator() {
+        let w = parse_workload_str(
+            "test",
+            "Ordered[300] {
+               <40%> New(uniform(1, 4K));
+               <10%> OverWrite(8K);
+               <20%> Embed(128);
+               <20%> Delete;
+               <10%> DeleteRange(10, 30);
+             }
+             Command \"at @ 1/s\" @ 500/s SideJob/2 { Get; Reopen; Put!(1K); Sync; JournalGc; }",
+        )
+        .unwrap();
+        let expected = generator::dry_run(&w);
+        let actual = dry_run(generator::Generator::new(&w).map(Ok), 512).unwrap();
+        assert_eq!(actual.commands, expected.commands);
+        assert_eq!(actual.peek_bytes, expected.peek_bytes);
+        assert_eq!(actual.peak, expected.peak);
+        assert_eq!(actual.reopens, 1);
+        assert_eq!(expected.reopens, 1);
+        assert_eq!(actual.syncs, 2);
+        assert_eq!(expected.syncs, 2);
+        assert_eq!(actual.maintenance, 2);
+        assert_eq!(expected.maintenance, 2);
+    }
+}
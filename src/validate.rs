@@ -0,0 +1,450 @@
+use crate::{
+    Bytes, Command, Freq, Perc, Repeat, SectionBody, Selector, SizeDist, Statement, StorageOptions,
+    Workload,
+};
+use cannyls::lump::LumpData;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// 検査で見つかった問題.
+///
+/// `section`は0始まりのセクション番号.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub section: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}: section #{}: {}",
+            severity, self.section, self.message
+        )
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// コマンド生成の前にワークロードの意味的な検査を行う.
+///
+/// ここでエラーとなるワークロードは、そのまま実行すると
+/// `generator`や`Storage::put`の内部でパニックする.
+pub fn validate(workload: &Workload) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (i, section) in workload.sections.iter().enumerate() {
+        let mut checker = Checker {
+            section: i,
+            diagnostics: &mut diagnostics,
+        };
+        if section.rate == Some(0) {
+            checker.push(
+                Severity::Error,
+                "target rate must be positive: @ 0/s".to_owned(),
+            );
+        }
+        if section.side_job_every == Some(0) {
+            checker.push(
+                Severity::Error,
+                "side job interval must be positive: SideJob/0".to_owned(),
+            );
+        }
+        match &section.body {
+            SectionBody::Ordered(repeat, v) | SectionBody::Unordered(repeat, v) => {
+                checker.freq_statements(repeat, v)
+            }
+            SectionBody::Commands(commands) => checker.commands(commands),
+        }
+    }
+
+    diagnostics
+}
+
+/// `StorageBuilder`に渡す設定を検査し、問題があればその説明を返す.
+///
+/// ワークロードの`Storage { .. }`とコマンドラインの指定を合わせた後に使う.
+pub fn validate_storage_options(options: &StorageOptions) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Some(ratio) = options.journal_ratio {
+        if !(0.0 < ratio && ratio < 1.0) {
+            errors.push(format!("journal_ratio {} must be in (0, 1)", ratio));
+        }
+    }
+    if options.gc_queue_size == Some(0) {
+        errors.push("gc_queue_size must be positive".to_owned());
+    }
+    if let Some(block_size) = options.block_size {
+        if block_size == 0 || block_size % 512 != 0 || block_size > u16::MAX as Bytes {
+            errors.push(format!(
+                "block_size {} must be a positive multiple of 512 below 64K",
+                block_size
+            ));
+        }
+    }
+    errors
+}
+
+struct Checker<'a> {
+    section: usize,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn push(&mut self, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            section: self.section,
+            message,
+        });
+    }
+
+    fn freq_statements(&mut self, repeat: &Repeat, v: &[(Freq, Statement)]) {
+        let total: usize = v.iter().map(|(freq, _)| *freq as usize).sum();
+        if total > 100 {
+            self.push(
+                Severity::Error,
+                format!("frequencies sum to {}%, which exceeds 100%", total),
+            );
+        }
+
+        let iter = match repeat {
+            Repeat::Times(iter) => *iter,
+            Repeat::For(duration) => {
+                if duration.as_secs() == 0 {
+                    self.push(
+                        Severity::Warning,
+                        "duration is 0s; this section generates nothing".to_owned(),
+                    );
+                }
+                if total == 0 {
+                    self.push(
+                        Severity::Warning,
+                        "frequencies sum to 0%; this section generates nothing".to_owned(),
+                    );
+                }
+                for (_, statement) in v {
+                    self.commands(&statement.0);
+                }
+                return;
+            }
+        };
+        if iter == 0 {
+            self.push(
+                Severity::Warning,
+                "iteration count is 0; this section generates nothing".to_owned(),
+            );
+        }
+
+        for (freq, statement) in v {
+            // generator::section_to_commands と同じ計算
+            let count = (iter * *freq as usize) / 100;
+            if iter != 0 && count == 0 {
+                self.push(
+                    Severity::Warning,
+                    format!(
+                        "<{}%> of {} iterations is truncated to 0; the statement is never executed",
+                        freq, iter
+                    ),
+                );
+            }
+            self.commands(&statement.0);
+        }
+    }
+
+    fn commands(&mut self, commands: &[Command]) {
+        for command in commands {
+            self.command(command);
+        }
+    }
+
+    fn command(&mut self, command: &Command) {
+        match command {
+            Command::NewPut(dist) => self.lump_size("New", dist, LumpData::MAX_SIZE),
+            Command::NewPutSync(dist) => self.lump_size("Put!", dist, LumpData::MAX_SIZE),
+            Command::Overwrite(dist, selector) => {
+                self.lump_size("OverWrite", dist, LumpData::MAX_SIZE);
+                if let Some(selector) = selector {
+                    self.selector("OverWrite", selector);
+                }
+            }
+            Command::Embed(dist) => self.lump_size("Embed", dist, LumpData::MAX_EMBEDDED_SIZE),
+            Command::RandomGet
+            | Command::RandomDelete
+            | Command::Reopen
+            | Command::Sync
+            | Command::JournalGc => {}
+            Command::SideJob(n) => {
+                if *n == 0 {
+                    self.push(Severity::Warning, "SideJob(0) runs no side job".to_owned());
+                }
+            }
+            Command::Get(selector) => self.selector("Get", selector),
+            Command::Delete(selector) => self.selector("Delete", selector),
+            Command::DeleteRange(left, right) => self.range("DeleteRange", *left, *right),
+            Command::Times(count, commands) => {
+                if *count == 0 {
+                    self.push(
+                        Severity::Warning,
+                        "0.times { .. } is never executed".to_owned(),
+                    );
+                }
+                self.commands(commands);
+            }
+        }
+    }
+
+    fn lump_size(&mut self, name: &str, dist: &SizeDist, limit: Bytes) {
+        // 上限が決まる分布はその上限を、そうでないものは平均を検査する
+        let (bytes, tail) = match dist {
+            SizeDist::Fixed(bytes) => (*bytes, None),
+            SizeDist::Uniform(min, max) => {
+                if min > max {
+                    self.push(
+                        Severity::Error,
+                        format!("{}: uniform({}, {}) has min > max", name, min, max),
+                    );
+                }
+                (*max, None)
+            }
+            SizeDist::Normal(mean, stddev) | SizeDist::LogNormal(mean, stddev) => {
+                (*mean, Some(mean.saturating_add(stddev.saturating_mul(4))))
+            }
+            SizeDist::Choice(choices) => {
+                if choices.iter().all(|(_, weight)| *weight == 0) {
+                    self.push(
+                        Severity::Error,
+                        format!("{}: choice{{..}} has no positive weight", name),
+                    );
+                }
+                let max = choices.iter().map(|(bytes, _)| *bytes).max();
+                (max.unwrap_or(0), None)
+            }
+        };
+
+        if bytes > limit {
+            self.push(
+                Severity::Error,
+                format!(
+                    "{}({}) exceeds the maximum {} size ({} bytes)",
+                    name,
+                    bytes,
+                    if name == "Embed" {
+                        "embedded lump"
+                    } else {
+                        "lump"
+                    },
+                    limit
+                ),
+            );
+        } else if tail.is_some_and(|tail| tail > limit) {
+            self.push(
+                Severity::Warning,
+                format!(
+                    "{}: sizes sampled from {:?} may exceed {} bytes and are clamped",
+                    name, dist, limit
+                ),
+            );
+        }
+    }
+
+    fn selector(&mut self, name: &str, selector: &Selector) {
+        match selector {
+            Selector::Range(left, right) => self.range(name, *left, *right),
+            Selector::Zipf(exponent) => {
+                if !(exponent.is_finite() && *exponent > 0.0) {
+                    self.push(
+                        Severity::Error,
+                        format!("{}(zipf {}): exponent must be positive", name, exponent),
+                    );
+                }
+            }
+            Selector::Hotspot(hot, access) => {
+                if !(1 <= *hot && *hot <= 100 && *access <= 100) {
+                    self.push(
+                        Severity::Error,
+                        format!(
+                            "{}(hotspot {}% {}%) must satisfy 1 <= {} <= 100 and {} <= 100",
+                            name, hot, access, hot, access
+                        ),
+                    );
+                }
+            }
+            Selector::Latest => {}
+        }
+    }
+
+    fn range(&mut self, name: &str, left: Perc, right: Perc) {
+        if !(left <= right && right <= 100) {
+            self.push(
+                Severity::Error,
+                format!(
+                    "{}({}, {}) must satisfy {} <= {} <= 100",
+                    name, left, right, left, right
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workload(sections: Vec<SectionBody>) -> Workload {
+        Workload {
+            seed: None,
+            storage: StorageOptions::default(),
+            sections: sections.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn stmt(c: Command) -> Statement {
+        Statement(vec![c])
+    }
+
+    #[test]
+    fn valid_workload_has_no_diagnostics() {
+        let w = workload(vec![SectionBody::Ordered(
+            Repeat::Times(100),
+            vec![
+                (50, stmt(Command::NewPut(SizeDist::Fixed(1024)))),
+                (50, stmt(Command::Get(Selector::Range(10, 20)))),
+            ],
+        )]);
+        assert_eq!(validate(&w), vec![]);
+    }
+
+    #[test]
+    fn frequencies_over_100_is_error() {
+        let w = workload(vec![SectionBody::Unordered(
+            Repeat::Times(100),
+            vec![
+                (60, stmt(Command::NewPut(SizeDist::Fixed(1024)))),
+                (50, stmt(Command::RandomGet)),
+            ],
+        )]);
+        let d = validate(&w);
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].severity, Severity::Error);
+        assert!(has_errors(&d));
+    }
+
+    #[test]
+    fn truncated_frequency_is_warning() {
+        let w = workload(vec![SectionBody::Ordered(
+            Repeat::Times(50),
+            vec![
+                (99, stmt(Command::NewPut(SizeDist::Fixed(1)))),
+                (1, stmt(Command::RandomGet)),
+            ],
+        )]);
+        let d = validate(&w);
+        assert_eq!(d.len(), 1);
+        assert_eq!(d[0].severity, Severity::Warning);
+        assert!(!has_errors(&d));
+    }
+
+    #[test]
+    fn invalid_ranges_and_sizes_are_errors() {
+        let w = workload(vec![
+            SectionBody::Commands(vec![
+                Command::Get(Selector::Range(60, 40)),
+                Command::Times(2, vec![Command::Delete(Selector::Range(0, 101))]),
+                Command::DeleteRange(10, 10),
+            ]),
+            SectionBody::Commands(vec![
+                Command::Embed(SizeDist::Fixed(LumpData::MAX_EMBEDDED_SIZE + 1)),
+                Command::NewPut(SizeDist::Uniform(1, LumpData::MAX_SIZE + 1)),
+                Command::Overwrite(SizeDist::Fixed(LumpData::MAX_SIZE), None),
+            ]),
+        ]);
+        let d = validate(&w);
+        let sections: Vec<_> = d.iter().map(|d| (d.section, d.severity)).collect();
+        assert_eq!(
+            sections,
+            vec![
+                (0, Severity::Error),
+                (0, Severity::Error),
+                (1, Severity::Error),
+                (1, Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_selectors() {
+        let w = workload(vec![SectionBody::Commands(vec![
+            Command::Get(Selector::Zipf(0.0)),
+            Command::Delete(Selector::Hotspot(0, 90)),
+            Command::Overwrite(SizeDist::Fixed(1), Some(Selector::Hotspot(10, 101))),
+            Command::Get(Selector::Zipf(0.99)),
+            Command::Get(Selector::Latest),
+        ])]);
+        assert_eq!(validate(&w).len(), 3);
+    }
+
+    #[test]
+    fn invalid_size_dists() {
+        let w = workload(vec![SectionBody::Commands(vec![
+            Command::NewPut(SizeDist::Uniform(10, 5)),
+            Command::NewPut(SizeDist::Choice(vec![(10, 0)])),
+            Command::Embed(SizeDist::Normal(60_000, 10_000)),
+        ])]);
+        let severities: Vec<_> = validate(&w).iter().map(|d| d.severity).collect();
+        assert_eq!(
+            severities,
+            vec![Severity::Error, Severity::Error, Severity::Warning]
+        );
+    }
+
+    #[test]
+    fn invalid_storage_options() {
+        assert!(validate_storage_options(&StorageOptions::default()).is_empty());
+        let options = StorageOptions {
+            journal_ratio: Some(0.05),
+            sync_interval: Some(0),
+            gc_queue_size: Some(1),
+            block_size: Some(4096),
+        };
+        assert!(validate_storage_options(&options).is_empty());
+
+        let options = StorageOptions {
+            journal_ratio: Some(1.0),
+            sync_interval: None,
+            gc_queue_size: Some(0),
+            block_size: Some(1000),
+        };
+        assert_eq!(validate_storage_options(&options).len(), 3);
+    }
+
+    #[test]
+    fn zero_rate_is_error() {
+        let mut w = workload(vec![SectionBody::Commands(vec![Command::RandomGet])]);
+        w.sections[0].rate = Some(0);
+        let diagnostics = validate(&w);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn zero_side_jobs() {
+        let mut w = workload(vec![SectionBody::Commands(vec![Command::SideJob(0)])]);
+        w.sections[0].side_job_every = Some(0);
+        let severities: Vec<_> = validate(&w).iter().map(|d| d.severity).collect();
+        assert_eq!(severities, vec![Severity::Error, Severity::Warning]);
+    }
+}
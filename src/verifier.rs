@@ -1,7 +1,12 @@
-use crate::RealCommand;
-use cannyls::lump;
+use crate::faulty_nvm::{Fault, FaultInjector, FaultyNvm};
+use crate::run_commands::{make_storage, reopen_storage, storage_builder};
+use crate::{Bytes, RealCommand, StorageOptions};
+use cannyls::lump::{self, LumpId};
 use cannyls::nvm::NonVolatileMemory;
 use cannyls::storage::Storage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Range;
 
 /*
@@ -24,12 +29,31 @@ pub fn lumpid_to_bytes(lumpid: lump::LumpId, size: usize) -> Vec<u8> {
     v
 }
 
-pub fn verify_commands<N>(storage: &mut Storage<N>, commands: &[RealCommand])
-where
+/// `Reopen`では`reopen`で同じNVMをもう一度開き、開き直したストレージで続きを検証する.
+pub fn verify_commands<N, I>(
+    mut storage: Storage<N>,
+    commands: I,
+    mut reopen: Option<&mut dyn FnMut() -> Storage<N>>,
+) where
     N: NonVolatileMemory,
+    I: IntoIterator<Item = RealCommand>,
 {
     for command in commands {
-        verify_command(storage, command)
+        match (&command, reopen.as_mut()) {
+            (RealCommand::Reopen, Some(reopen)) => {
+                let expected = storage.list();
+                let (reopened, _, _, lumps) = reopen_storage(storage, reopen);
+                storage = reopened;
+                if lumps != expected.len() || storage.list() != expected {
+                    panic!(
+                        "Reopen Error: {} lumps before reopening, {} after",
+                        expected.len(),
+                        lumps
+                    );
+                }
+            }
+            _ => verify_command(&mut storage, &command),
+        }
     }
 }
 
@@ -41,16 +65,16 @@ where
         RealCommand::Put(lumpid, bytes) => {
             let v = lumpid_to_bytes(*lumpid, *bytes);
             let lump = storage.allocate_lump_data_with_bytes(&v).unwrap();
-            let _ = storage.put(&lumpid, &lump).unwrap();
+            let _ = storage.put(lumpid, &lump).unwrap();
         }
         RealCommand::Embed(lumpid, bytes) => {
             let v = lumpid_to_bytes(*lumpid, *bytes);
             let lump = lump::LumpData::new_embedded(v).unwrap();
-            let _ = storage.put(&lumpid, &lump).unwrap();
+            let _ = storage.put(lumpid, &lump).unwrap();
         }
         RealCommand::Get(lumpid, bytes) => {
             let v = lumpid_to_bytes(*lumpid, *bytes);
-            let lump = storage.get(&lumpid).unwrap();
+            let lump = storage.get(lumpid).unwrap();
             assert!(lump.is_some());
 
             let lump: lump::LumpData = lump.unwrap();
@@ -62,7 +86,7 @@ where
         RealCommand::Delete(lumpid, bytes) => {
             // 削除前にデータを取得して検証を行う。
             let v = lumpid_to_bytes(*lumpid, *bytes);
-            let lump = storage.get(&lumpid).unwrap();
+            let lump = storage.get(lumpid).unwrap();
             let lump: lump::LumpData = lump.unwrap();
             if lump.as_bytes() != v.as_slice() {
                 panic!(
@@ -71,7 +95,7 @@ where
                 );
             }
 
-            let existed = storage.delete(&lumpid).unwrap();
+            let existed = storage.delete(lumpid).unwrap();
             if !existed {
                 panic!("Delete Error: Lumpid = {} does not exist", lumpid);
             }
@@ -84,7 +108,314 @@ where
                 })
                 .unwrap();
         }
+        RealCommand::Sync => storage.journal_sync().unwrap(),
+        RealCommand::JournalGc => storage.journal_gc().unwrap(),
+        RealCommand::SideJob(n) => {
+            for _ in 0..*n {
+                storage.run_side_job_once().unwrap();
+            }
+        }
+        RealCommand::BeginSection(_) | RealCommand::EndSection(_) => {}
+        RealCommand::Reopen => panic!("Reopen Error: the NVM cannot be reopened here"),
+    }
+}
+
+/// 開いたストレージにあるlumpを読み出して中身を確かめ、そのサイズとともに返す.
+///
+/// 以前に検証モードで書き込んだlumpであることを前提とする.
+pub fn verify_live_lumps<N>(storage: &mut Storage<N>) -> Vec<(LumpId, Bytes)>
+where
+    N: NonVolatileMemory,
+{
+    storage
+        .list()
+        .into_iter()
+        .map(|lumpid| {
+            let lump = storage.get(&lumpid).unwrap().unwrap_or_else(|| {
+                panic!("Get Error[Lumpid = {}]: the lump does not exist", lumpid)
+            });
+            let bytes = lump.as_bytes().len();
+            if lump.as_bytes() != lumpid_to_bytes(lumpid, bytes).as_slice() {
+                panic!("Get Error[Lumpid = {}]: Obtained data is invalid", lumpid);
+            }
+            (lumpid, bytes)
+        })
+        .collect()
+}
+
+/// クラッシュ検証の設定.
+#[derive(Debug, Clone)]
+pub struct CrashOptions {
+    pub fault: Fault,
+    // 平均してこのコマンド数ごとにクラッシュさせる
+    pub every: u64,
+    pub seed: u64,
+}
+
+/// クラッシュ検証の結果.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CrashSummary {
+    pub crashes: usize,
+    // そのうち、コマンドの読み書きの途中で起こしたクラッシュの数
+    pub crashes_in_command: usize,
+    // 復旧後に中身を確かめたlumpの延べ数
+    pub checked_lumps: usize,
+}
+
+// 発行したコマンドから期待されるストレージの中身
+#[derive(Default)]
+struct Expected {
+    // 成功を返したコマンドまでの中身
+    current: BTreeMap<LumpId, Bytes>,
+    // 最後の`sync`の時点での中身
+    durable: BTreeMap<LumpId, Bytes>,
+    // 最後の`sync`以降に変更したlump. クラッシュ後にどちらの中身になっているかは分からない
+    touched: BTreeSet<LumpId>,
+}
+
+impl Expected {
+    fn apply(&mut self, command: &RealCommand) {
+        match command {
+            RealCommand::Put(lumpid, bytes) | RealCommand::Embed(lumpid, bytes) => {
+                self.current.insert(*lumpid, *bytes);
+                self.touched.insert(*lumpid);
+            }
+            RealCommand::Delete(lumpid, _) => {
+                self.current.remove(lumpid);
+                self.touched.insert(*lumpid);
+            }
+            RealCommand::DeleteRange(start, end) => {
+                let ids: Vec<LumpId> = self
+                    .current
+                    .range(*start..*end)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in ids {
+                    self.current.remove(&id);
+                    self.touched.insert(id);
+                }
+            }
+            RealCommand::Get(..)
+            | RealCommand::BeginSection(_)
+            | RealCommand::EndSection(_)
+            | RealCommand::Reopen
+            | RealCommand::Sync
+            | RealCommand::JournalGc
+            | RealCommand::SideJob(_) => {}
+        }
+    }
+
+    // `command`の実行中に`sync`した. `command`自体が反映されたかは分からない
+    fn synced(&mut self, command: &RealCommand) {
+        self.durable = self.current.clone();
+        self.touched.clear();
+        self.apply(command);
+    }
+}
+
+/// `nvm`上に作ったストレージで`commands`を実行しながら、ランダムな時点でクラッシュさせる.
+///
+/// クラッシュはコマンドの数回目の読み書きの直前に起こす.
+/// コマンドの読み書きがそれより少なければ、コマンドを終えた後にクラッシュさせる.
+///
+/// クラッシュのたびに`StorageBuilder::open`でストレージを開き直し、
+/// 最後の`sync`より前に成功したlumpがすべて`lumpid_to_bytes`の内容で読めることを確かめる.
+/// `sync`以降に変更したlumpは、読めた場合にのみ中身を確かめ、その後に期待される中身に揃える.
+/// ただし`Fault::TornWrites`では、`sync`以降に削除や上書きをしたlumpの領域が
+/// 他のlumpの書き込みに再利用され、その書き込みだけが残っているかもしれないので、
+/// そのようなlumpの中身は確かめない.
+pub fn crash_verify_commands<N, I>(
+    nvm: N,
+    storage_options: &StorageOptions,
+    commands: I,
+    options: &CrashOptions,
+) -> CrashSummary
+where
+    N: NonVolatileMemory,
+    I: IntoIterator<Item = RealCommand>,
+{
+    assert!(options.every > 0, "the crash interval must be positive");
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let nvm = FaultyNvm::new(nvm);
+    let injector = nvm.injector();
+    let mut storage = make_storage(nvm, storage_options);
+    let mut expected = Expected::default();
+    let mut syncs = injector.syncs();
+    let mut summary = CrashSummary::default();
+
+    for command in commands {
+        if command.is_marker() {
+            continue;
+        }
+        let crash_now = rng.gen_range(0, options.every) == 0;
+        if crash_now {
+            let n = rng.gen_range(1, 3);
+            match options.fault {
+                Fault::IoError => injector.fail_after(n),
+                fault => injector.crash_after(n, fault, rng.gen()),
+            }
+        }
+
+        // 失敗したコマンドも途中まで反映されているかもしれないので、成功したものとして扱う
+        let succeeded = try_command(&mut storage, &command);
+        // 起きなかった障害を次のコマンドに持ち越さない
+        injector.cancel();
+        let crashed_in_command = !succeeded || injector.is_crashed();
+        if injector.syncs() != syncs {
+            syncs = injector.syncs();
+            expected.synced(&command);
+        } else {
+            expected.apply(&command);
+        }
+
+        let crash = match options.fault {
+            Fault::IoError => !succeeded,
+            _ => crash_now,
+        };
+        if crash {
+            if crashed_in_command {
+                summary.crashes_in_command += 1;
+            }
+            storage = recover(
+                storage,
+                storage_options,
+                &injector,
+                &mut expected,
+                options.fault,
+                &mut rng,
+                &mut summary,
+            );
+            syncs = injector.syncs();
+        }
     }
+    summary
+}
+
+// ストレージへの操作がエラーを返したら`false`. 読めたデータが期待と異なる場合はpanicする
+fn try_command<N>(storage: &mut Storage<N>, command: &RealCommand) -> bool
+where
+    N: NonVolatileMemory,
+{
+    let result = match command {
+        RealCommand::Put(lumpid, bytes) => {
+            let v = lumpid_to_bytes(*lumpid, *bytes);
+            storage
+                .allocate_lump_data_with_bytes(&v)
+                .and_then(|lump| storage.put(lumpid, &lump))
+                .map(|_| ())
+        }
+        RealCommand::Embed(lumpid, bytes) => {
+            let v = lumpid_to_bytes(*lumpid, *bytes);
+            let lump = lump::LumpData::new_embedded(v).unwrap();
+            storage.put(lumpid, &lump).map(|_| ())
+        }
+        RealCommand::Get(lumpid, bytes) => storage.get(lumpid).map(|lump| {
+            let lump = lump.unwrap_or_else(|| {
+                panic!("Get Error[Lumpid = {}]: the lump does not exist", lumpid)
+            });
+            if lump.as_bytes() != lumpid_to_bytes(*lumpid, *bytes).as_slice() {
+                panic!("Get Error[Lumpid = {}]: Obtained data is invalid", lumpid);
+            }
+        }),
+        RealCommand::Delete(lumpid, _) => storage.delete(lumpid).map(|existed| {
+            if !existed {
+                panic!("Delete Error: Lumpid = {} does not exist", lumpid);
+            }
+        }),
+        RealCommand::DeleteRange(start, end) => storage
+            .delete_range(Range {
+                start: *start,
+                end: *end,
+            })
+            .map(|_| ()),
+        RealCommand::Sync => storage.journal_sync(),
+        RealCommand::JournalGc => storage.journal_gc(),
+        RealCommand::SideJob(n) => (0..*n).try_for_each(|_| storage.run_side_job_once()),
+        // クラッシュ検証ではクラッシュのたびに開き直すので、`Reopen`では何もしない
+        RealCommand::BeginSection(_) | RealCommand::EndSection(_) | RealCommand::Reopen => Ok(()),
+    };
+    result.is_ok()
+}
+
+// クラッシュさせてからストレージを開き直し、中身を確かめる
+fn recover<N>(
+    storage: Storage<FaultyNvm<N>>,
+    storage_options: &StorageOptions,
+    injector: &FaultInjector<N>,
+    expected: &mut Expected,
+    fault: Fault,
+    rng: &mut StdRng,
+    summary: &mut CrashSummary,
+) -> Storage<FaultyNvm<N>>
+where
+    N: NonVolatileMemory,
+{
+    if !injector.is_crashed() {
+        injector.crash(fault, rng);
+    }
+    drop(storage);
+    let mut storage = storage_builder(storage_options)
+        .open(injector.restart())
+        .unwrap_or_else(|e| panic!("Recovery Error: {}", e));
+    summary.crashes += 1;
+
+    for lumpid in storage.list() {
+        if !expected.durable.contains_key(&lumpid) && !expected.touched.contains(&lumpid) {
+            panic!(
+                "Recovery Error[Lumpid = {}]: a deleted lump is resurrected",
+                lumpid
+            );
+        }
+    }
+    for (lumpid, bytes) in &expected.durable {
+        if expected.touched.contains(lumpid) {
+            continue;
+        }
+        let lump = storage.get(lumpid).unwrap().unwrap_or_else(|| {
+            panic!("Recovery Error[Lumpid = {}]: a synced lump is lost", lumpid)
+        });
+        if lump.as_bytes() != lumpid_to_bytes(*lumpid, *bytes).as_slice() {
+            panic!(
+                "Recovery Error[Lumpid = {}]: Obtained data is invalid",
+                lumpid
+            );
+        }
+        summary.checked_lumps += 1;
+    }
+
+    // `sync`以降に変更したlumpは、古い中身か新しい中身のどちらかでなければならない
+    for lumpid in &expected.touched {
+        if let Some(lump) = storage.get(lumpid).unwrap() {
+            let bytes = lump.as_bytes();
+            let reused = fault == Fault::TornWrites && expected.durable.contains_key(lumpid);
+            if bytes == lumpid_to_bytes(*lumpid, bytes.len()).as_slice() {
+                summary.checked_lumps += 1;
+            } else if !reused {
+                panic!(
+                    "Recovery Error[Lumpid = {}]: Obtained data is invalid (updated after the last sync)",
+                    lumpid
+                );
+            }
+        }
+        // 中身は`lumpid_to_bytes`で決まるので、埋め込みだったlumpも通常のlumpとして書き直す
+        let command = match expected.current.get(lumpid) {
+            Some(bytes) => RealCommand::Put(*lumpid, *bytes),
+            None => {
+                let _ = storage.delete(lumpid).unwrap();
+                continue;
+            }
+        };
+        assert!(
+            try_command(&mut storage, &command),
+            "Recovery Error: {:?}",
+            command
+        );
+    }
+    storage.journal_sync().unwrap();
+    expected.durable = expected.current.clone();
+    expected.touched.clear();
+    storage
 }
 
 #[cfg(test)]
@@ -114,4 +445,39 @@ mod tests {
         let lumpid1 = lump::LumpId::new(1);
         assert_ne!(lumpid_to_bytes(lumpid0, 512), lumpid_to_bytes(lumpid1, 512));
     }
+
+    #[test]
+    fn crash_verify_recovers_synced_lumps() {
+        use crate::generator::Generator;
+        use crate::parse::parse_workload_str;
+        use cannyls::nvm::MemoryNvm;
+
+        let run = |workload: &str, fault: Fault| {
+            let w = parse_workload_str("test", workload).unwrap();
+            let options = CrashOptions {
+                fault,
+                every: 200,
+                seed: 0,
+            };
+            let nvm = MemoryNvm::new(vec![0; 16 * 1024 * 1024]);
+            let summary = crash_verify_commands(
+                nvm,
+                &StorageOptions::default(),
+                Generator::new(&w),
+                &options,
+            );
+            assert!(summary.crashes > 0, "{:?}", fault);
+            assert!(summary.crashes_in_command > 0, "{:?}", fault);
+            assert!(summary.checked_lumps > 0, "{:?}", fault);
+        };
+
+        // cannyls 0.9.2の`journal_gc`は、書き直したレコードを書き出す前に
+        // 新しい先頭位置をヘッダに書いて同期するので、直後のクラッシュで同期済みのlumpを失う.
+        // そのため`JournalGc`は含めない
+        let mixed = r#"Command { 100.times { New(1K); }; }
+            Unordered[1500] SideJob/50 { <30%> New(uniform(1, 8K)); <30%> Get; <20%> OverWrite(2K); <14%> Delete; <5%> Put!(1K); <1%> DeleteRange(90, 100); }"#;
+        run(mixed, Fault::DropUnsynced);
+        run(mixed, Fault::IoError);
+        run(mixed, Fault::TornWrites);
+    }
 }